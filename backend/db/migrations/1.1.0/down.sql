@@ -0,0 +1,5 @@
+DROP TABLE cheat_flags;
+
+DROP TABLE positions;
+
+DROP TABLE anticheat_settings;
\ No newline at end of file
@@ -0,0 +1,32 @@
+CREATE TABLE anticheat_settings (
+    id INTEGER PRIMARY KEY NOT NULL,
+    reject BOOLEAN NOT NULL DEFAULT 0,
+    max_speed DOUBLE NOT NULL DEFAULT 30.0,
+    repeat_threshold INTEGER NOT NULL DEFAULT 3,
+    history_size INTEGER NOT NULL DEFAULT 10
+);
+
+INSERT INTO
+    anticheat_settings (id)
+VALUES
+    (1);
+
+CREATE TABLE positions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    latitude DOUBLE NOT NULL,
+    longitude DOUBLE NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX positions_user_id ON positions (user_id, recorded_at);
+
+CREATE TABLE cheat_flags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    details VARCHAR NOT NULL,
+    rejected BOOLEAN NOT NULL,
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+ALTER TABLE
+    steps
+ADD
+    COLUMN show_bearing BOOLEAN NOT NULL DEFAULT 0;
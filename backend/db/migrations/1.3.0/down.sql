@@ -0,0 +1,5 @@
+ALTER TABLE
+    steps DROP COLUMN secret_code;
+
+ALTER TABLE
+    steps DROP COLUMN validation_mode;
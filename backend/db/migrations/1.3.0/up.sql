@@ -0,0 +1,9 @@
+ALTER TABLE
+    steps
+ADD
+    COLUMN validation_mode VARCHAR NOT NULL DEFAULT 'LocationAndAnswer';
+
+ALTER TABLE
+    steps
+ADD
+    COLUMN secret_code VARCHAR;
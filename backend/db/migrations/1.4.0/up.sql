@@ -0,0 +1,10 @@
+CREATE TABLE photo_submissions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'Pending',
+    submitted_at BIGINT NOT NULL,
+    reviewed_at BIGINT
+);
+
+CREATE INDEX photo_submissions_status ON photo_submissions (status, submitted_at);
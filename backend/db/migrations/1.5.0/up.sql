@@ -0,0 +1,7 @@
+CREATE TABLE medias (
+    step_id INTEGER PRIMARY KEY NOT NULL,
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL
+);
@@ -0,0 +1,14 @@
+CREATE TABLE step_assets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    step_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    caption VARCHAR NOT NULL DEFAULT '',
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    checksum VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL
+);
+
+CREATE INDEX step_assets_step_id_position ON step_assets (step_id, position);
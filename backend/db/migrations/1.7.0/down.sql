@@ -0,0 +1 @@
+DROP INDEX steps_rank;
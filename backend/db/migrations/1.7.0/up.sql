@@ -0,0 +1,10 @@
+-- Renumber the steps from 1 without gaps, as concurrent edits may have left duplicate ranks
+CREATE TEMPORARY TABLE step_ranks AS
+SELECT id, ROW_NUMBER() OVER (ORDER BY rank, id) AS new_rank FROM steps;
+
+UPDATE steps SET rank = (SELECT new_rank FROM step_ranks WHERE step_ranks.id = steps.id);
+
+DROP TABLE step_ranks;
+
+-- An instance runs a single game, whose steps each have their own rank
+CREATE UNIQUE INDEX steps_rank ON steps (rank);
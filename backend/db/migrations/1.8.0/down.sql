@@ -0,0 +1,6 @@
+ALTER TABLE
+    users DROP COLUMN version_id;
+
+DROP TABLE published_steps;
+
+DROP TABLE versions;
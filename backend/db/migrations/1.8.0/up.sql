@@ -0,0 +1,31 @@
+CREATE TABLE versions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    published_at BIGINT NOT NULL
+);
+
+-- Copies of the draft steps as they were when their version was published, they never change.
+-- step_id is the draft step they were published from, whose image, media and assets they use.
+CREATE TABLE published_steps (
+    version_id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    latitude DOUBLE NOT NULL,
+    longitude DOUBLE NOT NULL,
+    location_hint VARCHAR NOT NULL,
+    question VARCHAR NOT NULL,
+    shake_message VARCHAR,
+    answer VARCHAR NOT NULL,
+    is_end BOOLEAN NOT NULL,
+    show_bearing BOOLEAN NOT NULL,
+    validation_mode VARCHAR NOT NULL,
+    secret_code VARCHAR,
+    PRIMARY KEY (version_id, rank)
+);
+
+CREATE UNIQUE INDEX published_steps_version_id_step_id ON published_steps (version_id, step_id);
+
+-- The version a player plays, players without one follow the draft steps
+ALTER TABLE
+    users
+ADD
+    COLUMN version_id INTEGER;
@@ -0,0 +1,35 @@
+-- The step a player is solving, by id. current_step stays the position of that step in the route
+-- of the player, it follows the step when the draft is edited.
+ALTER TABLE
+    users
+ADD
+    COLUMN step_id INTEGER;
+
+UPDATE
+    users
+SET
+    step_id = (
+        SELECT
+            published_steps.step_id
+        FROM
+            published_steps
+        WHERE
+            published_steps.version_id = users.version_id
+            AND published_steps.rank = users.current_step
+    )
+WHERE
+    version_id IS NOT NULL;
+
+UPDATE
+    users
+SET
+    step_id = (
+        SELECT
+            steps.id
+        FROM
+            steps
+        WHERE
+            steps.rank = users.current_step
+    )
+WHERE
+    version_id IS NULL;
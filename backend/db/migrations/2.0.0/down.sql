@@ -0,0 +1,89 @@
+-- Rebuild the tables without their constraints, children first
+CREATE TABLE photo_submissions_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'Pending',
+    submitted_at BIGINT NOT NULL,
+    reviewed_at BIGINT
+);
+
+INSERT INTO photo_submissions_old SELECT * FROM photo_submissions;
+
+DROP TABLE photo_submissions;
+
+ALTER TABLE photo_submissions_old RENAME TO photo_submissions;
+
+CREATE INDEX photo_submissions_status ON photo_submissions (status, submitted_at);
+
+CREATE TABLE cheat_flags_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    details VARCHAR NOT NULL,
+    rejected BOOLEAN NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+INSERT INTO cheat_flags_old SELECT * FROM cheat_flags;
+
+DROP TABLE cheat_flags;
+
+ALTER TABLE cheat_flags_old RENAME TO cheat_flags;
+
+CREATE TABLE positions_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    latitude DOUBLE NOT NULL,
+    longitude DOUBLE NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+
+INSERT INTO positions_old SELECT * FROM positions;
+
+DROP TABLE positions;
+
+ALTER TABLE positions_old RENAME TO positions;
+
+CREATE INDEX positions_user_id ON positions (user_id, recorded_at);
+
+CREATE TABLE users_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    current_step INTEGER NOT NULL DEFAULT 1,
+    version_id INTEGER,
+    step_id INTEGER
+);
+
+INSERT INTO users_old SELECT * FROM users;
+
+DROP TABLE users;
+
+ALTER TABLE users_old RENAME TO users;
+
+CREATE TABLE published_steps_old (
+    version_id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    latitude DOUBLE NOT NULL,
+    longitude DOUBLE NOT NULL,
+    location_hint VARCHAR NOT NULL,
+    question VARCHAR NOT NULL,
+    shake_message VARCHAR,
+    answer VARCHAR NOT NULL,
+    is_end BOOLEAN NOT NULL,
+    show_bearing BOOLEAN NOT NULL,
+    validation_mode VARCHAR NOT NULL,
+    secret_code VARCHAR,
+    PRIMARY KEY (version_id, rank)
+);
+
+INSERT INTO published_steps_old SELECT * FROM published_steps;
+
+DROP TABLE published_steps;
+
+ALTER TABLE published_steps_old RENAME TO published_steps;
+
+CREATE UNIQUE INDEX published_steps_version_id_step_id ON published_steps (version_id, step_id);
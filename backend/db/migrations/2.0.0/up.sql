@@ -0,0 +1,118 @@
+-- Remove the rows left behind by deleted users, steps and versions
+DELETE FROM positions WHERE user_id NOT IN (SELECT id FROM users);
+
+DELETE FROM cheat_flags WHERE user_id NOT IN (SELECT id FROM users);
+
+DELETE FROM photo_submissions WHERE user_id NOT IN (SELECT id FROM users);
+
+DELETE FROM published_steps WHERE version_id NOT IN (SELECT id FROM versions);
+
+UPDATE users SET version_id = NULL, step_id = NULL WHERE version_id NOT IN (SELECT id FROM versions);
+
+DELETE FROM medias
+WHERE step_id NOT IN (SELECT id FROM steps)
+    AND step_id NOT IN (SELECT step_id FROM published_steps);
+
+DELETE FROM step_assets
+WHERE step_id NOT IN (SELECT id FROM steps)
+    AND step_id NOT IN (SELECT step_id FROM published_steps);
+
+-- SQLite cannot add constraints to a table, the tables are rebuilt with them. The parents are
+-- rebuilt before their children, so that no constraint refers to a dropped table.
+-- The step ids are not constrained: medias, assets, photos, flags and players may refer to a step
+-- that is no longer in the draft but still is in a published version.
+CREATE TABLE published_steps_new (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    latitude DOUBLE NOT NULL,
+    longitude DOUBLE NOT NULL,
+    location_hint VARCHAR NOT NULL,
+    question VARCHAR NOT NULL,
+    shake_message VARCHAR,
+    answer VARCHAR NOT NULL,
+    is_end BOOLEAN NOT NULL,
+    show_bearing BOOLEAN NOT NULL,
+    validation_mode VARCHAR NOT NULL,
+    secret_code VARCHAR,
+    PRIMARY KEY (version_id, rank)
+);
+
+INSERT INTO published_steps_new SELECT * FROM published_steps;
+
+DROP TABLE published_steps;
+
+ALTER TABLE published_steps_new RENAME TO published_steps;
+
+CREATE UNIQUE INDEX published_steps_version_id_step_id ON published_steps (version_id, step_id);
+
+-- A version cannot be deleted while players play it
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    current_step INTEGER NOT NULL DEFAULT 1,
+    version_id INTEGER REFERENCES versions (id) ON DELETE RESTRICT,
+    step_id INTEGER
+);
+
+INSERT INTO users_new (id, name, password, current_step, version_id, step_id)
+SELECT id, name, password, current_step, version_id, step_id FROM users;
+
+DROP TABLE users;
+
+ALTER TABLE users_new RENAME TO users;
+
+-- What a player did goes away with them
+CREATE TABLE positions_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    latitude DOUBLE NOT NULL,
+    longitude DOUBLE NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+
+INSERT INTO positions_new SELECT * FROM positions;
+
+DROP TABLE positions;
+
+ALTER TABLE positions_new RENAME TO positions;
+
+CREATE INDEX positions_user_id ON positions (user_id, recorded_at);
+
+CREATE TABLE cheat_flags_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    details VARCHAR NOT NULL,
+    rejected BOOLEAN NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+INSERT INTO cheat_flags_new SELECT * FROM cheat_flags;
+
+DROP TABLE cheat_flags;
+
+ALTER TABLE cheat_flags_new RENAME TO cheat_flags;
+
+CREATE TABLE photo_submissions_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'Pending',
+    submitted_at BIGINT NOT NULL,
+    reviewed_at BIGINT
+);
+
+INSERT INTO photo_submissions_new SELECT * FROM photo_submissions;
+
+DROP TABLE photo_submissions;
+
+ALTER TABLE photo_submissions_new RENAME TO photo_submissions;
+
+CREATE INDEX photo_submissions_status ON photo_submissions (status, submitted_at);
+
+CREATE INDEX photo_submissions_user_id ON photo_submissions (user_id);
+
+CREATE INDEX cheat_flags_user_id ON cheat_flags (user_id);
@@ -0,0 +1,39 @@
+-- The files of the published steps, copied when their version is published so that changing the
+-- draft does not change what its players see. The copies are named after their content, so that
+-- the versions sharing a file share its copy. A file is found by the key of the draft file it was
+-- copied from.
+CREATE TABLE published_files (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    name VARCHAR NOT NULL,
+    file_key VARCHAR NOT NULL,
+    mime VARCHAR NOT NULL,
+    PRIMARY KEY (version_id, name)
+);
+
+CREATE INDEX published_files_file_key ON published_files (file_key);
+
+-- The assets of the published steps, whose files are already named after their content
+CREATE TABLE published_assets (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    id INTEGER NOT NULL,
+    step_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    caption VARCHAR NOT NULL,
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    checksum VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL,
+    PRIMARY KEY (version_id, id)
+);
+
+CREATE INDEX published_assets_checksum ON published_assets (checksum);
+
+-- The versions published before their files were copied use the files of the draft, they are
+-- copied when the server starts
+ALTER TABLE
+    versions
+ADD
+    COLUMN files_copied BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,81 @@
+-- The published versions have their own copies of the steps, of their files and of their assets:
+-- the records of the steps deleted from the draft are removed, and their players are found in
+-- their version by their position. The versions whose files are not copied yet lose the media and
+-- the assets of these steps.
+DELETE FROM medias WHERE step_id NOT IN (SELECT id FROM steps);
+
+DELETE FROM step_assets WHERE step_id NOT IN (SELECT id FROM steps);
+
+UPDATE users SET step_id = NULL WHERE step_id NOT IN (SELECT id FROM steps);
+
+-- SQLite cannot add constraints to a table, the tables are rebuilt with them
+CREATE TABLE medias_new (
+    step_id INTEGER PRIMARY KEY NOT NULL REFERENCES steps (id) ON DELETE CASCADE,
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL
+);
+
+INSERT INTO medias_new SELECT * FROM medias;
+
+DROP TABLE medias;
+
+ALTER TABLE medias_new RENAME TO medias;
+
+CREATE TABLE step_assets_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    step_id INTEGER NOT NULL REFERENCES steps (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    caption VARCHAR NOT NULL DEFAULT '',
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    checksum VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL
+);
+
+INSERT INTO step_assets_new SELECT * FROM step_assets;
+
+DROP TABLE step_assets;
+
+ALTER TABLE step_assets_new RENAME TO step_assets;
+
+CREATE INDEX step_assets_step_id_position ON step_assets (step_id, position);
+
+-- Dropping the players deletes what is recorded about them, which is put back once they are
+-- rebuilt
+CREATE TEMP TABLE saved_positions AS SELECT * FROM positions;
+
+CREATE TEMP TABLE saved_cheat_flags AS SELECT * FROM cheat_flags;
+
+CREATE TEMP TABLE saved_photo_submissions AS SELECT * FROM photo_submissions;
+
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    current_step INTEGER NOT NULL DEFAULT 1,
+    version_id INTEGER REFERENCES versions (id) ON DELETE RESTRICT,
+    step_id INTEGER REFERENCES steps (id) ON DELETE SET NULL,
+    revision INTEGER NOT NULL DEFAULT 1
+);
+
+INSERT INTO users_new SELECT * FROM users;
+
+DROP TABLE users;
+
+ALTER TABLE users_new RENAME TO users;
+
+INSERT INTO positions SELECT * FROM saved_positions;
+
+INSERT INTO cheat_flags SELECT * FROM saved_cheat_flags;
+
+INSERT INTO photo_submissions SELECT * FROM saved_photo_submissions;
+
+DROP TABLE saved_positions;
+
+DROP TABLE saved_cheat_flags;
+
+DROP TABLE saved_photo_submissions;
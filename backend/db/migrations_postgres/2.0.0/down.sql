@@ -0,0 +1,19 @@
+DROP TABLE step_assets;
+
+DROP TABLE medias;
+
+DROP TABLE photo_submissions;
+
+DROP TABLE cheat_flags;
+
+DROP TABLE positions;
+
+DROP TABLE anticheat_settings;
+
+DROP TABLE users;
+
+DROP TABLE published_steps;
+
+DROP TABLE versions;
+
+DROP TABLE steps;
@@ -0,0 +1,150 @@
+-- The schema of the SQLite database as of its 2.0.0 migration, later migrations are written for
+-- both backends with the same versions
+CREATE TABLE steps (
+    id SERIAL PRIMARY KEY,
+    rank INTEGER NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    location_hint VARCHAR NOT NULL,
+    question VARCHAR NOT NULL,
+    shake_message VARCHAR,
+    answer VARCHAR NOT NULL,
+    is_end BOOLEAN NOT NULL,
+    show_bearing BOOLEAN NOT NULL DEFAULT FALSE,
+    validation_mode VARCHAR NOT NULL DEFAULT 'LocationAndAnswer',
+    secret_code VARCHAR
+);
+
+-- An instance runs a single game, whose steps each have their own rank
+CREATE UNIQUE INDEX steps_rank ON steps (rank);
+
+INSERT INTO
+    steps (
+        rank,
+        latitude,
+        longitude,
+        location_hint,
+        question,
+        answer,
+        is_end
+    )
+VALUES
+    (
+        1,
+        45.74846,
+        4.84671,
+        'Go there',
+        'What is the color of the sky?',
+        'Blue',
+        false
+    );
+
+CREATE TABLE versions (
+    id SERIAL PRIMARY KEY,
+    published_at BIGINT NOT NULL
+);
+
+-- Copies of the draft steps as they were when their version was published, they never change.
+-- step_id is the draft step they were published from, whose image, media and assets they use.
+CREATE TABLE published_steps (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    location_hint VARCHAR NOT NULL,
+    question VARCHAR NOT NULL,
+    shake_message VARCHAR,
+    answer VARCHAR NOT NULL,
+    is_end BOOLEAN NOT NULL,
+    show_bearing BOOLEAN NOT NULL,
+    validation_mode VARCHAR NOT NULL,
+    secret_code VARCHAR,
+    PRIMARY KEY (version_id, rank)
+);
+
+CREATE UNIQUE INDEX published_steps_version_id_step_id ON published_steps (version_id, step_id);
+
+-- The version a player plays, players without one follow the draft steps. The step a player is
+-- solving is not constrained, as it may no longer be in the draft but still be in their version.
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    current_step INTEGER NOT NULL DEFAULT 1,
+    version_id INTEGER REFERENCES versions (id) ON DELETE RESTRICT,
+    step_id INTEGER
+);
+
+CREATE TABLE anticheat_settings (
+    id INTEGER PRIMARY KEY,
+    reject BOOLEAN NOT NULL DEFAULT FALSE,
+    max_speed DOUBLE PRECISION NOT NULL DEFAULT 30.0,
+    repeat_threshold INTEGER NOT NULL DEFAULT 3,
+    history_size INTEGER NOT NULL DEFAULT 10
+);
+
+INSERT INTO
+    anticheat_settings (id)
+VALUES
+    (1);
+
+CREATE TABLE positions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX positions_user_id ON positions (user_id, recorded_at);
+
+CREATE TABLE cheat_flags (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    details VARCHAR NOT NULL,
+    rejected BOOLEAN NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX cheat_flags_user_id ON cheat_flags (user_id);
+
+CREATE TABLE photo_submissions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    step_id INTEGER NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'Pending',
+    submitted_at BIGINT NOT NULL,
+    reviewed_at BIGINT
+);
+
+CREATE INDEX photo_submissions_status ON photo_submissions (status, submitted_at);
+
+CREATE INDEX photo_submissions_user_id ON photo_submissions (user_id);
+
+-- Medias, assets, photos and flags may refer to a step that is no longer in the draft but still
+-- is in a published version, their step ids are not constrained either
+CREATE TABLE medias (
+    step_id INTEGER PRIMARY KEY,
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL
+);
+
+CREATE TABLE step_assets (
+    id SERIAL PRIMARY KEY,
+    step_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    caption VARCHAR NOT NULL DEFAULT '',
+    mime VARCHAR NOT NULL,
+    extension VARCHAR NOT NULL,
+    checksum VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    uploaded_at BIGINT NOT NULL
+);
+
+CREATE INDEX step_assets_step_id_position ON step_assets (step_id, position);
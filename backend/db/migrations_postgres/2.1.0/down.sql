@@ -0,0 +1,5 @@
+ALTER TABLE
+    users DROP COLUMN revision;
+
+ALTER TABLE
+    steps DROP COLUMN revision;
@@ -0,0 +1,11 @@
+-- Revision of the steps and the players, sent as their ETag and raised by every change, so that
+-- an update made from an outdated copy is refused
+ALTER TABLE
+    steps
+ADD
+    COLUMN revision INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE
+    users
+ADD
+    COLUMN revision INTEGER NOT NULL DEFAULT 1;
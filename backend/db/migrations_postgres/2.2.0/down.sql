@@ -0,0 +1,6 @@
+ALTER TABLE
+    versions DROP COLUMN files_copied;
+
+DROP TABLE published_assets;
+
+DROP TABLE published_files;
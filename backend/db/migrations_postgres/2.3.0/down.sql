@@ -0,0 +1,8 @@
+ALTER TABLE
+    users DROP CONSTRAINT users_step_id_fkey;
+
+ALTER TABLE
+    step_assets DROP CONSTRAINT step_assets_step_id_fkey;
+
+ALTER TABLE
+    medias DROP CONSTRAINT medias_step_id_fkey;
@@ -0,0 +1,24 @@
+-- The published versions have their own copies of the steps, of their files and of their assets:
+-- the records of the steps deleted from the draft are removed, and their players are found in
+-- their version by their position. The versions whose files are not copied yet lose the media and
+-- the assets of these steps.
+DELETE FROM medias WHERE step_id NOT IN (SELECT id FROM steps);
+
+DELETE FROM step_assets WHERE step_id NOT IN (SELECT id FROM steps);
+
+UPDATE users SET step_id = NULL WHERE step_id NOT IN (SELECT id FROM steps);
+
+ALTER TABLE
+    medias
+ADD
+    CONSTRAINT medias_step_id_fkey FOREIGN KEY (step_id) REFERENCES steps (id) ON DELETE CASCADE;
+
+ALTER TABLE
+    step_assets
+ADD
+    CONSTRAINT step_assets_step_id_fkey FOREIGN KEY (step_id) REFERENCES steps (id) ON DELETE CASCADE;
+
+ALTER TABLE
+    users
+ADD
+    CONSTRAINT users_step_id_fkey FOREIGN KEY (step_id) REFERENCES steps (id) ON DELETE SET NULL;
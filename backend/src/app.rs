@@ -1,19 +1,28 @@
 #[macro_export]
 macro_rules! create_app {
     ($pool:expr, $app_data:expr) => {{
-        use crate::models::{step, user};
+        use crate::errors::ServerError;
+        use crate::models::{
+            anticheat, asset, backup, maintenance, photo, qrcodes, step, user, version,
+        };
+        use crate::openapi;
         use actix_cors::Cors;
-        use actix_web::{error, middleware, web, web::Data, App, HttpResponse};
+        use actix_web::{middleware, web, web::Data, App};
 
         App::new()
             .app_data(Data::new($pool.clone()))
             .app_data(
                 web::JsonConfig::default()
                     .limit(4096)
-                    .error_handler(|err, _req| {
-                        error::InternalError::from_response(err, HttpResponse::Conflict().finish())
-                            .into()
-                    }),
+                    .error_handler(|err, _req| ServerError::from(err).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _req| ServerError::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|err, _req| ServerError::NotFound(err.to_string()).into()),
             )
             .app_data(Data::clone($app_data))
             .wrap(Cors::permissive())
@@ -22,10 +31,13 @@ macro_rules! create_app {
                 web::scope("/api/users")
                     .service(user::advance)
                     .service(user::current_step)
+                    .service(user::ping)
+                    .service(photo::submit)
                     .service(user::read)
                     .service(user::create)
                     .service(user::read_all)
                     .service(user::update)
+                    .service(user::patch)
                     .service(user::delete_all)
                     .service(user::delete),
             )
@@ -37,14 +49,64 @@ macro_rules! create_app {
                     .service(step::check_media)
                     .service(step::read_all)
                     .service(step::create)
+                    .service(step::reorder)
                     .service(step::update)
+                    .service(step::patch)
                     .service(step::delete_all)
                     .service(step::delete)
                     .service(step::upload_image)
                     .service(step::delete_image)
                     .service(step::upload_media)
-                    .service(step::delete_media),
+                    .service(step::delete_media)
+                    .service(asset::read_all)
+                    .service(asset::create)
+                    .service(asset::read)
+                    .service(asset::retrieve_file)
+                    .service(asset::update)
+                    .service(asset::delete),
+            )
+            .service(
+                web::scope("/api/maintenance")
+                    .service(maintenance::read_orphans)
+                    .service(maintenance::delete_orphans),
+            )
+            .service(
+                web::scope("/api/backups")
+                    .service(backup::create)
+                    .service(backup::read_all)
+                    .service(backup::restore)
+                    .service(backup::retrieve),
+            )
+            .service(
+                web::scope("/api/anticheat")
+                    .service(anticheat::read_settings)
+                    .service(anticheat::update_settings)
+                    .service(anticheat::report)
+                    .service(anticheat::clear_report),
+            )
+            .service(
+                web::scope("/api/photos")
+                    .service(photo::read_all)
+                    .service(photo::retrieve_photo)
+                    .service(photo::approve)
+                    .service(photo::reject),
+            )
+            .service(
+                web::scope("/api/qrcodes")
+                    .service(qrcodes::step_code)
+                    .service(qrcodes::user_code)
+                    .service(qrcodes::sheet),
+            )
+            .service(
+                web::scope("/api/versions")
+                    .service(version::publish)
+                    .service(version::read_all)
+                    .service(version::read_steps)
+                    .service(version::migrate)
+                    .service(version::delete),
             )
+            .service(openapi::spec)
+            .service(openapi::docs)
             .service(actix_files::Files::new("/", "./web").index_file("index.html"))
     }};
 }
@@ -1,12 +1,30 @@
 use actix_web::{http::header::HeaderValue, FromRequest, HttpRequest};
 
-use std::future::{ready, Ready};
+use std::{
+    future::{ready, Ready},
+    sync::Arc,
+};
 
-use crate::errors::ServerError;
+use crate::{
+    errors::ServerError,
+    storage::{FileStorage, Storage},
+};
+
+// Default maximum size of an uploaded media: 50 MiB
+const MEDIA_MAX_SIZE: usize = 50 * 1024 * 1024;
+const BACKUP_MAX_SIZE: usize = 1024 * 1024 * 1024;
 
 pub struct AppConfig {
     pub bearer_token: String,
     pub location_check: bool,
+    pub media_max_size: usize,
+    pub low_bitrate_renditions: bool,
+    pub storage: Arc<dyn Storage>,
+    // Where the backups are saved, and how many of them are kept (0 keeps them all)
+    pub backups_path: String,
+    pub backup_retention: usize,
+    // Largest backup archive that may be uploaded to be restored
+    pub backup_max_size: usize,
 }
 
 impl AppConfig {
@@ -14,6 +32,12 @@ impl AppConfig {
         AppConfig {
             bearer_token: token,
             location_check: location_check,
+            media_max_size: MEDIA_MAX_SIZE,
+            low_bitrate_renditions: false,
+            storage: Arc::new(FileStorage::new("data/items")),
+            backups_path: "data/backups".to_string(),
+            backup_retention: 7,
+            backup_max_size: BACKUP_MAX_SIZE,
         }
     }
 }
@@ -158,7 +182,10 @@ mod extractor_tests {
         let res = test::call_service(&app, req).await;
         assert_eq!(res.status(), http::StatusCode::UNAUTHORIZED);
         let body = test::read_body(res).await;
-        assert_eq!(body, Bytes::from_static(b"no authorization header"));
+        assert_eq!(
+            body,
+            Bytes::from_static(br#"{"code":"unauthorized","message":"no authorization header"}"#)
+        );
     }
 
     #[actix_web::test]
@@ -179,7 +206,9 @@ mod extractor_tests {
         let body = test::read_body(res).await;
         assert_eq!(
             body,
-            Bytes::from_static(b"authorization header is too short")
+            Bytes::from_static(
+                br#"{"code":"unauthorized","message":"authorization header is too short"}"#
+            )
         );
     }
 
@@ -201,7 +230,9 @@ mod extractor_tests {
         let body = test::read_body(res).await;
         assert_eq!(
             body,
-            Bytes::from_static(b"authorization header is too short")
+            Bytes::from_static(
+                br#"{"code":"unauthorized","message":"authorization header is too short"}"#
+            )
         );
     }
 
@@ -221,7 +252,10 @@ mod extractor_tests {
         let res = test::call_service(&app, req).await;
         assert_eq!(res.status(), http::StatusCode::FORBIDDEN);
         let body = test::read_body(res).await;
-        assert_eq!(body, Bytes::from_static(b"wrong token"));
+        assert_eq!(
+            body,
+            Bytes::from_static(br#"{"code":"forbidden","message":"wrong token"}"#)
+        );
     }
 
     #[actix_web::test]
@@ -0,0 +1,120 @@
+use actix_web::web;
+use diesel::connection::{AnsiTransactionManager, TransactionManager};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+
+use crate::errors::ServerError;
+
+// The database is SQLite by default, or Postgres when given a postgres:// URL
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Pg(PgConnection),
+    Sqlite(SqliteConnection),
+}
+
+pub type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+pub type DbBackend = <DbConnection as Connection>::Backend;
+
+pub const DEFAULT_DATABASE_URL: &str = "db/db.sqlite";
+
+const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations");
+const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations_postgres");
+
+// Run some work on the database from a handler. Waiting for a connection and running the queries
+// both happen on the blocking thread pool, so that the async executor keeps serving requests when
+// the database is busy.
+pub async fn run<T, E, F>(pool: &DbPool, f: F) -> Result<T, ServerError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Into<ServerError>,
+{
+    let pool = pool.clone();
+    web::block(move || -> Result<T, ServerError> { f(&mut *pool.get()?).map_err(Into::into) })
+        .await?
+}
+
+// Tell if a database URL is for Postgres, any other one being the path of a SQLite database
+pub fn is_postgres(database_url: &str) -> bool {
+    database_url.starts_with("postgres://") || database_url.starts_with("postgresql://")
+}
+
+// Apply the migrations of the backend of the connection
+pub fn run_migrations(conn: &mut DbConnection) -> Result<(), String> {
+    let applied = match conn {
+        DbConnection::Pg(conn) => conn
+            .run_pending_migrations(POSTGRES_MIGRATIONS)
+            .map(|v| v.len()),
+        DbConnection::Sqlite(conn) => conn
+            .run_pending_migrations(SQLITE_MIGRATIONS)
+            .map(|v| v.len()),
+    };
+    applied.map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    integrity_check: String,
+}
+
+// Check that the database can be used, telling why it cannot: SQLite reports when the file is
+// locked by another process or corrupt, Postgres refused the connection already if anything is
+// wrong.
+pub fn check(conn: &mut DbConnection) -> Result<(), String> {
+    let DbConnection::Sqlite(conn) = conn else {
+        return Ok(());
+    };
+    let problems = diesel::sql_query("PRAGMA integrity_check")
+        .load::<IntegrityCheck>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(_, info) if info.message().contains("locked") => {
+                "the database is locked by another process".to_string()
+            }
+            e => format!("the database cannot be read: {}", e),
+        })?
+        .into_iter()
+        .map(|r| r.integrity_check)
+        .filter(|r| r != "ok")
+        .collect::<Vec<_>>();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("the database is corrupt: {}", problems.join(", ")))
+    }
+}
+
+impl DbConnection {
+    // Run a transaction that holds the write lock from its start, so that concurrent writers wait
+    // for each other instead of failing or reading stale data: SQLite begins it as IMMEDIATE, and
+    // Postgres takes a lock shared by all these transactions.
+    pub fn immediate_transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        match self {
+            DbConnection::Pg(conn) => {
+                AnsiTransactionManager::begin_transaction_sql(conn, "BEGIN")?;
+                if let Err(e) = diesel::sql_query("SELECT pg_advisory_xact_lock(0)").execute(conn) {
+                    let _ = AnsiTransactionManager::rollback_transaction(conn);
+                    return Err(e.into());
+                }
+            }
+            DbConnection::Sqlite(conn) => {
+                AnsiTransactionManager::begin_transaction_sql(conn, "BEGIN IMMEDIATE")?
+            }
+        }
+        match f(self) {
+            Ok(value) => {
+                <Self as TransactionManager<Self>>::commit_transaction(self)?;
+                Ok(value)
+            }
+            Err(e) => match <Self as TransactionManager<Self>>::rollback_transaction(self) {
+                Ok(()) | Err(diesel::result::Error::BrokenTransactionManager) => Err(e),
+                Err(rollback_error) => Err(rollback_error.into()),
+            },
+        }
+    }
+}
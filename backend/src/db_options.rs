@@ -1,23 +1,104 @@
+use std::str::FromStr;
 use std::time::Duration;
 
-use diesel::{connection::SimpleConnection, SqliteConnection};
+use diesel::connection::SimpleConnection;
 
+use crate::db::DbConnection;
+
+// Options of the SQLite connections, Postgres always enforces the foreign keys and waits for locks
 #[derive(Debug)]
 pub struct ConnectionOptions {
     pub enable_foreign_keys: bool,
     pub busy_timeout: Option<Duration>,
+    // Write ahead logging lets the players read while an advance is written
+    pub enable_wal: bool,
+    pub synchronous: Option<Synchronous>,
+    // Pages when positive, KiB when negative, as SQLite takes it
+    pub cache_size: Option<i64>,
+}
+
+// How often SQLite waits for the writes to reach the disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl FromStr for Synchronous {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "OFF" => Ok(Synchronous::Off),
+            "NORMAL" => Ok(Synchronous::Normal),
+            "FULL" => Ok(Synchronous::Full),
+            "EXTRA" => Ok(Synchronous::Extra),
+            _ => Err(format!("unknown synchronous level: {}", s)),
+        }
+    }
+}
+
+impl Synchronous {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
 }
 
-impl diesel::r2d2::CustomizeConnection<SqliteConnection, diesel::r2d2::Error>
-    for ConnectionOptions
-{
-    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+impl ConnectionOptions {
+    // Options from the SQLITE_WAL, SQLITE_SYNCHRONOUS and SQLITE_CACHE_SIZE environment variables,
+    // WAL being enabled unless told otherwise
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Ok(ConnectionOptions {
+            enable_foreign_keys: true,
+            busy_timeout: Some(Duration::from_secs(30)),
+            enable_wal: var("SQLITE_WAL")
+                .map(|v| {
+                    bool::from_str(&v).map_err(|_| format!("SQLITE_WAL is not a boolean: {}", v))
+                })
+                .transpose()?
+                .unwrap_or(true),
+            synchronous: var("SQLITE_SYNCHRONOUS")
+                .map(|v| Synchronous::from_str(&v))
+                .transpose()?,
+            cache_size: var("SQLITE_CACHE_SIZE")
+                .map(|v| {
+                    i64::from_str(&v)
+                        .map_err(|_| format!("SQLITE_CACHE_SIZE is not a number: {}", v))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+impl diesel::r2d2::CustomizeConnection<DbConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), diesel::r2d2::Error> {
+        let DbConnection::Sqlite(conn) = conn else {
+            return Ok(());
+        };
         (|| {
+            // The busy timeout comes first, so that the other pragmas wait for the locks too
+            if let Some(d) = self.busy_timeout {
+                conn.batch_execute(&format!("PRAGMA busy_timeout = {};", d.as_millis()))?;
+            }
             if self.enable_foreign_keys {
                 conn.batch_execute("PRAGMA foreign_keys = ON;")?;
             }
-            if let Some(d) = self.busy_timeout {
-                conn.batch_execute(&format!("PRAGMA busy_timeout = {};", d.as_millis()))?;
+            if self.enable_wal {
+                conn.batch_execute("PRAGMA journal_mode = WAL;")?;
+            }
+            if let Some(s) = self.synchronous {
+                conn.batch_execute(&format!("PRAGMA synchronous = {};", s.as_str()))?;
+            }
+            if let Some(size) = self.cache_size {
+                conn.batch_execute(&format!("PRAGMA cache_size = {};", size))?;
             }
             Ok(())
         })()
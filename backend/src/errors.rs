@@ -1,8 +1,14 @@
 use actix_web::error::BlockingError;
+use actix_web::error::JsonPayloadError;
 use actix_web::error::PayloadError;
 use actix_web::error::ResponseError;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
+use diesel::result::DatabaseErrorKind;
 use image::ImageError;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
 
 #[derive(Debug)]
 pub enum ServerError {
@@ -11,47 +17,119 @@ pub enum ServerError {
     Diesel,
     DieselNotFound,
     DieselDatabaseError(String),
+    // A constraint of the database refused the change
+    DieselConstraint(String),
+    BadRequest(String),
     Unauthorized(String),
     Forbidden(String),
-    NotAcceptable(String),
     Image(String),
     NotFound(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    PreconditionFailed(String),
+    // A refusal the client acts upon, told apart by its code, with what it needs in the details
+    Refused {
+        status: StatusCode,
+        code: &'static str,
+        message: String,
+        details: Option<Value>,
+    },
 }
 
-impl std::fmt::Display for ServerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+// The body of every error response
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    // Stable, for the clients to tell the errors apart
+    code: &'a str,
+    // For humans, it may change
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    details: Option<&'a Value>,
+}
+
+impl ServerError {
+    pub fn code(&self) -> &str {
         match self {
-            ServerError::R2D2 => write!(f, "R2D2 error"),
-            ServerError::Blocking => write!(f, "Blocking error"),
-            ServerError::Diesel => write!(f, "Diesel error"),
-            ServerError::DieselNotFound => write!(f, "Item not found"),
-            ServerError::DieselDatabaseError(m) => write!(f, "{}", m),
-            ServerError::Unauthorized(m) => write!(f, "Error: {}", m),
-            ServerError::Forbidden(m) => write!(f, "Error: {}", m),
-            ServerError::NotAcceptable(m) => write!(f, "Error: {}", m),
-            ServerError::Image(m) => write!(f, "Image error: {}", m),
-            ServerError::NotFound(m) => write!(f, "Error: {}", m),
+            ServerError::R2D2 => "database_unavailable",
+            ServerError::Blocking => "internal_error",
+            ServerError::Diesel => "database_error",
+            ServerError::DieselNotFound => "not_found",
+            ServerError::DieselDatabaseError(_) => "database_error",
+            ServerError::DieselConstraint(_) => "conflict",
+            ServerError::BadRequest(_) => "bad_request",
+            ServerError::Unauthorized(_) => "unauthorized",
+            ServerError::Forbidden(_) => "forbidden",
+            ServerError::Image(_) => "file_error",
+            ServerError::NotFound(_) => "not_found",
+            ServerError::PayloadTooLarge(_) => "payload_too_large",
+            ServerError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ServerError::PreconditionFailed(_) => "precondition_failed",
+            ServerError::Refused { code, .. } => code,
         }
     }
+
+    fn message(&self) -> String {
+        match self {
+            ServerError::R2D2 => "the database is not available".to_string(),
+            ServerError::Blocking => "the request could not be handled".to_string(),
+            ServerError::Diesel => "the database could not handle the request".to_string(),
+            ServerError::DieselNotFound => "Item not found".to_string(),
+            ServerError::DieselDatabaseError(m)
+            | ServerError::DieselConstraint(m)
+            | ServerError::BadRequest(m)
+            | ServerError::Unauthorized(m)
+            | ServerError::Forbidden(m)
+            | ServerError::Image(m)
+            | ServerError::NotFound(m)
+            | ServerError::PayloadTooLarge(m)
+            | ServerError::UnsupportedMediaType(m)
+            | ServerError::PreconditionFailed(m)
+            | ServerError::Refused { message: m, .. } => m.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error {}: {}", self.code(), self.message())
+    }
 }
 
 impl std::error::Error for ServerError {}
 
 impl ResponseError for ServerError {
-    fn error_response(&self) -> HttpResponse {
+    fn status_code(&self) -> StatusCode {
         match self {
-            ServerError::R2D2 => HttpResponse::InternalServerError().body("R2D2 error"),
-            ServerError::Blocking => HttpResponse::InternalServerError().body("Blocking error"),
-            ServerError::Diesel => HttpResponse::InternalServerError().body("Diesel error"),
-            ServerError::DieselNotFound => HttpResponse::NotFound().body("Item not found"),
-            ServerError::DieselDatabaseError(m) => HttpResponse::NotFound().body(m.clone()),
-            ServerError::Unauthorized(m) => HttpResponse::Unauthorized().body(m.clone()),
-            ServerError::Forbidden(m) => HttpResponse::Forbidden().body(m.clone()),
-            ServerError::NotAcceptable(m) => HttpResponse::NotAcceptable().body(m.clone()),
-            ServerError::Image(m) => HttpResponse::InternalServerError().body(m.clone()),
-            ServerError::NotFound(m) => HttpResponse::NotFound().body(m.clone()),
+            ServerError::R2D2 => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Blocking => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::Diesel => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::DieselNotFound => StatusCode::NOT_FOUND,
+            ServerError::DieselDatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::DieselConstraint(_) => StatusCode::CONFLICT,
+            ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ServerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ServerError::Image(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServerError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ServerError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ServerError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ServerError::Refused { status, .. } => *status,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let details = match self {
+            ServerError::Refused { details, .. } => details.as_ref(),
+            _ => None,
+        };
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            details,
+        })
+    }
 }
 
 impl From<r2d2::Error> for ServerError {
@@ -64,6 +142,13 @@ impl From<diesel::result::Error> for ServerError {
     fn from(err: diesel::result::Error) -> ServerError {
         match err {
             diesel::result::Error::NotFound => ServerError::DieselNotFound,
+            diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::UniqueViolation
+                | DatabaseErrorKind::ForeignKeyViolation
+                | DatabaseErrorKind::NotNullViolation
+                | DatabaseErrorKind::CheckViolation,
+                info,
+            ) => ServerError::DieselConstraint(info.message().to_string()),
             diesel::result::Error::DatabaseError(_, info) => {
                 ServerError::DieselDatabaseError(info.message().to_string())
             }
@@ -86,7 +171,23 @@ impl From<std::io::Error> for ServerError {
 
 impl From<PayloadError> for ServerError {
     fn from(err: PayloadError) -> ServerError {
-        ServerError::Image(err.to_string())
+        match err {
+            PayloadError::Overflow => ServerError::PayloadTooLarge(err.to_string()),
+            _ => ServerError::BadRequest(err.to_string()),
+        }
+    }
+}
+
+// The body of a request is too large, of another type, or not the expected JSON
+impl From<JsonPayloadError> for ServerError {
+    fn from(err: JsonPayloadError) -> ServerError {
+        match err {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                ServerError::PayloadTooLarge(err.to_string())
+            }
+            JsonPayloadError::ContentType => ServerError::UnsupportedMediaType(err.to_string()),
+            _ => ServerError::BadRequest(err.to_string()),
+        }
     }
 }
 
@@ -6,24 +6,46 @@ extern crate diesel_migrations;
 use crate::auth::AppConfig;
 use actix_web::web::Data;
 use actix_web::HttpServer;
-use diesel::prelude::*;
+use db::DbConnection;
 use diesel::r2d2::{self, ConnectionManager};
-use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use diesel::Connection;
 use std::env;
+use std::sync::Arc;
+use storage::{FileStorage, S3Storage, Storage};
 
 mod app;
 mod auth;
+mod db;
 mod db_options;
 mod errors;
 mod models;
+mod openapi;
 mod schema;
+mod sniff;
+mod storage;
 #[cfg(test)]
 pub mod tester;
 #[cfg(test)]
 mod tests;
 mod utils;
 
-use log::info;
+use log::{error, info};
+
+// Stop the server on a problem it cannot recover from, with a message rather than a panic
+fn exit_with(message: &str) -> ! {
+    error!("{}", message);
+    std::process::exit(1)
+}
+
+// A number set in the environment, stopping the server if it is set to anything else
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| exit_with(&format!("{} is not a number: {}", name, value))),
+    )
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -33,26 +55,157 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init();
 
+    // Documentation command: `pistou openapi` prints the OpenAPI document of the API, without
+    // needing a database
+    if env::args().nth(1).as_deref() == Some("openapi") {
+        use utoipa::OpenApi;
+        println!(
+            "{}",
+            openapi::ApiDoc::openapi()
+                .to_pretty_json()
+                .map_err(std::io::Error::other)?
+        );
+        return Ok(());
+    }
+
     // create the db folder if it doesn't already exist
     std::fs::create_dir_all("db").expect("failed creating db folder");
 
-    // set up database connection pool
-    let manager = ConnectionManager::<SqliteConnection>::new("db/db.sqlite");
-    let pool = r2d2::Pool::builder()
-        .connection_customizer(Box::new(db_options::ConnectionOptions {
-            enable_foreign_keys: false,
-            busy_timeout: Some(std::time::Duration::from_secs(30)),
-        }))
+    // set up database connection pool, on the SQLite database by default or on the Postgres one
+    // given by a postgres:// URL
+    let database_url = env::var("DATABASE_URL").unwrap_or(db::DEFAULT_DATABASE_URL.to_string());
+    info!(
+        "Using the {} database",
+        if db::is_postgres(&database_url) {
+            "Postgres"
+        } else {
+            "SQLite"
+        }
+    );
+    let options = db_options::ConnectionOptions::from_env().unwrap_or_else(|e| exit_with(&e));
+    let mut builder = r2d2::Pool::builder().connection_customizer(Box::new(options));
+    if let Ok(size) = env::var("DATABASE_POOL_SIZE") {
+        builder = builder.max_size(size.parse().unwrap_or_else(|_| {
+            exit_with(&format!("DATABASE_POOL_SIZE is not a number: {}", size))
+        }));
+    }
+    // check the database on its own first, the pool would retry a broken one until it times out
+    db::check(
+        &mut DbConnection::establish(&database_url)
+            .unwrap_or_else(|e| exit_with(&format!("couldn't open the database: {}", e))),
+    )
+    .unwrap_or_else(|e| exit_with(&e));
+    let manager = ConnectionManager::<DbConnection>::new(database_url);
+    let pool: db::DbPool = builder
         .build(manager)
-        .expect("failed to create pool.");
-    pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations");
-    pool.get()
-        .expect("couldn't get db connection from pool")
-        .run_pending_migrations(MIGRATIONS)
-        .expect("couldn't run migrations");
+        .unwrap_or_else(|e| exit_with(&format!("couldn't open the database: {}", e)));
+    let connection = || {
+        pool.get()
+            .unwrap_or_else(|e| exit_with(&format!("couldn't get a database connection: {}", e)))
+    };
+    db::run_migrations(&mut connection())
+        .unwrap_or_else(|e| exit_with(&format!("couldn't run the migrations: {}", e)));
+
+    // Files of the steps are kept under data/items, unless an S3 compatible bucket is configured
+    let storage: Arc<dyn Storage> = match (env::var("S3_ENDPOINT"), env::var("S3_BUCKET")) {
+        (Ok(endpoint), Ok(bucket)) => {
+            info!("Storing the files in the bucket {} of {}", bucket, endpoint);
+            Arc::new(S3Storage {
+                endpoint,
+                bucket,
+                region: env::var("S3_REGION").unwrap_or("us-east-1".to_string()),
+                access_key: env::var("S3_ACCESS_KEY").unwrap_or_default(),
+                secret_key: env::var("S3_SECRET_KEY").unwrap_or_default(),
+                path_style: std::str::FromStr::from_str(
+                    &env::var("S3_PATH_STYLE").unwrap_or_default(),
+                )
+                .unwrap_or(true),
+            })
+        }
+        _ => Arc::new(FileStorage::new("data/items")),
+    };
+
+    let recorded = crate::models::step::record_legacy_medias(&mut connection(), storage.as_ref())
+        .unwrap_or_else(|e| exit_with(&format!("couldn't record the legacy medias: {}", e)));
+    if recorded > 0 {
+        info!(
+            "Recorded {} medias uploaded by a previous version",
+            recorded
+        );
+    }
+    let copied = crate::models::version::copy_legacy_files(&mut connection(), storage.as_ref())
+        .unwrap_or_else(|e| exit_with(&format!("couldn't copy the files of the versions: {}", e)));
+    if copied > 0 {
+        info!("Copied the files of {} versions published before", copied);
+    }
+
+    // Backups are saved under data/backups, the 7 latest ones being kept
+    let backups_path = env::var("BACKUP_PATH").unwrap_or("data/backups".to_string());
+    let backup_retention = env_number("BACKUP_RETENTION").unwrap_or(7);
+
+    // Maintenance command: `pistou gc [--dry-run]` reports and removes the orphaned files
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("gc") {
+        let dry_run = args.iter().any(|a| a == "--dry-run");
+        let report = crate::models::maintenance::collect_garbage(
+            &mut connection(),
+            storage.as_ref(),
+            !dry_run,
+        )
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+        for file in &report.files {
+            println!("{}", file);
+        }
+        println!(
+            "{} {} orphaned files ({} bytes)",
+            if dry_run { "Found" } else { "Removed" },
+            report.files.len(),
+            report.bytes
+        );
+        return Ok(());
+    }
+
+    // Backup commands: `pistou backup` saves an archive of the database and the files, and
+    // `pistou restore <archive>` restores one
+    match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("backup"), _) => {
+            let backup = crate::models::backup::create_backup(
+                &mut connection(),
+                storage.as_ref(),
+                &backups_path,
+                backup_retention,
+            )
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+            println!("{}/{} ({} bytes)", backups_path, backup.name, backup.size);
+            return Ok(());
+        }
+        (Some("restore"), Some(archive)) => {
+            crate::models::backup::restore_backup(
+                &mut connection(),
+                storage.as_ref(),
+                std::path::Path::new(archive),
+            )
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+            println!("Restored {}", archive);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Periodic backups, every BACKUP_INTERVAL minutes
+    if let Some(minutes) = env_number::<u64>("BACKUP_INTERVAL").filter(|m| *m > 0) {
+        info!("Backing up the database every {} minutes", minutes);
+        crate::models::backup::schedule_backups(
+            pool.clone(),
+            storage.clone(),
+            backups_path.clone(),
+            backup_retention,
+            std::time::Duration::from_secs(minutes * 60),
+        );
+    }
 
     // Set up authorization token
-    let app_config = AppConfig::new(
+    let mut app_config = AppConfig::new(
         env::var("TOKEN").unwrap_or_else(|_| -> String {
             let token = crate::utils::random_string();
             info!("Authorization token: {}", token);
@@ -61,6 +214,19 @@ async fn main() -> std::io::Result<()> {
         std::str::FromStr::from_str(&env::var("LOCATION_CHECK").unwrap_or_default())
             .unwrap_or(true),
     );
+    if let Some(size) = env_number("MEDIA_MAX_SIZE") {
+        app_config.media_max_size = size;
+    }
+    if let Some(size) = env_number("BACKUP_MAX_SIZE") {
+        app_config.backup_max_size = size;
+    }
+    // Lighter copies of the medias need ffmpeg to be installed
+    app_config.low_bitrate_renditions =
+        std::str::FromStr::from_str(&env::var("LOW_BITRATE_RENDITIONS").unwrap_or_default())
+            .unwrap_or(false);
+    app_config.storage = storage;
+    app_config.backups_path = backups_path;
+    app_config.backup_retention = backup_retention;
     // Data should be constructed outside the HttpServer::new closure if shared, potentially mutable state is desired...
     let app_data = Data::new(app_config);
 
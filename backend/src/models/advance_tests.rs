@@ -1,9 +1,6 @@
-use crate::{auth::AppConfig, create_app};
+use crate::{auth::AppConfig, create_app, tester::error_json};
 
-pub async fn advance_test(
-    pool: &r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
-    app_config: &actix_web::web::Data<AppConfig>,
-) {
+pub async fn advance_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
     use crate::{do_test, do_test_extract_id};
     use actix_web::{
         http::{Method, StatusCode},
@@ -57,8 +54,8 @@ pub async fn advance_test(
         "{\"id\""
     );
 
-    // Get the current step
-    do_test!(
+    // Get the current step, whose answer is not given to the player
+    let current = do_test!(
         app,
         "",
         Method::GET,
@@ -66,9 +63,10 @@ pub async fn advance_test(
         "",
         StatusCode::OK,
         format!(
-            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue","is_end":false}}"#
+            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
+    assert!(!current.contains(r#""answer""#));
 
     // Try to advance step with the wrong password (must fail)
     do_test!(
@@ -78,7 +76,7 @@ pub async fn advance_test(
         &format!("/api/users/{id}/advance"),
         r#"{"password":"Wrong test password","latitude":45.74846,"longitude":4.84671,"answer":"yellow"}"#,
         StatusCode::FORBIDDEN,
-        r#"{"type":"WrongPassword"}"#
+        error_json("wrong_password", "the password is wrong")
     );
     // Try to advance step with the right password, but the wrong position (must fail, and give a hint on how to reach the right position)
     do_test!(
@@ -88,7 +86,7 @@ pub async fn advance_test(
         &format!("/api/users/{id}/advance"),
         r#"{"password":"Test password","latitude":45.16667,"longitude":5.71667,"answer":"yellow"}"#,
         StatusCode::NOT_ACCEPTABLE,
-        r#"{"type":"WrongPlace","distance":93749.54"#
+        r#"{"code":"wrong_place","message":"the step is 93750 meters away","details":{"distance":93749.54"#
     );
 
     // Try to advance step with the right password, the right position, but the wrong answer (must fail)
@@ -99,11 +97,11 @@ pub async fn advance_test(
         &format!("/api/users/{id}/advance"),
         r#"{"password":"Test password","latitude":45.74846,"longitude":4.84671,"answer":"yellow"}"#,
         StatusCode::NOT_ACCEPTABLE,
-        r#"{"type":"WrongAnswer"}"#
+        error_json("wrong_answer", "the answer is wrong")
     );
 
-    // Try to advance step with the right password, the right position, and the right answer (must pass)
-    do_test!(
+    // Try to advance step with the right password, the right position, and the right answer (must pass, without giving the answer of the next step)
+    let next = do_test!(
         app,
         "",
         Method::POST,
@@ -111,9 +109,10 @@ pub async fn advance_test(
         r#"{"password":"Test password","latitude":45.74846,"longitude":4.84671,"answer":"blue"}"#,
         StatusCode::OK,
         format!(
-            r#"{{"type":"Success","id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"quel est le plus grand parc de Lyon ?","answer":"Le Parc de la Tête d'Or","is_end":false}}"#
+            r#"{{"type":"Success","id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"quel est le plus grand parc de Lyon ?","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
+    assert!(!next.contains(r#""answer""#));
 
     // Get the current step
     do_test!(
@@ -124,10 +123,78 @@ pub async fn advance_test(
         "",
         StatusCode::OK,
         format!(
-            r#"{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"quel est le plus grand parc de Lyon ?","answer":"Le Parc de la Tête d'Or","is_end":false}}"#
+            r#"{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"quel est le plus grand parc de Lyon ?","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
 
+    // Ping with the wrong password (must fail)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/ping"),
+        r#"{"password":"Wrong test password","latitude":45.16667,"longitude":5.71667}"#,
+        StatusCode::FORBIDDEN,
+        error_json("wrong_password", "the password is wrong")
+    );
+
+    // Ping from far away (must be cold, without bearing since it is not enabled for this step)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/ping"),
+        r#"{"password":"Test password","latitude":45.74846,"longitude":4.84671}"#,
+        StatusCode::OK,
+        r#"{"band":"Cold","location_ok":false}"#
+    );
+
+    // Enable the bearing disclosure for the step
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        &format!("/api/steps/{id2}"),
+        &format!(
+            r#"{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"quel est le plus grand parc de Lyon ?","answer":"Le Parc de la Tête d'Or","is_end":false,"show_bearing":true}}"#
+        ),
+        StatusCode::OK,
+        format!(r#"{{"id":{id2},"rank":2"#)
+    );
+
+    // Ping from 500 m south of the step (must be warm, with the bearing to the north)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/ping"),
+        r#"{"password":"Test password","latitude":45.16217,"longitude":5.71667}"#,
+        StatusCode::OK,
+        r#"{"band":"Warm","bearing":0.0,"location_ok":false}"#
+    );
+
+    // Ping from 100 m east of the step (must be hot, with the bearing to the west)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/ping"),
+        r#"{"password":"Test password","latitude":45.16667,"longitude":5.71794}"#,
+        StatusCode::OK,
+        r#"{"band":"Hot","bearing":270.0,"location_ok":false}"#
+    );
+
+    // Ping from the step (must be here, and the location check must pass)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/ping"),
+        r#"{"password":"Test password","latitude":45.16667,"longitude":5.71667}"#,
+        StatusCode::OK,
+        r#"{"band":"Here","location_ok":true}"#
+    );
+
     // Try to advance step with the right password, the right position, and a CLOSE answer (must pass, with 404 since there is no more steps)
     do_test!(
         app,
@@ -136,7 +203,166 @@ pub async fn advance_test(
         &format!("/api/users/{id}/advance"),
         r#"{"password":"Test password","latitude":45.16667,"longitude":5.71667,"answer":"parc tete dor"}"#,
         StatusCode::NOT_FOUND,
-        "Item not found"
+        error_json("no_more_steps", "there are no more steps")
+    );
+
+    // Delete all the steps
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/steps",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+
+    // Create a user
+    let id = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"Test name 2","password":"Test password"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+
+    // Create a secret code step without code (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"find the sticker","question":"what is the code?","answer":"","validation_mode":"SecretCode","secret_code":"   "}"#,
+        StatusCode::BAD_REQUEST,
+        error_json(
+            "bad_request",
+            "secret_code cannot be empty for a SecretCode step"
+        )
+    );
+
+    // Create a location only step, a secret code step and an answer only step
+    let id1 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"just get there","answer":"","validation_mode":"LocationOnly"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let id2 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"find the sticker","question":"what is the code?","answer":"","validation_mode":"SecretCode","secret_code":"  ABC123  "}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let id3 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":3,"latitude":45.16667,"longitude":5.71667,"location_hint":"anywhere","question":"what is the color of the sky?","answer":"blue","validation_mode":"AnswerOnly"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+
+    // Get the secret code step as an organizer
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        &format!("/api/steps/{id2}"),
+        "",
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"find the sticker","question":"what is the code?","is_end":false,"show_bearing":false,"validation_mode":"SecretCode","secret_code":"ABC123"}}"#
+        )
+    );
+
+    // Get the current step
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{id}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(r#"{{"id":{id1},"rank":1"#)
+    );
+
+    // Try to validate the location only step from elsewhere (must fail)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.16667,"longitude":5.71667,"answer":""}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        r#"{"code":"wrong_place""#
+    );
+
+    // Validate the location only step without answer (must pass, without revealing the code of the next step)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.74846,"longitude":4.84671,"answer":""}"#,
+        StatusCode::OK,
+        format!(
+            r#"{{"type":"Success","id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"find the sticker","question":"what is the code?","is_end":false,"show_bearing":false,"validation_mode":"SecretCode"}}"#
+        )
+    );
+
+    // Get the current step (must not reveal the code)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{id}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"find the sticker","question":"what is the code?","is_end":false,"show_bearing":false,"validation_mode":"SecretCode"}}"#
+        )
+    );
+
+    // Try to validate the secret code step with the wrong code (must fail)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","answer":"ABC124"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json("wrong_answer", "the answer is wrong")
+    );
+
+    // Validate the secret code step without position (must pass)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","answer":" abc123 "}"#,
+        StatusCode::OK,
+        format!(r#"{{"type":"Success","id":{id3},"rank":3"#)
+    );
+
+    // Validate the answer only step from far away (must pass, with 404 since there is no more steps)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":48.85341,"longitude":2.3488,"answer":"blue"}"#,
+        StatusCode::NOT_FOUND,
+        error_json("no_more_steps", "there are no more steps")
     );
 
     // Delete all the users
@@ -0,0 +1,316 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::Authenticated,
+    db::DbConnection,
+    errors::ServerError,
+    models::step::Step,
+    schema::{anticheat_settings, cheat_flags, positions},
+    utils::{get_dist, now},
+};
+
+// Moves shorter than this are considered as GPS jitter and never checked for speed
+const JITTER_DISTANCE: f64 = 50.0;
+// The same coordinates reported again within this delay, in milliseconds, are a retry of the
+// same advance and not a replay
+const RETRY_WINDOW: i64 = 60_000;
+const SETTINGS_ID: i32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, AsChangeset, Identifiable, ToSchema)]
+#[diesel(table_name = anticheat_settings)]
+pub struct AntiCheatSettings {
+    #[serde(default)]
+    pub id: i32,
+    // Refuse the advance instead of just flagging it
+    pub reject: bool,
+    // Maximum plausible speed between two reported positions, in km/h
+    pub max_speed: f64,
+    // Number of identical consecutive positions that is considered suspicious
+    pub repeat_threshold: i32,
+    // Number of positions kept for each player
+    pub history_size: i32,
+}
+
+impl AntiCheatSettings {
+    fn validate(&self) -> Result<(), ServerError> {
+        if self.max_speed <= 0.0 {
+            return Err(ServerError::BadRequest(
+                "max_speed must be positive".to_string(),
+            ));
+        }
+        if self.repeat_threshold < 1 {
+            return Err(ServerError::BadRequest(
+                "repeat_threshold must be at least 1".to_string(),
+            ));
+        }
+        if self.history_size < self.repeat_threshold {
+            return Err(ServerError::BadRequest(
+                "history_size cannot be lower than repeat_threshold".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = positions)]
+pub struct Position {
+    pub id: i32,
+    pub user_id: i32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub recorded_at: i64,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = positions)]
+pub struct NewPosition {
+    pub user_id: i32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub recorded_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagKind {
+    ImpossibleSpeed,
+    RepeatedCoordinates,
+    ExactStepLocation,
+}
+
+impl FlagKind {
+    fn name(&self) -> &'static str {
+        match self {
+            FlagKind::ImpossibleSpeed => "ImpossibleSpeed",
+            FlagKind::RepeatedCoordinates => "RepeatedCoordinates",
+            FlagKind::ExactStepLocation => "ExactStepLocation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, ToSchema)]
+#[diesel(table_name = cheat_flags)]
+pub struct CheatFlag {
+    pub id: i32,
+    pub user_id: i32,
+    pub step_id: i32,
+    pub kind: String,
+    pub details: String,
+    pub rejected: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = cheat_flags)]
+pub struct NewCheatFlag {
+    pub user_id: i32,
+    pub step_id: i32,
+    pub kind: String,
+    pub details: String,
+    pub rejected: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReportEntry {
+    #[serde(flatten)]
+    pub flag: CheatFlag,
+    pub user_name: Option<String>,
+}
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+pub fn settings(conn: &mut DbConnection) -> Result<AntiCheatSettings, diesel::result::Error> {
+    use crate::schema::anticheat_settings::dsl::*;
+    anticheat_settings
+        .find(SETTINGS_ID)
+        .first::<AntiCheatSettings>(conn)
+}
+
+// Check the plausibility of a reported position against the player's history, record it, and record the raised flags
+pub fn check(
+    conn: &mut DbConnection,
+    settings: &AntiCheatSettings,
+    uid: i32,
+    step: &Step,
+    latitude: f64,
+    longitude: f64,
+) -> Result<Vec<FlagKind>, diesel::result::Error> {
+    let recorded_at = now();
+    let history = {
+        use crate::schema::positions::dsl;
+        dsl::positions
+            .filter(dsl::user_id.eq(uid))
+            .order(dsl::id.desc())
+            .limit(settings.history_size as i64)
+            .load::<Position>(conn)?
+    };
+
+    let mut flags = Vec::new();
+
+    // Check that the player could have travelled from the last reported position
+    if let Some(last) = history.first() {
+        let distance = get_dist(last.latitude, last.longitude, latitude, longitude);
+        if distance > JITTER_DISTANCE {
+            let elapsed = std::cmp::max(recorded_at - last.recorded_at, 1) as f64 / 1000.0;
+            let speed = distance / elapsed * 3.6;
+            if speed > settings.max_speed {
+                flags.push((
+                    FlagKind::ImpossibleSpeed,
+                    format!("{distance:.0} m in {elapsed:.1} s ({speed:.0} km/h)"),
+                ));
+            }
+        }
+    }
+
+    // Check that the coordinates are not replayed, a player retrying from where they stand is not
+    let retry = history.first().is_some_and(|last| {
+        last.latitude == latitude
+            && last.longitude == longitude
+            && recorded_at - last.recorded_at < RETRY_WINDOW
+    });
+    let repeats = history
+        .iter()
+        .take_while(|p| p.latitude == latitude && p.longitude == longitude)
+        .count();
+    if !retry && repeats > 0 && repeats + 1 >= settings.repeat_threshold as usize {
+        flags.push((
+            FlagKind::RepeatedCoordinates,
+            format!("same coordinates reported {} times in a row", repeats + 1),
+        ));
+    }
+
+    // Check that the coordinates are not copied from the step
+    if latitude == step.latitude && longitude == step.longitude {
+        flags.push((
+            FlagKind::ExactStepLocation,
+            format!("coordinates are exactly {latitude}, {longitude}"),
+        ));
+    }
+
+    // Record the position and forget about the oldest ones, a retry being already recorded
+    if !retry {
+        use crate::schema::positions::dsl;
+        diesel::insert_into(dsl::positions)
+            .values(&NewPosition {
+                user_id: uid,
+                latitude,
+                longitude,
+                recorded_at,
+            })
+            .execute(conn)?;
+        if history.len() >= settings.history_size as usize {
+            if let Some(oldest) = history.last() {
+                diesel::delete(dsl::positions)
+                    .filter(dsl::user_id.eq(uid))
+                    .filter(dsl::id.le(oldest.id))
+                    .execute(conn)?;
+            }
+        }
+    }
+
+    // Record the flags, one by one as batch inserts are not supported on both backends
+    for (kind, details) in &flags {
+        diesel::insert_into(cheat_flags::table)
+            .values(&NewCheatFlag {
+                user_id: uid,
+                step_id: step.id,
+                kind: kind.name().to_string(),
+                details: details.clone(),
+                rejected: settings.reject,
+                created_at: recorded_at,
+            })
+            .execute(conn)?;
+    }
+
+    Ok(flags.into_iter().map(|(kind, _)| kind).collect())
+}
+
+#[utoipa::path(
+    summary = "Read the settings of the checks on the reported positions",
+    responses((status = 200, body = AntiCheatSettings)),
+    security(("token" = [])),
+)]
+#[get("/settings")]
+pub async fn read_settings(
+    pool: web::Data<DbPool>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let s = crate::db::run(&pool, settings).await?;
+    Ok(HttpResponse::Ok().json(s))
+}
+
+fn save_settings(conn: &mut DbConnection, o: &AntiCheatSettings) -> QueryResult<AntiCheatSettings> {
+    use crate::schema::anticheat_settings::dsl::*;
+    diesel::update(anticheat_settings)
+        .filter(id.eq(SETTINGS_ID))
+        .set(o)
+        .execute(conn)?;
+    settings(conn)
+}
+
+#[utoipa::path(
+    summary = "Change the settings of the checks on the reported positions",
+    responses((status = 200, body = AntiCheatSettings)),
+    security(("token" = [])),
+)]
+#[put("/settings")]
+pub async fn update_settings(
+    pool: web::Data<DbPool>,
+    mut o: web::Json<AntiCheatSettings>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    o.validate()?;
+    o.id = SETTINGS_ID;
+    let s = crate::db::run(&pool, move |conn| save_settings(conn, &o)).await?;
+    Ok(HttpResponse::Ok().json(s))
+}
+
+// The flags raised so far, with the name of the players who are still there
+fn report_entries(conn: &mut DbConnection) -> QueryResult<Vec<ReportEntry>> {
+    use crate::schema::users;
+    Ok(cheat_flags::table
+        .left_join(users::table.on(users::id.eq(cheat_flags::user_id)))
+        .select((cheat_flags::all_columns, users::name.nullable()))
+        .order(cheat_flags::id.asc())
+        .load::<(CheatFlag, Option<String>)>(conn)?
+        .into_iter()
+        .map(|(flag, user_name)| ReportEntry { flag, user_name })
+        .collect())
+}
+
+#[utoipa::path(
+    summary = "List the suspicious advances",
+    responses((status = 200, body = Vec<ReportEntry>)),
+    security(("token" = [])),
+)]
+#[get("/report")]
+pub async fn report(
+    pool: web::Data<DbPool>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let entries = crate::db::run(&pool, report_entries).await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+fn clear_flags(conn: &mut DbConnection) -> QueryResult<usize> {
+    diesel::delete(cheat_flags::table).execute(conn)
+}
+
+#[utoipa::path(
+    summary = "Clear the suspicious advances",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
+#[delete("/report")]
+pub async fn clear_report(
+    pool: web::Data<DbPool>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    crate::db::run(&pool, clear_flags).await?;
+    Ok(HttpResponse::Ok().body("Report cleared"))
+}
@@ -0,0 +1,278 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+use diesel::prelude::*;
+
+pub async fn anticheat_test(
+    pool: &crate::db::DbPool,
+    app_config: &actix_web::web::Data<AppConfig>,
+) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/users")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Clear the report
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/anticheat/report",
+        "",
+        StatusCode::OK,
+        "Report cleared"
+    );
+
+    // Get the settings without a token (must fail)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        "/api/anticheat/settings",
+        "",
+        StatusCode::UNAUTHORIZED,
+        error_json("unauthorized", "authorization header is too short")
+    );
+
+    // Get the default settings
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/anticheat/settings",
+        "",
+        StatusCode::OK,
+        r#"{"id":1,"reject":false,"max_speed":30.0,"repeat_threshold":3,"history_size":10}"#
+    );
+
+    // Update the settings with an incoherent history size (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        "/api/anticheat/settings",
+        r#"{"reject":false,"max_speed":30.0,"repeat_threshold":3,"history_size":2}"#,
+        StatusCode::BAD_REQUEST,
+        error_json(
+            "bad_request",
+            "history_size cannot be lower than repeat_threshold"
+        )
+    );
+
+    // Create a user
+    let id = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"Cheater","password":"Test password"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+
+    // Create two steps
+    let id1 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue","is_end":false}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"what is the color of the grass?","answer":"green","is_end":false}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+
+    // Advance from a realistic position, near the step (must only fail on the answer)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.74851,"longitude":4.84668,"answer":"yellow"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json("wrong_answer", "the answer is wrong")
+    );
+
+    // Advance from a position 90 km away a few milliseconds later (flagged, but not rejected)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.16667,"longitude":5.71667,"answer":"yellow"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        r#"{"code":"wrong_place""#
+    );
+
+    // Advance from the exact step coordinates (flagged, but not rejected)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.74846,"longitude":4.84671,"answer":"yellow"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json("wrong_answer", "the answer is wrong")
+    );
+
+    // Check the report
+    let report = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/anticheat/report",
+        "",
+        StatusCode::OK,
+        "["
+    );
+    assert!(!report.contains(r#""kind":"RepeatedCoordinates""#));
+    assert!(report.contains(&format!(
+        r#""user_id":{id},"step_id":{id1},"kind":"ImpossibleSpeed""#
+    )));
+    assert!(report.contains(&format!(
+        r#""user_id":{id},"step_id":{id1},"kind":"ExactStepLocation","details":"coordinates are exactly 45.74846, 4.84671","rejected":false"#
+    )));
+    assert!(report.contains(r#""user_name":"Cheater""#));
+
+    // Reject suspicious advances
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        "/api/anticheat/settings",
+        r#"{"reject":true,"max_speed":30.0,"repeat_threshold":2,"history_size":10}"#,
+        StatusCode::OK,
+        r#"{"id":1,"reject":true,"max_speed":30.0,"repeat_threshold":2,"history_size":10}"#
+    );
+
+    // Retry a wrong answer from the same place (must not be flagged)
+    for _ in 0..2 {
+        do_test!(
+            app,
+            "",
+            Method::POST,
+            &format!("/api/users/{id}/advance"),
+            r#"{"password":"Test password","latitude":45.74851,"longitude":4.84668,"answer":"yellow"}"#,
+            StatusCode::NOT_ACCEPTABLE,
+            error_json("wrong_answer", "the answer is wrong")
+        );
+    }
+    let report = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/anticheat/report",
+        "",
+        StatusCode::OK,
+        "["
+    );
+    assert!(!report.contains(r#""kind":"RepeatedCoordinates""#));
+
+    // Replay the same coordinates with the right answer a few minutes later (must be rejected)
+    {
+        use crate::schema::positions::dsl;
+        diesel::update(dsl::positions.filter(dsl::user_id.eq(id)))
+            .set(dsl::recorded_at.eq(dsl::recorded_at - 600_000))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+    }
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.74851,"longitude":4.84668,"answer":"blue"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json("suspicious", "the reported position is not plausible")
+    );
+
+    // Check that the player did not advance
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{id}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(r#"{{"id":{id1},"rank":1"#)
+    );
+
+    // Check that the rejection is in the report
+    let report = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/anticheat/report",
+        "",
+        StatusCode::OK,
+        "["
+    );
+    assert!(report.contains(r#""kind":"RepeatedCoordinates","details":"same coordinates reported 2 times in a row","rejected":true"#));
+
+    // Restore the default settings
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        "/api/anticheat/settings",
+        r#"{"reject":false,"max_speed":30.0,"repeat_threshold":3,"history_size":10}"#,
+        StatusCode::OK,
+        r#"{"id":1,"reject":false"#
+    );
+
+    // Clear the report
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/anticheat/report",
+        "",
+        StatusCode::OK,
+        "Report cleared"
+    );
+
+    // Delete all the users
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/users",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+
+    // Delete all the steps
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/steps",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+}
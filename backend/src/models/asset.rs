@@ -0,0 +1,534 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::remove_file,
+    path::Path,
+    sync::{Condvar, Mutex, MutexGuard},
+};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{AppConfig, Authenticated},
+    db::DbConnection,
+    errors::ServerError,
+    models::{
+        step::{self, receive_upload, Upload, VersionQuery},
+        version,
+    },
+    schema::{published_assets, step_assets},
+    sniff::{sniff, sniff_document, MediaType},
+    storage::{serve, Storage},
+    utils::now,
+};
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+pub(crate) const ASSETS_DIR: &str = "assets";
+// The files being attached to a step or to a version, and the ones being removed. They are only
+// known to this process: instances sharing a storage do not see each other's.
+static FILES: Mutex<Files> = Mutex::new(Files {
+    attaching: BTreeMap::new(),
+    removing: BTreeSet::new(),
+});
+// Signaled when a file is no longer being removed
+static REMOVED: Condvar = Condvar::new();
+// The file of an asset never changes, a new asset is created instead
+const ASSETS_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum AssetKind {
+    Image,
+    Audio,
+    Video,
+    Document,
+}
+
+impl AssetKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AssetKind::Image => "Image",
+            AssetKind::Audio => "Audio",
+            AssetKind::Video => "Video",
+            AssetKind::Document => "Document",
+        }
+    }
+
+    fn of(mime: &str) -> Self {
+        match mime.split('/').next() {
+            Some("image") => AssetKind::Image,
+            Some("audio") => AssetKind::Audio,
+            Some("video") => AssetKind::Video,
+            _ => AssetKind::Document,
+        }
+    }
+}
+
+impl<DB> ToSql<Text, DB> for AssetKind
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for AssetKind
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        match String::from_sql(bytes)?.as_str() {
+            "Image" => Ok(AssetKind::Image),
+            "Audio" => Ok(AssetKind::Audio),
+            "Video" => Ok(AssetKind::Video),
+            "Document" => Ok(AssetKind::Document),
+            v => Err(format!("unknown asset kind: {}", v).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, ToSchema)]
+#[diesel(table_name = step_assets)]
+pub struct Asset {
+    pub id: i32,
+    pub step_id: i32,
+    pub position: i32,
+    pub kind: AssetKind,
+    pub caption: String,
+    pub mime: String,
+    pub extension: String,
+    pub checksum: String,
+    pub size: i64,
+    pub uploaded_at: i64,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = step_assets)]
+pub struct NewAsset {
+    pub step_id: i32,
+    pub position: i32,
+    pub kind: AssetKind,
+    pub caption: String,
+    pub mime: String,
+    pub extension: String,
+    pub checksum: String,
+    pub size: i64,
+    pub uploaded_at: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AssetQuery {
+    #[serde(default)]
+    caption: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AssetUpdate {
+    caption: Option<String>,
+    position: Option<i32>,
+}
+
+// Asset files are named after the checksum of their content, so that a file attached to several
+// steps is only stored once
+fn file_key(checksum: &str, extension: &str) -> String {
+    format!("{ASSETS_DIR}/{checksum}.{extension}")
+}
+
+fn asset_key(a: &Asset) -> String {
+    file_key(&a.checksum, &a.extension)
+}
+
+struct Files {
+    // With the number of uploads or publications attaching them: they are not removed meanwhile,
+    // even though nothing uses them yet
+    attaching: BTreeMap<String, usize>,
+    removing: BTreeSet<String>,
+}
+
+// The lock is only held to look at the files, never while using the storage or the database
+fn files() -> MutexGuard<'static, Files> {
+    FILES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// A file being attached, from when it is stored until it is recorded
+pub(crate) struct Attaching(String);
+
+impl Attaching {
+    // Wait until the file is not being removed, so that nobody finds a file that is about to go
+    // away. This blocks, it is only called from the blocking thread pool.
+    pub(crate) fn new(key: &str) -> Self {
+        let mut files = files();
+        while files.removing.contains(key) {
+            files = REMOVED.wait(files).unwrap_or_else(|e| e.into_inner());
+        }
+        *files.attaching.entry(key.to_string()).or_default() += 1;
+        Attaching(key.to_string())
+    }
+}
+
+impl Drop for Attaching {
+    fn drop(&mut self) {
+        let mut files = files();
+        if let Some(count) = files.attaching.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                files.attaching.remove(&self.0);
+            }
+        }
+    }
+}
+
+// Remove a file once nothing uses it and nobody is attaching it. The file is marked as being
+// removed before checking that nothing uses it, nobody attaches it until it is gone.
+pub(crate) fn release_file(
+    storage: &dyn Storage,
+    key: &str,
+    in_use: impl FnOnce() -> QueryResult<bool>,
+) -> Result<(), ServerError> {
+    {
+        let mut files = files();
+        if files.attaching.contains_key(key) || !files.removing.insert(key.to_string()) {
+            return Ok(());
+        }
+    }
+    let used = in_use();
+    if let Ok(false) = used {
+        let _ = storage.delete(key);
+    }
+    files().removing.remove(key);
+    REMOVED.notify_all();
+    used?;
+    Ok(())
+}
+
+// Tell if an asset of a step or of a published version uses the file with the given checksum
+pub(crate) fn is_used(conn: &mut DbConnection, sum: &str) -> QueryResult<bool> {
+    let drafts = step_assets::table.filter(step_assets::checksum.eq(sum));
+    let published = published_assets::table.filter(published_assets::checksum.eq(sum));
+    diesel::select(diesel::dsl::exists(drafts).or(diesel::dsl::exists(published))).get_result(conn)
+}
+
+// Remove the files of assets whose deletion is committed, unless other assets use them
+pub(crate) fn release(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    assets: &[Asset],
+) -> Result<(), ServerError> {
+    for a in assets {
+        release_file(storage, &asset_key(a), || is_used(conn, &a.checksum))?;
+    }
+    Ok(())
+}
+
+// Assets may be any allowed media or a document
+fn recognize(head: &[u8]) -> Option<MediaType> {
+    sniff(head).or_else(|| sniff_document(head))
+}
+
+fn find(conn: &mut DbConnection, sid: i32, aid: i32) -> Result<Asset, ServerError> {
+    use crate::schema::step_assets::dsl::*;
+    Ok(step_assets
+        .filter(id.eq(aid))
+        .filter(step_id.eq(sid))
+        .first::<Asset>(conn)?)
+}
+
+// An asset of a step, in the draft or in a version
+fn find_in(
+    conn: &mut DbConnection,
+    version: Option<i32>,
+    sid: i32,
+    aid: i32,
+) -> Result<Asset, ServerError> {
+    match version {
+        Some(vid) => Ok(version::asset_of(conn, vid, sid, aid)?),
+        None => find(conn, sid, aid),
+    }
+}
+
+// Number the assets of a step from 1, optionally moving one of them to a new position first
+fn renumber(
+    conn: &mut DbConnection,
+    sid: i32,
+    moved: Option<(i32, i32)>,
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::step_assets::dsl::*;
+    let mut ids = step_assets
+        .filter(step_id.eq(sid))
+        .order((position.asc(), id.asc()))
+        .select(id)
+        .load::<i32>(conn)?;
+    if let Some((aid, new_position)) = moved {
+        ids.retain(|i| *i != aid);
+        let index = (new_position - 1).clamp(0, ids.len() as i32) as usize;
+        ids.insert(index, aid);
+    }
+    for (i, aid) in ids.into_iter().enumerate() {
+        diesel::update(step_assets.find(aid))
+            .set(position.eq(i as i32 + 1))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+// The assets of a step, in their order
+pub fn find_all(conn: &mut DbConnection, sid: i32) -> QueryResult<Vec<Asset>> {
+    use crate::schema::step_assets::dsl::*;
+    step_assets
+        .filter(step_id.eq(sid))
+        .order(position.asc())
+        .load::<Asset>(conn)
+}
+
+#[utoipa::path(
+    summary = "List the files attached to a step",
+    params(VersionQuery),
+    responses((status = 200, body = Vec<Asset>)),
+)]
+#[get("/{sid}/assets")]
+pub async fn read_all(
+    pool: web::Data<DbPool>,
+    sid: web::Path<i32>,
+    query: web::Query<VersionQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let (sid, version) = (*sid, query.version);
+    let assets = crate::db::run(&pool, move |conn| match version {
+        Some(vid) => version::assets_of(conn, vid, sid),
+        None => find_all(conn, sid),
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(assets))
+}
+
+// Move an uploaded file to the storage, unless an identical file is already there
+fn store_file(storage: &dyn Storage, key: &str, upload: &Upload) -> std::io::Result<()> {
+    if storage.exists(key)? {
+        return Ok(());
+    }
+    storage.put_file(
+        key,
+        Path::new(&upload.temp_filename),
+        upload.media_type.mime,
+    )
+}
+
+// Record an uploaded file, already in the storage, as the last asset of a step
+fn insert(
+    conn: &mut DbConnection,
+    sid: i32,
+    new_caption: String,
+    upload: &Upload,
+) -> Result<Asset, ServerError> {
+    conn.transaction(|conn| {
+        use crate::schema::step_assets::dsl::*;
+        let last = step_assets
+            .filter(step_id.eq(sid))
+            .select(diesel::dsl::max(position))
+            .first::<Option<i32>>(conn)?;
+        let a = diesel::insert_into(step_assets)
+            .values(&NewAsset {
+                step_id: sid,
+                position: last.unwrap_or(0) + 1,
+                kind: AssetKind::of(upload.media_type.mime),
+                caption: new_caption,
+                mime: upload.media_type.mime.to_string(),
+                extension: upload.media_type.extension.to_string(),
+                checksum: upload.checksum.clone(),
+                size: upload.size as i64,
+                uploaded_at: now(),
+            })
+            .get_result::<Asset>(conn)?;
+        Ok(a)
+    })
+}
+
+// Attach a file to a step, after the existing ones. Its kind and type are detected from the content.
+#[utoipa::path(
+    summary = "Attach a file to a step",
+    params(AssetQuery),
+    description = "Images, sounds, videos and PDF documents are allowed, found from the content",
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses((status = 201, body = Asset)),
+    security(("token" = [])),
+)]
+#[post("/{sid}/assets")]
+pub async fn create(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    sid: web::Path<i32>,
+    query: web::Query<AssetQuery>,
+    body: web::Payload,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let sid = *sid;
+    crate::db::run(&pool, move |conn| step::find(conn, sid)).await?;
+    let upload =
+        std::sync::Arc::new(receive_upload(body, app_config.media_max_size, recognize).await?);
+    let new_caption = query.into_inner().caption.trim().to_string();
+    let checksum = upload.checksum.clone();
+    let key = file_key(&checksum, upload.media_type.extension);
+
+    // The file is stored before the asset is recorded, so that no transaction waits for the storage
+    let stored = {
+        let (storage, key, upload) = (app_config.storage.clone(), key.clone(), upload.clone());
+        web::block(move || {
+            let attaching = Attaching::new(&key);
+            store_file(storage.as_ref(), &key, &upload).map(|_| attaching)
+        })
+        .await
+    };
+    let created = match stored {
+        Ok(Ok(attaching)) => {
+            let upload = upload.clone();
+            let created =
+                crate::db::run(&pool, move |conn| insert(conn, sid, new_caption, &upload)).await;
+            drop(attaching);
+            created
+        }
+        Ok(Err(e)) => Err(e.into()),
+        Err(e) => Err(e.into()),
+    };
+    let _ = remove_file(&upload.temp_filename);
+
+    // The file is removed again if the asset could not be recorded, unless it is used
+    if created.is_err() {
+        let storage = app_config.storage.clone();
+        let _ = crate::db::run(&pool, move |conn| {
+            release_file(storage.as_ref(), &key, || is_used(conn, &checksum))
+        })
+        .await;
+    }
+    Ok(HttpResponse::Created().json(created?))
+}
+
+#[utoipa::path(
+    summary = "Read a file attached to a step",
+    params(VersionQuery),
+    responses((status = 200, body = Asset)),
+)]
+#[get("/{sid}/assets/{aid}")]
+pub async fn read(
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, i32)>,
+    query: web::Query<VersionQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let (sid, aid) = path.into_inner();
+    let a = crate::db::run(&pool, move |conn| find_in(conn, query.version, sid, aid)).await?;
+    Ok(HttpResponse::Ok().json(a))
+}
+
+#[utoipa::path(
+    summary = "Download a file attached to a step",
+    params(VersionQuery),
+    responses(
+        (status = 200, body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 307, description = "The file is served by the storage"),
+    ),
+)]
+#[get("/{sid}/assets/{aid}/file")]
+pub async fn retrieve_file(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    path: web::Path<(i32, i32)>,
+    query: web::Query<VersionQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let (sid, aid) = path.into_inner();
+    let a = crate::db::run(&pool, move |conn| find_in(conn, query.version, sid, aid)).await?;
+    serve(
+        &req,
+        app_config.storage.as_ref(),
+        &asset_key(&a),
+        &a.mime,
+        ASSETS_CACHE_CONTROL,
+    )
+}
+
+// Change the caption or the position of an asset, the other assets of the step are shifted accordingly
+fn save(
+    conn: &mut DbConnection,
+    sid: i32,
+    aid: i32,
+    o: &AssetUpdate,
+) -> Result<Asset, ServerError> {
+    conn.transaction(|conn| {
+        use crate::schema::step_assets::dsl::*;
+        find(conn, sid, aid)?;
+        if let Some(c) = &o.caption {
+            diesel::update(step_assets.find(aid))
+                .set(caption.eq(c.trim()))
+                .execute(conn)?;
+        }
+        if let Some(p) = o.position {
+            renumber(conn, sid, Some((aid, p)))?;
+        }
+        find(conn, sid, aid)
+    })
+}
+
+#[utoipa::path(
+    summary = "Change the caption or the position of a file attached to a step",
+    responses((status = 200, body = Asset)),
+    security(("token" = [])),
+)]
+#[put("/{sid}/assets/{aid}")]
+pub async fn update(
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, i32)>,
+    o: web::Json<AssetUpdate>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let (sid, aid) = path.into_inner();
+    let a = crate::db::run(&pool, move |conn| save(conn, sid, aid, &o)).await?;
+    Ok(HttpResponse::Ok().json(a))
+}
+
+// Delete an asset, its file too if no other asset uses it
+fn remove(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    sid: i32,
+    aid: i32,
+) -> Result<(), ServerError> {
+    let a = conn.transaction(|conn| {
+        use crate::schema::step_assets::dsl::*;
+        let a = find(conn, sid, aid)?;
+        diesel::delete(step_assets.find(aid)).execute(conn)?;
+        renumber(conn, sid, None)?;
+        Ok::<_, ServerError>(a)
+    })?;
+    release(conn, storage, &[a])
+}
+
+#[utoipa::path(
+    summary = "Remove a file attached to a step",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
+#[delete("/{sid}/assets/{aid}")]
+pub async fn delete(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    path: web::Path<(i32, i32)>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let (sid, aid) = path.into_inner();
+    let storage = app_config.storage.clone();
+    crate::db::run(&pool, move |conn| remove(conn, storage.as_ref(), sid, aid)).await?;
+    Ok(HttpResponse::Ok().body(format!("Deleted object with id: {}", aid)))
+}
@@ -0,0 +1,269 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+
+pub async fn asset_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{header, Method, StatusCode},
+        test,
+    };
+    use std::path::Path;
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create a step
+    let sid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+
+    // The step has no assets yet
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{sid}/assets"),
+        "",
+        StatusCode::OK,
+        "[]"
+    );
+
+    // Attach a file without a token (must fail)
+    let img_body = std::fs::read("test_img.jpg").unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{sid}/assets"))
+        .method(Method::POST)
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Attach a file to a non existing step (must fail)
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{}/assets", sid + 1))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Attach a file that is not allowed (must fail)
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{sid}/assets"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload("<html></html>")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // Attach an image, a sound and a document
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    let document_body = b"%PDF-1.7\n%%EOF\n".to_vec();
+    let id_regex = regex::Regex::new(r#""id":(\d+)"#).unwrap();
+    let mut ids = Vec::new();
+    for (body, caption) in [
+        (img_body.clone(), "The%20statue"),
+        (sound_body.clone(), "A%20song"),
+        (document_body.clone(), ""),
+    ] {
+        let req =
+            test::TestRequest::with_uri(&format!("/api/steps/{sid}/assets?caption={caption}"))
+                .method(Method::POST)
+                .insert_header(("Authorization", "Bearer 0101"))
+                .set_payload(body)
+                .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = test::read_body(resp).await;
+        let id: i32 = id_regex
+            .captures(std::str::from_utf8(&body).unwrap())
+            .unwrap()[1]
+            .parse()
+            .unwrap();
+        ids.push(id);
+    }
+    let (a1, a2, a3) = (ids[0], ids[1], ids[2]);
+
+    // Get the metadata, with the kind and the SHA-256 checksum of the content
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{sid}/assets/{a2}"),
+        "",
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{a2},"step_id":{sid},"position":2,"kind":"Audio","caption":"A song","mime":"audio/mpeg","extension":"mp3","checksum":"affa2f429722c547275b64721fec3ccf0e72c896d52a2a05207212ea51b5817b","size":14,"uploaded_at":"#
+        )
+    );
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{sid}/assets/{a3}"),
+        "",
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{a3},"step_id":{sid},"position":3,"kind":"Document","caption":"","mime":"application/pdf","extension":"pdf","checksum":"1e7313ace78f0fb481a486939b4885902663102818090805515553d84e0bbfd3","size":15,"uploaded_at":"#
+        )
+    );
+
+    // Get an asset through another step (must fail)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{}/assets/{a2}", sid + 1),
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "Item not found")
+    );
+
+    // Get the file of the sound, with its stored type
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/{sid}/assets/{a2}/file")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "audio/mpeg"
+    );
+    assert_eq!(
+        resp.headers().get(header::CACHE_CONTROL).unwrap(),
+        "public, max-age=31536000, immutable"
+    );
+    let body = test::read_body(resp).await;
+    assert_eq!(body, sound_body);
+
+    // Move the document first and rename the image
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        &format!("/api/steps/{sid}/assets/{a3}"),
+        r#"{"position":1}"#,
+        StatusCode::OK,
+        format!(r#"{{"id":{a3},"step_id":{sid},"position":1,"kind":"Document""#)
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        &format!("/api/steps/{sid}/assets/{a1}"),
+        r#"{"caption":"  The big statue  "}"#,
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{a1},"step_id":{sid},"position":2,"kind":"Image","caption":"The big statue","mime":"image/jpeg","extension":"jpg""#
+        )
+    );
+
+    // Move the document beyond the end, it must become the last one
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        &format!("/api/steps/{sid}/assets/{a3}"),
+        r#"{"position":10}"#,
+        StatusCode::OK,
+        format!(r#"{{"id":{a3},"step_id":{sid},"position":3,"#)
+    );
+    let list = do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{sid}/assets"),
+        "",
+        StatusCode::OK,
+        format!(r#"[{{"id":{a1},"step_id":{sid},"position":1,"#)
+    );
+    assert!(list.contains(&format!(r#"{{"id":{a2},"step_id":{sid},"position":2,"#)));
+
+    // Delete the image, the other ones must move up
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{sid}/assets/{a1}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {a1}")
+    );
+    assert!(!Path::new(&format!("data/items/assets/{a1}.jpg")).exists());
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{sid}/assets"),
+        "",
+        StatusCode::OK,
+        format!(r#"[{{"id":{a2},"step_id":{sid},"position":1,"#)
+    );
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/{sid}/assets/{a1}/file")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Delete the step, its assets must be gone too
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{sid}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {sid}")
+    );
+    assert!(!Path::new(&format!("data/items/assets/{a2}.mp3")).exists());
+    assert!(!Path::new(&format!("data/items/assets/{a3}.pdf")).exists());
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{sid}/assets"),
+        "",
+        StatusCode::OK,
+        "[]"
+    );
+
+    // Delete all the steps, the files of their assets must be gone too
+    let sid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{sid}/assets"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let sound_file =
+        "data/items/assets/affa2f429722c547275b64721fec3ccf0e72c896d52a2a05207212ea51b5817b.mp3";
+    assert!(Path::new(sound_file).exists());
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/steps",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+    assert!(!Path::new(sound_file).exists());
+}
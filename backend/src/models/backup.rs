@@ -0,0 +1,505 @@
+use actix_files::NamedFile;
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    post, web, HttpRequest, HttpResponse,
+};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::sql_types::Text;
+use diesel_migrations::MigrationHarness;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{self, create_dir_all, remove_file, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{AppConfig, Authenticated},
+    db::DbConnection,
+    errors::ServerError,
+    models::{
+        asset::ASSETS_DIR,
+        photo::PHOTOS_PATH,
+        step::{receive_upload, IMAGES_DIR, MEDIAS_DIR, MEDIAS_LOW_DIR, UPLOADS_PATH},
+        version::PUBLISHED_DIR,
+    },
+    sniff::{sniff, MediaType},
+    storage::{FileStorage, Storage},
+    utils::{now, random_string},
+};
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+// Directories of the storage that are saved, the photos being saved apart as they stay on the local disk
+const STORAGE_DIRS: [&str; 5] = [
+    IMAGES_DIR,
+    MEDIAS_DIR,
+    MEDIAS_LOW_DIR,
+    ASSETS_DIR,
+    PUBLISHED_DIR,
+];
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const DATABASE_ENTRY: &str = "db.sqlite";
+const ITEMS_ENTRY: &str = "items/";
+const PHOTOS_ENTRY: &str = "photos/";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    // Latest migration applied to the saved database
+    pub schema_version: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Backup {
+    pub name: String,
+    pub size: u64,
+    pub created_at: i64,
+}
+
+// Backups are named after their creation time, so that they sort by age
+fn backup_name(created_at: i64) -> String {
+    format!("pistou-{created_at}.tar")
+}
+
+fn parse_backup_name(name: &str) -> Option<i64> {
+    name.strip_prefix("pistou-")?
+        .strip_suffix(".tar")
+        .filter(|t| !t.is_empty() && t.bytes().all(|b| b.is_ascii_digit()))?
+        .parse()
+        .ok()
+}
+
+// Backups are made from a SQLite database only, Postgres has its own tools
+fn sqlite(conn: &mut DbConnection) -> Result<&mut SqliteConnection, ServerError> {
+    match conn {
+        DbConnection::Sqlite(conn) => Ok(conn),
+        DbConnection::Pg(_) => Err(ServerError::BadRequest(
+            "backups are only made of SQLite databases, use pg_dump for Postgres".to_string(),
+        )),
+    }
+}
+
+fn schema_version(conn: &mut SqliteConnection) -> Result<String, ServerError> {
+    conn.applied_migrations()
+        .map_err(|e| ServerError::DieselDatabaseError(e.to_string()))?
+        .first()
+        .map(|v| v.to_string())
+        .ok_or(ServerError::DieselDatabaseError(
+            "the database has no schema".to_string(),
+        ))
+}
+
+// Take a consistent snapshot of the database while the server runs, and save it with the files of
+// the steps and the photos as one archive in the backups directory. The oldest backups are then
+// removed, so that only the given number of them is kept (0 keeps them all).
+pub fn create_backup(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    dir: &str,
+    retention: usize,
+) -> Result<Backup, ServerError> {
+    let conn = sqlite(conn)?;
+    create_dir_all(dir)?;
+    let created_at = now();
+    let name = backup_name(created_at);
+    let snapshot = format!("{dir}/{}.sqlite.tmp", random_string());
+    let archive = format!("{dir}/{}.tar.tmp", random_string());
+    let result = (|| {
+        let manifest = Manifest {
+            schema_version: schema_version(conn)?,
+            created_at,
+        };
+        diesel::sql_query("VACUUM INTO ?")
+            .bind::<Text, _>(&snapshot)
+            .execute(conn)?;
+
+        let mut builder = tar::Builder::new(File::create(&archive)?);
+        let manifest = serde_json::to_vec(&manifest).map_err(std::io::Error::other)?;
+        append_data(&mut builder, MANIFEST_ENTRY, &manifest)?;
+        builder.append_path_with_name(&snapshot, DATABASE_ENTRY)?;
+        // Files removed since the snapshot are not used by it anymore
+        let mut append_objects = |storage: &dyn Storage, prefix: &str, entry: &str| {
+            for object in storage.list(prefix)? {
+                let name = format!("{entry}{}", object.key);
+                match storage.local_path(&object.key) {
+                    Some(path) => builder.append_path_with_name(path, name)?,
+                    None => match storage.get(&object.key) {
+                        Ok(data) => append_data(&mut builder, &name, &data)?,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    },
+                }
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        for dir in STORAGE_DIRS {
+            append_objects(storage, &format!("{dir}/"), ITEMS_ENTRY)?;
+        }
+        append_objects(&FileStorage::new(PHOTOS_PATH), "", PHOTOS_ENTRY)?;
+        builder.into_inner()?.sync_all()?;
+        fs::rename(&archive, format!("{dir}/{name}"))?;
+        Ok::<_, ServerError>(())
+    })();
+    let _ = remove_file(&snapshot);
+    if let Err(e) = result {
+        let _ = remove_file(&archive);
+        return Err(e);
+    }
+    prune_backups(dir, retention)?;
+    Ok(Backup {
+        size: fs::metadata(format!("{dir}/{name}"))?.len(),
+        name,
+        created_at,
+    })
+}
+
+fn append_data(builder: &mut tar::Builder<File>, name: &str, data: &[u8]) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime((now() / 1000) as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)
+}
+
+// The backups of the directory, oldest first
+pub fn list_backups(dir: &str) -> Result<Vec<Backup>, ServerError> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut backups = Vec::new();
+    for entry in entries.flatten() {
+        let Some(name) = entry.file_name().to_str().map(|n| n.to_string()) else {
+            continue;
+        };
+        if let Some(created_at) = parse_backup_name(&name) {
+            backups.push(Backup {
+                size: entry.metadata()?.len(),
+                name,
+                created_at,
+            });
+        }
+    }
+    backups.sort_by_key(|b| b.created_at);
+    Ok(backups)
+}
+
+fn prune_backups(dir: &str, retention: usize) -> Result<(), ServerError> {
+    if retention == 0 {
+        return Ok(());
+    }
+    let backups = list_backups(dir)?;
+    for b in backups.iter().take(backups.len().saturating_sub(retention)) {
+        remove_file(format!("{dir}/{}", b.name))?;
+    }
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct TableName {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+#[derive(QueryableByName)]
+struct Violation {
+    #[diesel(sql_type = Text)]
+    table: String,
+}
+
+#[derive(QueryableByName)]
+struct Version {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+// Restore a backup archive over the running server. The archive must come from a database of the
+// same schema version: its rows replace all the rows of the database in one transaction, then its
+// files are put back. The images, medias and photos that are not in the backup are removed, as
+// they are named after ids the restored rows may use. The other files are named after their
+// content and are left for the garbage collection.
+pub fn restore_backup(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    archive: &Path,
+) -> Result<(), ServerError> {
+    let conn = sqlite(conn)?;
+    let invalid = |m: &str| ServerError::BadRequest(format!("invalid backup: {m}"));
+
+    // Find the manifest and extract the database
+    create_dir_all(UPLOADS_PATH)?;
+    let snapshot = PathBuf::from(format!("{UPLOADS_PATH}/{}.sqlite.tmp", random_string()));
+    let mut manifest: Option<Manifest> = None;
+    let mut extracted = false;
+    let result = (|| {
+        for entry in tar::Archive::new(File::open(archive)?)
+            .entries()
+            .map_err(|_| invalid("not an archive"))?
+        {
+            let mut entry = entry.map_err(|_| invalid("not an archive"))?;
+            match entry.path()?.to_str() {
+                Some(MANIFEST_ENTRY) => {
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data)?;
+                    manifest = Some(
+                        serde_json::from_slice(&data)
+                            .map_err(|_| invalid("unreadable manifest"))?,
+                    );
+                }
+                Some(DATABASE_ENTRY) => {
+                    entry.unpack(&snapshot)?;
+                    extracted = true;
+                }
+                _ => {}
+            }
+        }
+        let manifest = manifest.ok_or_else(|| invalid("no manifest"))?;
+        if !extracted {
+            return Err(invalid("no database"));
+        }
+        let expected = schema_version(conn)?;
+        if manifest.schema_version != expected {
+            return Err(ServerError::BadRequest(format!(
+                "the backup has the schema version {}, the database has {}",
+                manifest.schema_version, expected
+            )));
+        }
+        replace_rows(conn, &snapshot, &expected)
+    })();
+    let _ = remove_file(&snapshot);
+    result?;
+
+    // Put the files back
+    let photos = FileStorage::new(PHOTOS_PATH);
+    let mut restored_items = HashSet::new();
+    let mut restored_photos = HashSet::new();
+    for entry in tar::Archive::new(File::open(archive)?).entries()? {
+        let mut entry = entry?;
+        let Some(path) = entry.path()?.to_str().map(|p| p.to_string()) else {
+            continue;
+        };
+        let (storage, key, restored) = if let Some(key) = path.strip_prefix(ITEMS_ENTRY) {
+            if !STORAGE_DIRS
+                .iter()
+                .any(|d| key.rsplit_once('/').map(|(d, _)| d) == Some(*d))
+            {
+                continue;
+            }
+            (storage, key, &mut restored_items)
+        } else if let Some(key) = path.strip_prefix(PHOTOS_ENTRY) {
+            (&photos as &dyn Storage, key, &mut restored_photos)
+        } else {
+            continue;
+        };
+        if key
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        let content_type = sniff(&data)
+            .map(|t| t.mime)
+            .unwrap_or("application/octet-stream");
+        storage.put(key, &data, content_type)?;
+        restored.insert(key.to_string());
+    }
+    for dir in [IMAGES_DIR, MEDIAS_DIR, MEDIAS_LOW_DIR] {
+        remove_others(storage, &format!("{dir}/"), &restored_items)?;
+    }
+    remove_others(&photos, "", &restored_photos)?;
+    Ok(())
+}
+
+// Remove the objects of a directory that were not restored
+fn remove_others(
+    storage: &dyn Storage,
+    prefix: &str,
+    restored: &HashSet<String>,
+) -> std::io::Result<()> {
+    for object in storage.list(prefix)? {
+        if !restored.contains(&object.key) {
+            storage.delete(&object.key)?;
+        }
+    }
+    Ok(())
+}
+
+// Replace the rows of every table by the ones of another database of the same schema
+fn replace_rows(
+    conn: &mut SqliteConnection,
+    snapshot: &Path,
+    version: &str,
+) -> Result<(), ServerError> {
+    diesel::sql_query("ATTACH DATABASE ? AS backup")
+        .bind::<Text, _>(snapshot.to_string_lossy())
+        .execute(conn)?;
+    let result = (|| {
+        let saved = diesel::sql_query(
+            "SELECT version FROM backup.__diesel_schema_migrations ORDER BY version DESC LIMIT 1",
+        )
+        .get_result::<Version>(conn)
+        .map_err(|_| ServerError::BadRequest("invalid backup: unreadable database".to_string()))?;
+        if saved.version != version {
+            return Err(ServerError::BadRequest(
+                "invalid backup: the database does not match the manifest".to_string(),
+            ));
+        }
+        let tables = diesel::sql_query(
+            "SELECT name FROM main.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != '__diesel_schema_migrations'",
+        )
+        .load::<TableName>(conn)?;
+        // The rows reference each other, they are only checked once all are there. SQLite checks
+        // the RESTRICT constraints at once even when they are deferred, so the constraints are
+        // turned off, which can only be done outside of a transaction.
+        diesel::sql_query("PRAGMA foreign_keys = OFF").execute(conn)?;
+        let replaced = conn.immediate_transaction(|conn| {
+            for t in &tables {
+                let name = t.name.replace('"', "\"\"");
+                diesel::sql_query(format!("DELETE FROM main.\"{name}\"")).execute(conn)?;
+                diesel::sql_query(format!(
+                    "INSERT INTO main.\"{name}\" SELECT * FROM backup.\"{name}\""
+                ))
+                .execute(conn)?;
+            }
+            let violations =
+                diesel::sql_query("PRAGMA main.foreign_key_check").load::<Violation>(conn)?;
+            if let Some(v) = violations.first() {
+                return Err(ServerError::BadRequest(format!(
+                    "invalid backup: rows of {} refer to missing rows",
+                    v.table
+                )));
+            }
+            Ok::<_, ServerError>(())
+        });
+        diesel::sql_query("PRAGMA foreign_keys = ON").execute(conn)?;
+        replaced
+    })();
+    diesel::sql_query("DETACH DATABASE backup").execute(conn)?;
+    result
+}
+
+// Back up the database every interval in a background thread
+pub fn schedule_backups(
+    pool: DbPool,
+    storage: Arc<dyn Storage>,
+    dir: String,
+    retention: usize,
+    interval: Duration,
+) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let backup = pool
+            .get()
+            .map_err(ServerError::from)
+            .and_then(|mut conn| create_backup(&mut conn, storage.as_ref(), &dir, retention));
+        match backup {
+            Ok(b) => info!("Backup {} created ({} bytes)", b.name, b.size),
+            Err(e) => warn!("Backup failed: {}", e),
+        }
+    });
+}
+
+#[utoipa::path(
+    summary = "Back up the database and the files",
+    responses((status = 201, body = Backup)),
+    security(("token" = [])),
+)]
+#[post("")]
+pub async fn create(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let storage = app_config.storage.clone();
+    let (dir, retention) = (app_config.backups_path.clone(), app_config.backup_retention);
+    let backup = crate::db::run(&pool, move |conn| {
+        create_backup(conn, storage.as_ref(), &dir, retention)
+    })
+    .await?;
+    Ok(HttpResponse::Created().json(backup))
+}
+
+#[utoipa::path(
+    summary = "List the backups",
+    responses((status = 200, body = Vec<Backup>)),
+    security(("token" = [])),
+)]
+#[get("")]
+pub async fn read_all(
+    app_config: web::Data<AppConfig>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let dir = app_config.backups_path.clone();
+    let backups = web::block(move || list_backups(&dir)).await??;
+    Ok(HttpResponse::Ok().json(backups))
+}
+
+#[utoipa::path(
+    summary = "Download a backup",
+    responses((status = 200, body = Vec<u8>, content_type = "application/x-tar")),
+    security(("token" = [])),
+)]
+#[get("/{name}")]
+pub async fn retrieve(
+    req: HttpRequest,
+    app_config: web::Data<AppConfig>,
+    name: web::Path<String>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    if parse_backup_name(&name).is_none() {
+        return Err(ServerError::NotFound("File does not exist".to_owned()));
+    }
+    let file = NamedFile::open(format!("{}/{}", app_config.backups_path, name))
+        .map_err(|_| ServerError::NotFound("File does not exist".to_owned()))?
+        .set_content_type("application/x-tar".parse().expect("valid mime"))
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(name.to_string())],
+        });
+    Ok(file.into_response(&req))
+}
+
+// Restore an uploaded backup archive
+#[utoipa::path(
+    summary = "Restore a backup",
+    description = "The backup must have been made by the same version of the schema",
+    request_body(content = Vec<u8>, content_type = "application/x-tar"),
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
+#[post("/restore")]
+pub async fn restore(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    body: web::Payload,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    // The archive is checked while it is read, rather than recognized from its first bytes
+    let upload = receive_upload(body, app_config.backup_max_size, |_| {
+        Some(MediaType {
+            mime: "application/x-tar",
+            extension: "tar",
+        })
+    })
+    .await?;
+    let storage = app_config.storage.clone();
+    let archive = upload.temp_filename.clone();
+    let restored = crate::db::run(&pool, move |conn| {
+        restore_backup(conn, storage.as_ref(), Path::new(&archive))
+    })
+    .await;
+    let _ = remove_file(&upload.temp_filename);
+    restored?;
+    Ok(HttpResponse::Ok().body("Backup restored"))
+}
@@ -0,0 +1,260 @@
+use crate::{auth::AppConfig, create_app, db::DbPool, tester::error_json};
+use actix_web::web::Data;
+use serde_json::Value;
+use std::io::Read;
+
+// A copy of a backup archive with another schema version in its manifest
+fn with_schema_version(archive: &[u8], version: &str) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in tar::Archive::new(archive).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        if path == "manifest.json" {
+            let mut manifest: Value = serde_json::from_slice(&data).unwrap();
+            manifest["schema_version"] = version.into();
+            data = serde_json::to_vec(&manifest).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, data.as_slice())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+pub async fn backup_test(pool: &DbPool, app_config: &Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    // Keep the backups of the tests apart, and only two of them
+    let backups_path = "data/test_backups";
+    let _ = std::fs::remove_dir_all(backups_path);
+    let mut config = AppConfig::new(app_config.bearer_token.clone(), true);
+    config.storage = app_config.storage.clone();
+    config.backups_path = backups_path.to_string();
+    config.backup_retention = 2;
+    let config = Data::new(config);
+    let mut app = test::init_service(create_app!(pool, &config)).await;
+
+    // Postgres databases are not backed up
+    if matches!(*pool.get().unwrap(), crate::db::DbConnection::Pg(_)) {
+        do_test!(
+            app,
+            "0101",
+            Method::POST,
+            "/api/backups",
+            "",
+            StatusCode::BAD_REQUEST,
+            error_json(
+                "bad_request",
+                "backups are only made of SQLite databases, use pg_dump for Postgres"
+            )
+        );
+        return;
+    }
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Create a step with an image and one without, publish them, and a player pinned to the version
+    let sid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let bare_sid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the grass?","answer":"green"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{sid}"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(std::fs::read("test_img.jpg").unwrap())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let vid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let uid = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"saved","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+
+    // Back up without a token (must fail), then with it
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        "/api/backups",
+        "",
+        StatusCode::UNAUTHORIZED,
+        ""
+    );
+    let req = test::TestRequest::post()
+        .uri("/api/backups")
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let backup: Value = test::read_body_json(resp).await;
+    let name = backup["name"].as_str().unwrap().to_string();
+    let req = test::TestRequest::with_uri("/api/backups")
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let backups: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(backups, vec![backup]);
+
+    // Download it, only backups can be downloaded
+    let download = |name: &str| {
+        test::TestRequest::with_uri(&format!("/api/backups/{name}"))
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request()
+    };
+    let resp = test::call_service(&app, download(&name)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let archive = test::read_body(resp).await.to_vec();
+    let resp = test::call_service(&app, download("db.sqlite")).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Delete the player and the image, and give an image to the other step, then restore the
+    // backup: the player and the image are back, the other step has no image again
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/users/{uid}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {uid}")
+    );
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/steps/images/{sid}"))
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{bare_sid}"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(std::fs::read("test_img.jpg").unwrap())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let restore = |body: Vec<u8>| {
+        test::TestRequest::post()
+            .uri("/api/backups/restore")
+            .insert_header(("Authorization", "Bearer 0101"))
+            .set_payload(body)
+            .to_request()
+    };
+    let resp = test::call_service(&app, restore(archive.clone())).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::with_uri(&format!("/api/users/{uid}"))
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let user: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(user["name"], "saved");
+    assert_eq!(user["version_id"], vid);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{sid}")).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{bare_sid}")).to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    // Restoring over players pinned to a version replaces them as well
+    let resp = test::call_service(&app, restore(archive.clone())).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Archives of another schema, or that are not archives, are refused
+    let resp = test::call_service(&app, restore(with_schema_version(&archive, "0.0.1"))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(test::read_body(resp).await.to_vec())
+        .unwrap()
+        .starts_with(
+            r#"{"code":"bad_request","message":"the backup has the schema version 0.0.1, the database has "#
+        ));
+    let resp = test::call_service(&app, restore(b"not an archive".to_vec())).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Archives larger than the limit are refused while they are received
+    let mut small_config = AppConfig::new(app_config.bearer_token.clone(), true);
+    small_config.backup_max_size = 8;
+    let small_app = test::init_service(create_app!(pool, &Data::new(small_config))).await;
+    let resp = test::call_service(&small_app, restore(archive.clone())).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Only the two latest backups are kept
+    let mut names = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::post()
+            .uri("/api/backups")
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request();
+        let backup: Value = test::call_and_read_body_json(&app, req).await;
+        names.push(backup["name"].clone());
+    }
+    let req = test::TestRequest::with_uri("/api/backups")
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let backups: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        backups
+            .iter()
+            .map(|b| b["name"].clone())
+            .collect::<Vec<_>>(),
+        names
+    );
+
+    // Delete everything
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/versions/{vid}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {vid}")
+    );
+    std::fs::remove_dir_all(backups_path).unwrap();
+}
@@ -1,10 +1,180 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+
+use actix_web::{
+    http::header::{self, ETag, EntityTag, Header, IfMatch},
+    FromRequest, HttpRequest,
+};
+use diesel::sql_types::Text;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::errors::ServerError;
+
+diesel::define_sql_function!(fn lower(x: Text) -> Text);
+
+// Paging, sorting and filtering of a list, from the query string of the request. The filters are
+// kept by name, each model tells which ones it understands.
+#[derive(Debug)]
+pub struct ListParams {
+    limit: Option<i64>,
+    offset: i64,
+    // Field to sort on, and whether in descending order
+    sort: Option<(String, bool)>,
+    filters: HashMap<String, String>,
+}
+
+impl ListParams {
+    pub fn parse(mut query: HashMap<String, String>) -> Result<Self, ServerError> {
+        let count = |name: &str, v: Option<String>| {
+            v.map(|v| {
+                v.parse::<i64>().ok().filter(|n| *n >= 0).ok_or_else(|| {
+                    ServerError::BadRequest(format!("{name} is not a positive number: {v}"))
+                })
+            })
+            .transpose()
+        };
+        Ok(ListParams {
+            limit: count("limit", query.remove("limit"))?,
+            offset: count("offset", query.remove("offset"))?.unwrap_or(0),
+            sort: query.remove("sort").map(|s| match s.strip_prefix('-') {
+                Some(field) => (field.to_string(), true),
+                None => (s, false),
+            }),
+            filters: query,
+        })
+    }
+
+    // Refuse the fields and the filters that the model does not know
+    pub fn check(&self, fields: &[&str], filters: &[&str]) -> Result<(), ServerError> {
+        if let Some((field, _)) = &self.sort {
+            if !fields.contains(&field.as_str()) {
+                return Err(ServerError::BadRequest(format!("cannot sort on {field}")));
+            }
+        }
+        match self.filters.keys().find(|f| !filters.contains(&f.as_str())) {
+            Some(f) => Err(ServerError::BadRequest(format!("unknown filter: {f}"))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn limit(&self) -> Option<i64> {
+        self.limit
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    pub fn sort(&self) -> Option<(&str, bool)> {
+        self.sort.as_ref().map(|(f, d)| (f.as_str(), *d))
+    }
+
+    // A LIKE pattern for the lowercased texts that contain the filter's value
+    pub fn pattern(&self, filter: &str) -> Option<String> {
+        self.filters.get(filter).map(|v| {
+            let escaped = v
+                .to_lowercase()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{escaped}%")
+        })
+    }
+
+    // A bound of a range filter
+    pub fn bound(&self, filter: &str) -> Result<Option<i32>, ServerError> {
+        self.filters
+            .get(filter)
+            .map(|v| {
+                v.parse::<i32>()
+                    .map_err(|_| ServerError::BadRequest(format!("{filter} is not a number: {v}")))
+            })
+            .transpose()
+    }
+}
+
+// The ETag of an object, from its revision
+pub fn etag(revision: i32) -> ETag {
+    ETag(EntityTag::new_strong(revision.to_string()))
+}
+
+// The If-Match precondition of an update, any revision will do without it
+pub struct Precondition(IfMatch);
+
+impl Precondition {
+    // Refuse to change an object that changed since the client read it
+    pub fn check(&self, revision: i32) -> Result<(), ServerError> {
+        match &self.0 {
+            IfMatch::Items(tags) if !tags.iter().any(|t| t.strong_eq(&etag(revision))) => {
+                Err(ServerError::PreconditionFailed(format!(
+                    "the object was changed since it was read, its ETag is now \"{revision}\""
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromRequest for Precondition {
+    type Error = ServerError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        if !req.headers().contains_key(header::IF_MATCH) {
+            return ready(Ok(Precondition(IfMatch::Any)));
+        }
+        ready(
+            IfMatch::parse(req).map(Precondition).map_err(|_| {
+                ServerError::BadRequest("the If-Match header is not valid".to_string())
+            }),
+        )
+    }
+}
+
+// Apply a JSON merge patch (RFC 7396): the members of the patch replace those of the document,
+// objects being merged and nulls removing the members
+fn merge_patch(document: &mut Value, patch: &Value) {
+    match (document, patch) {
+        (Value::Object(fields), Value::Object(members)) => {
+            for (name, value) in members {
+                if value.is_null() {
+                    fields.remove(name);
+                } else {
+                    merge_patch(fields.entry(name.clone()).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (document, Value::Object(_)) => {
+            *document = Value::Object(Default::default());
+            merge_patch(document, patch);
+        }
+        (document, _) => *document = patch.clone(),
+    }
+}
+
+// An object as changed by a JSON merge patch
+pub fn merged<T: Serialize + DeserializeOwned>(
+    current: &T,
+    patch: &Value,
+) -> Result<T, ServerError> {
+    let mut document = serde_json::to_value(current)
+        .map_err(|e| ServerError::BadRequest(format!("the object cannot be patched: {e}")))?;
+    merge_patch(&mut document, patch);
+    serde_json::from_value(document)
+        .map_err(|e| ServerError::BadRequest(format!("the patched object is not valid: {e}")))
+}
+
 #[macro_export]
 macro_rules! crud_use {
     () => {
-        use actix_web::{delete, get, post, put, web, HttpResponse};
+        $crate::crud_use!($crate::db::DbConnection);
+    };
+    ($connection:ty) => {
+        use actix_web::{delete, get, patch, post, put, web, HttpResponse};
         use diesel::prelude::*;
         use diesel::r2d2::ConnectionManager;
-        type DbPool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+        type DbPool = r2d2::Pool<ConnectionManager<$connection>>;
         use crate::auth::Authenticated;
     };
 }
@@ -12,37 +182,117 @@ macro_rules! crud_use {
 #[macro_export]
 macro_rules! crud_read {
     ($model:ty, $table:tt) => {
+        pub fn find(
+            conn: &mut $crate::db::DbConnection,
+            oid: i32,
+        ) -> Result<$model, diesel::result::Error> {
+            use crate::schema::$table::dsl::*;
+            $table.filter(id.eq(oid)).first::<$model>(conn)
+        }
+
+        // On one line, as rustfmt indents the attributes of a macro further on every run
+        #[utoipa::path(summary = "Read one", responses((status = 200, body = $model, headers(("ETag" = String)))))]
         #[get("/{oid}")]
         pub async fn read(
             pool: web::Data<DbPool>,
             oid: web::Path<i32>,
         ) -> Result<HttpResponse, ServerError> {
-            let mut conn = pool.get()?;
-            let object = web::block(move || {
-                use crate::schema::$table::dsl::*;
-                $table.filter(id.eq(*oid)).first::<$model>(&mut conn)
-            })
-            .await??;
-            Ok(HttpResponse::Ok().json(object))
+            let oid = *oid;
+            let object = $crate::db::run(&pool, move |conn| find(conn, oid)).await?;
+            Ok(HttpResponse::Ok()
+                .insert_header($crate::models::crud::etag(object.revision))
+                .json(object))
         }
     };
 }
 
+// List the objects a page at a time, sorted on one of the given fields (descending when prefixed
+// with a minus) and filtered on the fields whose text contains a value or whose number is within
+// <field>_min and <field>_max. The number of objects matching the filters goes in X-Total-Count.
 #[macro_export]
 macro_rules! crud_read_all {
-    ($model:ty, $table:tt) => {
+    ($model:ty, $table:tt, sort: [$($sort:ident),*], contains: [$($contains:ident),*], range: [$($range:ident),*]) => {
+        pub fn find_all(
+            conn: &mut $crate::db::DbConnection,
+            params: &$crate::models::crud::ListParams,
+        ) -> Result<(Vec<$model>, i64), ServerError> {
+            use crate::schema::$table::dsl::*;
+            params.check(
+                &[$(stringify!($sort)),*],
+                &[
+                    $(stringify!($contains),)*
+                    $(concat!(stringify!($range), "_min"), concat!(stringify!($range), "_max"),)*
+                ],
+            )?;
+            let filtered = || -> Result<_, ServerError> {
+                #[allow(unused_mut)]
+                let mut q = $table.into_boxed::<$crate::db::DbBackend>();
+                $(
+                    if let Some(p) = params.pattern(stringify!($contains)) {
+                        q = q.filter($crate::models::crud::lower($contains).like(p).escape('\\'));
+                    }
+                )*
+                $(
+                    if let Some(b) = params.bound(concat!(stringify!($range), "_min"))? {
+                        q = q.filter($range.ge(b));
+                    }
+                    if let Some(b) = params.bound(concat!(stringify!($range), "_max"))? {
+                        q = q.filter($range.le(b));
+                    }
+                )*
+                Ok(q)
+            };
+            let total = filtered()?.count().get_result::<i64>(conn)?;
+            let mut q = filtered()?;
+            if let Some((field, descending)) = params.sort() {
+                $(
+                    if field == stringify!($sort) {
+                        q = match descending {
+                            true => q.order($sort.desc()),
+                            false => q.order($sort.asc()),
+                        };
+                    }
+                )*
+            }
+            q = q.then_order_by(id.asc());
+            match (params.limit(), params.offset()) {
+                (None, 0) => {}
+                // SQLite only takes an offset after a limit
+                (l, o) => q = q.limit(l.unwrap_or(i64::MAX)).offset(o),
+            }
+            Ok((q.load::<$model>(conn)?, total))
+        }
+
+        #[utoipa::path(
+            summary = "List a page",
+            description = concat!(
+                "Sorted with `sort` on ",
+                $("`", stringify!($sort), "` ",)*
+                "(descending when prefixed with `-`), filtered on the text of ",
+                $("`", stringify!($contains), "` ",)*
+                "and on the numbers with ",
+                $("`", stringify!($range), "_min` `", stringify!($range), "_max` ",)*
+            ),
+            params(
+                ("limit" = Option<i64>, Query),
+                ("offset" = Option<i64>, Query),
+                ("sort" = Option<String>, Query),
+            ),
+            responses((status = 200, body = Vec<$model>, headers(("X-Total-Count" = i64)))),
+            security(("token" = [])),
+        )]
         #[get("")]
         pub async fn read_all(
             pool: web::Data<DbPool>,
+            query: web::Query<std::collections::HashMap<String, String>>,
             _: Authenticated,
         ) -> Result<HttpResponse, ServerError> {
-            let mut conn = pool.get()?;
-            let object = web::block(move || {
-                use crate::schema::$table::dsl::*;
-                $table.order(id.asc()).load::<$model>(&mut conn)
-            })
-            .await??;
-            Ok(HttpResponse::Ok().json(object))
+            let params = $crate::models::crud::ListParams::parse(query.into_inner())?;
+            let (objects, total) =
+                $crate::db::run(&pool, move |conn| find_all(conn, &params)).await?;
+            Ok(HttpResponse::Ok()
+                .insert_header(("X-Total-Count", total.to_string()))
+                .json(objects))
         }
     };
 }
@@ -111,25 +361,27 @@ macro_rules! crud_update {
 #[macro_export]
 macro_rules! crud_delete {
     ($model:ty, $table:tt) => {
+        pub fn remove(
+            conn: &mut $crate::db::DbConnection,
+            oid: i32,
+        ) -> Result<usize, diesel::result::Error> {
+            use crate::schema::$table::dsl::*;
+            let deleted = diesel::delete($table).filter(id.eq(oid)).execute(conn)?;
+            match deleted {
+                0 => Err(diesel::result::Error::NotFound),
+                _ => Ok(deleted),
+            }
+        }
+
+        #[utoipa::path(summary = "Delete one", responses((status = 200, body = String)), security(("token" = [])))]
         #[delete("/{oid}")]
         pub async fn delete(
             pool: web::Data<DbPool>,
             oid: web::Path<i32>,
             _: Authenticated,
         ) -> Result<HttpResponse, ServerError> {
-            let mut conn = pool.get()?;
             let oid = *oid;
-            web::block(move || {
-                use crate::schema::$table::dsl::*;
-                let deleted = diesel::delete($table)
-                    .filter(id.eq(oid))
-                    .execute(&mut conn)?;
-                match deleted {
-                    0 => Err(diesel::result::Error::NotFound),
-                    _ => Ok(deleted),
-                }
-            })
-            .await??;
+            $crate::db::run(&pool, move |conn| remove(conn, oid)).await?;
             Ok(HttpResponse::Ok().body(format!("Deleted object with id: {}", oid)))
         }
     };
@@ -138,21 +390,24 @@ macro_rules! crud_delete {
 #[macro_export]
 macro_rules! crud_delete_all {
     ($model:ty, $table:tt) => {
+        pub fn remove_all(
+            conn: &mut $crate::db::DbConnection,
+        ) -> Result<usize, diesel::result::Error> {
+            use crate::schema::$table::dsl::*;
+            let deleted = diesel::delete($table).execute(conn)?;
+            match deleted {
+                0 => Err(diesel::result::Error::NotFound),
+                _ => Ok(deleted),
+            }
+        }
+
+        #[utoipa::path(summary = "Delete all", responses((status = 200, body = String)), security(("token" = [])))]
         #[delete("")]
         pub async fn delete_all(
             pool: web::Data<DbPool>,
             _: Authenticated,
         ) -> Result<HttpResponse, ServerError> {
-            let mut conn = pool.get()?;
-            web::block(move || {
-                use crate::schema::$table::dsl::*;
-                let deleted = diesel::delete($table).execute(&mut conn)?;
-                match deleted {
-                    0 => Err(diesel::result::Error::NotFound),
-                    _ => Ok(deleted),
-                }
-            })
-            .await??;
+            $crate::db::run(&pool, remove_all).await?;
             Ok(HttpResponse::Ok().body("Deleted all objects"))
         }
     };
@@ -0,0 +1,221 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+use serde_json::Value;
+
+pub async fn errors_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Every error is a JSON object with a code and a message
+    let req = test::TestRequest::get().uri("/api/users").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+    assert_eq!(
+        test::read_body(resp).await,
+        error_json("unauthorized", "no authorization header")
+    );
+    do_test!(
+        app,
+        "0202",
+        Method::GET,
+        "/api/users",
+        "",
+        StatusCode::FORBIDDEN,
+        error_json("forbidden", "wrong token")
+    );
+
+    // Bodies that are not valid JSON, not JSON at all or too large are told apart
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"errors""#,
+        StatusCode::BAD_REQUEST,
+        r#"{"code":"bad_request","message":"Json deserialize error: "#
+    );
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"errors","password":7}"#,
+        StatusCode::BAD_REQUEST,
+        r#"{"code":"bad_request","message":"Json deserialize error: invalid type"#
+    );
+    let req = test::TestRequest::post()
+        .uri("/api/users")
+        .insert_header(("content-type", "text/plain"))
+        .set_payload(r#"{"name":"errors","password":"pass"}"#)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "unsupported_media_type");
+    let req = test::TestRequest::post()
+        .uri("/api/users")
+        .insert_header(("content-type", "application/json"))
+        .set_payload(format!(
+            r#"{{"name":"{}","password":"pass"}}"#,
+            "x".repeat(5000)
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "payload_too_large");
+
+    // Malformed queries are bad requests, malformed or unknown ids are not found
+    let req = test::TestRequest::get()
+        .uri("/api/photos?status=Lost")
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "bad_request");
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/users/first",
+        "",
+        StatusCode::NOT_FOUND,
+        r#"{"code":"not_found","message":"#
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/users/999999",
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "Item not found")
+    );
+
+    // A change refused by a constraint of the database is a conflict
+    let req = test::TestRequest::post()
+        .uri("/api/users")
+        .insert_header(("content-type", "application/json"))
+        .set_payload(r#"{"name":"errors","password":"pass"}"#)
+        .to_request();
+    let u: Value = test::call_and_read_body_json(&app, req).await;
+    let id = u["id"].as_i64().unwrap();
+    do_test!(
+        app,
+        "0101",
+        Method::PUT,
+        &format!("/api/users/{id}"),
+        &format!(
+            r#"{{"id":{id},"name":"errors","password":"","current_step":1,"version_id":999999}}"#
+        ),
+        StatusCode::CONFLICT,
+        r#"{"code":"conflict","message":"#
+    );
+
+    // The refusals of an advance have their own codes, with what the player needs in the details
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"pass","answer":"none"}"#,
+        StatusCode::NOT_FOUND,
+        error_json("no_more_steps", "there are no more steps")
+    );
+    let sid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what?","answer":"red"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/users/{id}/advance"))
+        .insert_header(("content-type", "application/json"))
+        .set_payload(
+            r#"{"password":"pass","latitude":45.16667,"longitude":5.71667,"answer":"red"}"#,
+        )
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "wrong_place");
+    assert_eq!(body["message"], "the step is 93750 meters away");
+    assert!(body["details"]["distance"].as_f64().unwrap() > 93749.0);
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"wrong","answer":"red"}"#,
+        StatusCode::FORBIDDEN,
+        error_json("wrong_password", "the password is wrong")
+    );
+
+    // So do the errors of the files of the steps and of the photos
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        &format!("/api/steps/images/{sid}"),
+        "not an image",
+        StatusCode::BAD_REQUEST,
+        r#"{"code":"bad_request","message":"the uploaded file is not a valid image: "#
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/images/{sid}"),
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "File not found")
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/medias/{sid}.mp3"),
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "File does not exist")
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/photos/999999/image",
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "File does not exist")
+    );
+
+    // Delete the user and the step
+    for uri in [format!("/api/users/{id}"), "/api/steps".to_string()] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(&uri)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{
+    auth::AppConfig,
+    create_app,
+    models::{
+        anticheat::{NewCheatFlag, NewPosition},
+        photo::{NewPhotoSubmission, PhotoStatus},
+        step::Media,
+    },
+    utils::now,
+};
+use diesel::{
+    prelude::*,
+    result::{DatabaseErrorKind, Error},
+};
+
+use crate::db::DbPool;
+
+// What is recorded about a player: positions, cheat flags and photos
+fn player_rows(pool: &DbPool, uid: i32) -> (i64, i64, i64) {
+    use crate::schema::{cheat_flags, photo_submissions, positions};
+    let mut conn = pool.get().unwrap();
+    (
+        positions::table
+            .filter(positions::user_id.eq(uid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+        cheat_flags::table
+            .filter(cheat_flags::user_id.eq(uid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+        photo_submissions::table
+            .filter(photo_submissions::user_id.eq(uid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+    )
+}
+
+// What is recorded about a step: its media, its assets, and its published copies and their assets
+fn step_rows(pool: &DbPool, sid: i32) -> (i64, i64, i64, i64) {
+    use crate::schema::{medias, published_assets, published_steps, step_assets};
+    let mut conn = pool.get().unwrap();
+    (
+        medias::table
+            .filter(medias::step_id.eq(sid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+        step_assets::table
+            .filter(step_assets::step_id.eq(sid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+        published_steps::table
+            .filter(published_steps::step_id.eq(sid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+        published_assets::table
+            .filter(published_assets::step_id.eq(sid))
+            .count()
+            .get_result(&mut conn)
+            .unwrap(),
+    )
+}
+
+// The draft step a player is on
+fn player_step(pool: &DbPool, uid: i32) -> Option<i32> {
+    use crate::schema::users;
+    users::table
+        .find(uid)
+        .select(users::step_id)
+        .first(&mut pool.get().unwrap())
+        .unwrap()
+}
+
+// SQLite reports the failures of RESTRICT constraints without their kind
+fn is_foreign_key_violation<T>(result: QueryResult<T>) -> bool {
+    match result {
+        Err(Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => true,
+        Err(Error::DatabaseError(_, info)) => info.message() == "FOREIGN KEY constraint failed",
+        _ => false,
+    }
+}
+
+pub async fn integrity_test(pool: &DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Create two steps, each with a media and an asset, and a player
+    let step = r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#;
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let id = do_test_extract_id!(
+            app,
+            "0101",
+            Method::POST,
+            "/api/steps",
+            step,
+            StatusCode::CREATED,
+            "{\"id\""
+        );
+        for uri in [
+            format!("/api/steps/medias/{id}.mp3"),
+            format!("/api/steps/{id}/assets"),
+        ] {
+            let req = test::TestRequest::with_uri(&uri)
+                .method(Method::POST)
+                .insert_header(("Authorization", "Bearer 0101"))
+                .set_payload(sound_body.clone())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        }
+        assert_eq!(step_rows(pool, id), (1, 1, 0, 0));
+        ids.push(id);
+    }
+    let (draft, published) = (ids[0], ids[1]);
+    let uid = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"player","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+
+    // Record a position, a cheat flag and a photo of the player
+    {
+        let mut conn = pool.get().unwrap();
+        diesel::insert_into(crate::schema::positions::table)
+            .values(&NewPosition {
+                user_id: uid,
+                latitude: 45.74846,
+                longitude: 4.84671,
+                recorded_at: now(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(crate::schema::cheat_flags::table)
+            .values(&NewCheatFlag {
+                user_id: uid,
+                step_id: draft,
+                kind: "Teleport".to_string(),
+                details: "".to_string(),
+                rejected: false,
+                created_at: now(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(crate::schema::photo_submissions::table)
+            .values(&NewPhotoSubmission {
+                user_id: uid,
+                step_id: draft,
+                status: PhotoStatus::Pending,
+                submitted_at: now(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+    }
+    assert_eq!(player_rows(pool, uid), (1, 1, 1));
+
+    // Nothing can be recorded for a player that does not exist
+    assert!(is_foreign_key_violation(
+        diesel::insert_into(crate::schema::positions::table)
+            .values(&NewPosition {
+                user_id: uid + 1,
+                latitude: 45.74846,
+                longitude: 4.84671,
+                recorded_at: now(),
+            })
+            .execute(&mut pool.get().unwrap())
+    ));
+
+    // Deleting the player deletes what was recorded about them
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/users/{uid}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {uid}")
+    );
+    assert_eq!(player_rows(pool, uid), (0, 0, 0));
+
+    // Publish the steps, and pin a new player to the version
+    let vid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let uid = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"player","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+
+    // The version cannot be deleted while it is played, even bypassing the API
+    {
+        use crate::schema::versions::dsl::*;
+        assert!(is_foreign_key_violation(
+            diesel::delete(versions.find(vid)).execute(&mut pool.get().unwrap())
+        ));
+        assert!(is_foreign_key_violation(
+            diesel::update(crate::schema::users::table.find(uid))
+                .set(crate::schema::users::version_id.eq(vid + 1))
+                .execute(&mut pool.get().unwrap())
+        ));
+    }
+
+    // Nothing can be recorded for a step that does not exist
+    assert!(is_foreign_key_violation(
+        diesel::insert_into(crate::schema::medias::table)
+            .values(&Media {
+                step_id: draft + published,
+                mime: "audio/mpeg".to_string(),
+                extension: "mp3".to_string(),
+                size: 0,
+                uploaded_at: now(),
+            })
+            .execute(&mut pool.get().unwrap())
+    ));
+
+    // Deleting a published step from the draft, even bypassing the API, deletes its media and its
+    // assets, the version keeps its copies...
+    assert_eq!(player_step(pool, uid), Some(published));
+    diesel::delete(crate::schema::steps::table.find(published))
+        .execute(&mut pool.get().unwrap())
+        .unwrap();
+    assert_eq!(step_rows(pool, published), (0, 0, 1, 1));
+    std::fs::remove_file(format!("data/items/medias/{published}.mp3")).unwrap();
+
+    // ... its player is found in their version by their position...
+    assert_eq!(player_step(pool, uid), None);
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{uid}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(r#"{{"id":{published},"rank":1,"#)
+    );
+
+    // ... and once the version is deleted, its steps go with it
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/users/{uid}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {uid}")
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/versions/{vid}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {vid}")
+    );
+    assert_eq!(step_rows(pool, draft), (1, 1, 0, 0));
+    assert_eq!(step_rows(pool, published), (0, 0, 0, 0));
+
+    // Deleting a step that is in no version deletes its media and its assets
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{draft}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {draft}")
+    );
+    assert_eq!(step_rows(pool, draft), (0, 0, 0, 0));
+
+    // Nothing is left for the collection
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::OK,
+        r#"{"files":["#
+    );
+    assert_eq!(step_rows(pool, published), (0, 0, 0, 0));
+
+    // The database passes the startup check, and SQLite writes ahead of the database file
+    let mut conn = pool.get().unwrap();
+    assert_eq!(crate::db::check(&mut conn), Ok(()));
+    if let crate::db::DbConnection::Sqlite(conn) = &mut *conn {
+        #[derive(QueryableByName)]
+        struct JournalMode {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            journal_mode: String,
+        }
+        let mode = diesel::sql_query("PRAGMA journal_mode")
+            .get_result::<JournalMode>(conn)
+            .unwrap();
+        assert_eq!(mode.journal_mode, "wal");
+    }
+
+    // A file that is not a database is reported rather than used
+    let path = "db/test_corrupt.sqlite";
+    std::fs::write(path, vec![0x42; 4096]).unwrap();
+    let mut corrupt = crate::db::DbConnection::establish(path).unwrap();
+    let error = crate::db::check(&mut corrupt).unwrap_err();
+    assert!(error.starts_with("the database cannot be read"), "{error}");
+    std::fs::remove_file(path).unwrap();
+}
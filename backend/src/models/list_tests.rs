@@ -0,0 +1,163 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+use serde_json::Value;
+
+pub async fn list_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::do_test;
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Create players on different steps
+    let mut ids = Vec::new();
+    for (position, player) in ["alice", "Bob", "bobby", "d_x", "dax"].iter().enumerate() {
+        let req = test::TestRequest::post()
+            .uri("/api/users")
+            .insert_header(("content-type", "application/json"))
+            .set_payload(format!(r#"{{"name":"{player}","password":"pass"}}"#))
+            .to_request();
+        let u: Value = test::call_and_read_body_json(&app, req).await;
+        let id = u["id"].as_i64().unwrap();
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/users/{id}"))
+            .insert_header(("Authorization", "Bearer 0101"))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(format!(
+                r#"{{"id":{id},"name":"{player}","password":"","current_step":{}}}"#,
+                position + 1
+            ))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        ids.push(Value::from(id));
+    }
+
+    // The ids of the listed objects, and the number of objects matching the filters
+    let list = |uri: &str| {
+        test::TestRequest::with_uri(uri)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request()
+    };
+    macro_rules! listed {
+        ($uri:expr) => {{
+            let resp = test::call_service(&app, list($uri)).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let total = resp
+                .headers()
+                .get("X-Total-Count")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            let objects: Vec<Value> = test::read_body_json(resp).await;
+            (
+                objects.iter().map(|o| o["id"].clone()).collect::<Vec<_>>(),
+                total.parse::<usize>().unwrap(),
+            )
+        }};
+    }
+
+    // Without parameters, every player is listed by id
+    assert_eq!(listed!("/api/users"), (ids.clone(), 5));
+
+    // A page of them
+    assert_eq!(
+        listed!("/api/users?limit=2&offset=1"),
+        (ids[1..3].to_vec(), 5)
+    );
+    assert_eq!(listed!("/api/users?offset=4"), (ids[4..].to_vec(), 5));
+
+    // The players whose name contains a text, whatever its case, wildcards being taken literally
+    assert_eq!(listed!("/api/users?name=BOB"), (ids[1..3].to_vec(), 2));
+    assert_eq!(listed!("/api/users?name=_"), (ids[3..4].to_vec(), 1));
+
+    // The players within a range of steps, the latest first
+    assert_eq!(
+        listed!("/api/users?current_step_min=2&current_step_max=4&sort=-current_step&limit=2"),
+        (vec![ids[3].clone(), ids[2].clone()], 3)
+    );
+
+    // Unknown fields and filters, and bad numbers, are refused
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/users?sort=password",
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "cannot sort on password")
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/users?team=blue",
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "unknown filter: team")
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/users?limit=-1",
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "limit is not a positive number: -1")
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/users?current_step_min=first",
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "current_step_min is not a number: first")
+    );
+
+    // Steps are listed the same way
+    let mut steps = Vec::new();
+    for (rank, question) in [
+        (1, "where is the park?"),
+        (2, "what is the color of the sky?"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/steps")
+            .insert_header(("Authorization", "Bearer 0101"))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(format!(
+                r#"{{"rank":{rank},"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"{question}","answer":"blue"}}"#
+            ))
+            .to_request();
+        let s: Value = test::call_and_read_body_json(&app, req).await;
+        steps.push(s["id"].clone());
+    }
+    assert_eq!(
+        listed!("/api/steps?sort=-rank"),
+        (vec![steps[1].clone(), steps[0].clone()], 2)
+    );
+    assert_eq!(
+        listed!("/api/steps?question=Color"),
+        (steps[1..].to_vec(), 1)
+    );
+    assert_eq!(listed!("/api/steps?rank_max=1"), (steps[..1].to_vec(), 1));
+
+    // Delete everything
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+}
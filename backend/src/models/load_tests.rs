@@ -0,0 +1,115 @@
+use crate::{auth::AppConfig, create_app};
+use futures_util::future::{join, join_all};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+const PLAYERS: usize = 10;
+const STEPS: i32 = 3;
+const SLOW_WORK: Duration = Duration::from_secs(2);
+const TICK: Duration = Duration::from_millis(10);
+
+pub async fn load_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use actix_web::{http::StatusCode, test};
+
+    let app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Create the steps, answered without going there, and the players
+    let create = |uri: &str, token: &str, body: String| {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body)
+            .to_request()
+    };
+    for rank in 1..=STEPS {
+        let body = format!(
+            r#"{{"rank":{rank},"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the answer?","answer":"answer {rank}","is_end":{},"validation_mode":"AnswerOnly"}}"#,
+            rank == STEPS
+        );
+        let resp = test::call_service(&app, create("/api/steps", "0101", body)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+    let mut players = Vec::new();
+    for i in 0..PLAYERS {
+        let body = format!(r#"{{"name":"player {i}","password":"pass"}}"#);
+        let u: Value = test::call_and_read_body_json(&app, create("/api/users", "", body)).await;
+        players.push(u["id"].as_i64().unwrap());
+    }
+
+    // Every player goes through the hunt at the same time, all the advances must succeed
+    let app = &app;
+    join_all(players.iter().map(|&uid| async move {
+        for rank in 1..STEPS {
+            let req = test::TestRequest::post()
+                .uri(&format!("/api/users/{uid}/advance"))
+                .insert_header(("content-type", "application/json"))
+                .set_payload(format!(
+                    r#"{{"password":"pass","latitude":0,"longitude":0,"answer":"answer {rank}"}}"#
+                ))
+                .to_request();
+            let resp = test::call_service(app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK, "advance of player {uid}");
+        }
+    }))
+    .await;
+
+    // While slow work holds every connection, a request waits for one without stalling the others
+    let held = join_all((0..pool.max_size()).map(|_| {
+        crate::db::run(pool, |_| {
+            std::thread::sleep(SLOW_WORK);
+            Ok::<_, diesel::result::Error>(())
+        })
+    }));
+    let waiting = async {
+        while pool.state().idle_connections > 0 {
+            actix_web::rt::time::sleep(TICK).await;
+        }
+        let start = Instant::now();
+        let request = async {
+            let req = test::TestRequest::with_uri("/api/steps")
+                .insert_header(("Authorization", "Bearer 0101"))
+                .to_request();
+            test::call_service(app, req).await.status()
+        };
+        let tick = async {
+            actix_web::rt::time::sleep(TICK).await;
+            start.elapsed()
+        };
+        join(request, tick).await
+    };
+    let (held, (status, ticked)) = join(held, waiting).await;
+    assert!(held.into_iter().all(|r| r.is_ok()));
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        ticked < SLOW_WORK / 2,
+        "timer fired after {ticked:?} while the connections were held"
+    );
+
+    // They all reached the last step
+    for uid in players {
+        let req = test::TestRequest::with_uri(&format!("/api/users/{uid}"))
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request();
+        let u: Value = test::call_and_read_body_json(app, req).await;
+        assert_eq!(u["current_step"], STEPS);
+    }
+
+    // Delete everything
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(app, req).await;
+    }
+}
@@ -0,0 +1,229 @@
+use actix_web::{delete, get, web, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{AppConfig, Authenticated},
+    db::DbConnection,
+    errors::ServerError,
+    models::{
+        asset::{is_used, release_file, ASSETS_DIR},
+        photo::PHOTOS_PATH,
+        step::{IMAGES_DIR, MEDIAS_DIR, MEDIAS_LOW_DIR, UPLOADS_PATH},
+        version::{is_copy_used, PUBLISHED_DIR},
+    },
+    storage::{FileStorage, Storage},
+};
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+// Files younger than this are never collected, as they may belong to an upload in progress
+const GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+// Files left behind by deleted steps, photos or interrupted uploads
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct GarbageReport {
+    pub files: Vec<String>,
+    pub bytes: u64,
+}
+
+// Find the orphaned files of the storage, the photos and the uploads, and remove them if asked
+pub fn collect_garbage(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    remove: bool,
+) -> Result<GarbageReport, ServerError> {
+    let mut report = GarbageReport::default();
+    // The published versions have their own copies of the files, and share the files of the assets
+    let step_ids: HashSet<i32> = crate::schema::steps::table
+        .select(crate::schema::steps::id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect();
+
+    // The records of the medias and the assets are deleted with their steps
+    let medias = crate::schema::medias::table
+        .select((
+            crate::schema::medias::step_id,
+            crate::schema::medias::extension,
+        ))
+        .load::<(i32, String)>(conn)?;
+    let media_names: HashSet<String> = medias
+        .iter()
+        .map(|(sid, ext)| format!("{sid}.{ext}"))
+        .collect();
+    let media_ids: HashSet<i32> = medias.iter().map(|(sid, _)| *sid).collect();
+    let assets = crate::schema::step_assets::table
+        .select(crate::schema::step_assets::checksum)
+        .load::<String>(conn)?;
+    let published_checksums = crate::schema::published_assets::table
+        .select(crate::schema::published_assets::checksum)
+        .load::<String>(conn)?;
+    let checksums: HashSet<&str> = assets
+        .iter()
+        .map(String::as_str)
+        .chain(published_checksums.iter().map(String::as_str))
+        .collect();
+    let copies: HashSet<String> = crate::schema::published_files::table
+        .select(crate::schema::published_files::file_key)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+    let photo_ids: HashSet<i32> = crate::schema::photo_submissions::table
+        .select(crate::schema::photo_submissions::id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect();
+
+    let leading_id = |name: &str| -> Option<i32> {
+        let digits: String = name.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse().ok()
+    };
+    let stem = |name: &str| name.split('.').next().unwrap_or_default().to_string();
+
+    sweep(&mut report, remove, storage, IMAGES_DIR, |name| {
+        leading_id(name).is_some_and(|id| step_ids.contains(&id))
+    })?;
+    sweep(&mut report, remove, storage, MEDIAS_DIR, |name| {
+        media_names.contains(name)
+    })?;
+    sweep(&mut report, remove, storage, MEDIAS_LOW_DIR, |name| {
+        stem(name)
+            .parse()
+            .is_ok_and(|id: i32| media_ids.contains(&id))
+    })?;
+    // The files of the assets and the copies may be attached again meanwhile, they are released
+    sweep_with(
+        &mut report,
+        remove,
+        storage,
+        ASSETS_DIR,
+        |name| checksums.contains(stem(name).as_str()),
+        |key| {
+            let checksum = stem(&key[ASSETS_DIR.len() + 1..]);
+            release_file(storage, key, || is_used(conn, &checksum))
+        },
+    )?;
+    sweep_with(
+        &mut report,
+        remove,
+        storage,
+        PUBLISHED_DIR,
+        |name| copies.contains(&format!("{PUBLISHED_DIR}/{name}")),
+        |key| release_file(storage, key, || is_copy_used(conn, key)),
+    )?;
+    // Photos and uploads always stay on the local disk
+    sweep(
+        &mut report,
+        remove,
+        &FileStorage::new(PHOTOS_PATH),
+        "",
+        |name| {
+            stem(name)
+                .parse()
+                .is_ok_and(|id: i32| photo_ids.contains(&id))
+        },
+    )?;
+    sweep(
+        &mut report,
+        remove,
+        &FileStorage::new(UPLOADS_PATH),
+        "",
+        |_| false,
+    )?;
+    Ok(report)
+}
+
+// Report, and remove if asked, the objects of a directory that are not in use and older than the
+// grace period. Local files are reported by their path, other objects by their key.
+fn sweep(
+    report: &mut GarbageReport,
+    remove: bool,
+    storage: &dyn Storage,
+    dir: &str,
+    in_use: impl Fn(&str) -> bool,
+) -> Result<(), ServerError> {
+    sweep_with(report, remove, storage, dir, in_use, |key| {
+        Ok(storage.delete(key)?)
+    })
+}
+
+// Sweep a directory, removing the objects with the given function
+fn sweep_with(
+    report: &mut GarbageReport,
+    remove: bool,
+    storage: &dyn Storage,
+    dir: &str,
+    in_use: impl Fn(&str) -> bool,
+    mut delete: impl FnMut(&str) -> Result<(), ServerError>,
+) -> Result<(), ServerError> {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{dir}/")
+    };
+    let now = SystemTime::now();
+    for object in storage.list(&prefix)? {
+        if in_use(&object.key[prefix.len()..]) {
+            continue;
+        }
+        let age = now.duration_since(object.modified).unwrap_or_default();
+        if age < GRACE_PERIOD {
+            continue;
+        }
+        let name = storage
+            .local_path(&object.key)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|| object.key.clone());
+        if remove {
+            delete(&object.key)?;
+        }
+        report.bytes += object.size;
+        report.files.push(name);
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    summary = "List the files left behind",
+    responses((status = 200, body = GarbageReport)),
+    security(("token" = [])),
+)]
+#[get("/orphans")]
+pub async fn read_orphans(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let storage = app_config.storage.clone();
+    let report = crate::db::run(&pool, move |conn| {
+        collect_garbage(conn, storage.as_ref(), false)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[utoipa::path(
+    summary = "Remove the files left behind",
+    responses((status = 200, body = GarbageReport)),
+    security(("token" = [])),
+)]
+#[delete("/orphans")]
+pub async fn delete_orphans(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let storage = app_config.storage.clone();
+    let report = crate::db::run(&pool, move |conn| {
+        collect_garbage(conn, storage.as_ref(), true)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(report))
+}
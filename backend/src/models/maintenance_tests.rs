@@ -0,0 +1,253 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+
+// Make a file look older than the grace period of the garbage collection
+fn age(path: &str) {
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(7200))
+        .unwrap();
+}
+
+pub async fn maintenance_test(
+    pool: &crate::db::DbPool,
+    app_config: &actix_web::web::Data<AppConfig>,
+) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use std::path::Path;
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Collect what the previous tests left behind
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/maintenance/orphans")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create two steps, each with an image, a sound and an asset
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    let img_body = std::fs::read("test_img.jpg").unwrap();
+    let step = |rank: i32| {
+        format!(
+            r#"{{"rank":{rank},"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}}"#
+        )
+    };
+    let id1 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        &step(1),
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let id2 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        &step(2),
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    for id in [id1, id2] {
+        for uri in [
+            format!("/api/steps/images/{id}"),
+            format!("/api/steps/medias/{id}.mp3"),
+            format!("/api/steps/{id}/assets"),
+        ] {
+            let body = if uri.contains("images") {
+                img_body.clone()
+            } else {
+                sound_body.clone()
+            };
+            let req = test::TestRequest::with_uri(&uri)
+                .method(Method::POST)
+                .insert_header(("Authorization", "Bearer 0101"))
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        }
+    }
+    let image1 = format!("data/items/images/{id1}.jpg");
+    let image2 = format!("data/items/images/{id2}.jpg");
+    let media1 = format!("data/items/medias/{id1}.mp3");
+    let media2 = format!("data/items/medias/{id2}.mp3");
+    let asset =
+        "data/items/assets/affa2f429722c547275b64721fec3ccf0e72c896d52a2a05207212ea51b5817b.mp3";
+    let used = [
+        image1.as_str(),
+        image2.as_str(),
+        media1.as_str(),
+        media2.as_str(),
+        asset,
+    ];
+    for file in used {
+        age(file);
+    }
+
+    // The asset attached to both steps is stored once
+    let assets = std::fs::read_dir("data/items/assets")
+        .unwrap()
+        .filter(|e| {
+            e.as_ref()
+                .unwrap()
+                .file_name()
+                .to_str()
+                .unwrap()
+                .starts_with("affa2f42")
+        })
+        .count();
+    assert_eq!(assets, 1);
+
+    // Get the report without a token (must fail)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::UNAUTHORIZED,
+        error_json("unauthorized", "authorization header is too short")
+    );
+
+    // Nothing used by the steps is reported
+    let report = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::OK,
+        r#"{"files":["#
+    );
+    for file in used {
+        assert!(!report.contains(file), "{file} is reported");
+    }
+
+    // Leave files behind: an unknown image, an interrupted upload and a recent interrupted upload
+    std::fs::write("data/items/images/999999999.jpg", &img_body).unwrap();
+    age("data/items/images/999999999.jpg");
+    std::fs::create_dir_all("data/items/uploads").unwrap();
+    std::fs::write("data/items/uploads/interrupted.upload", &sound_body).unwrap();
+    age("data/items/uploads/interrupted.upload");
+    std::fs::write("data/items/uploads/in_progress.upload", &sound_body).unwrap();
+
+    // Delete the second step by hand, its records go with it but its files are left behind
+    {
+        use diesel::prelude::*;
+        let mut conn = pool.get().unwrap();
+        diesel::delete(crate::schema::steps::table.find(id2))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    // The files of the deleted step are reported, but the shared asset still used by the first step is not
+    let report = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::OK,
+        r#"{"files":["#
+    );
+    for file in [
+        image2.as_str(),
+        media2.as_str(),
+        "data/items/images/999999999.jpg",
+        "data/items/uploads/interrupted.upload",
+    ] {
+        assert!(report.contains(file), "{file} is not reported");
+    }
+    for file in [
+        image1.as_str(),
+        media1.as_str(),
+        asset,
+        "data/items/uploads/in_progress.upload",
+    ] {
+        assert!(!report.contains(file), "{file} is reported");
+    }
+    assert!(Path::new(&image2).exists());
+
+    // Remove them
+    let report = do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::OK,
+        r#"{"files":["#
+    );
+    assert!(report.contains(&image2));
+    assert!(!Path::new(&image2).exists());
+    assert!(!Path::new(&media2).exists());
+    assert!(!Path::new("data/items/images/999999999.jpg").exists());
+    assert!(!Path::new("data/items/uploads/interrupted.upload").exists());
+    assert!(Path::new("data/items/uploads/in_progress.upload").exists());
+    assert!(Path::new(&image1).exists());
+    assert!(Path::new(&media1).exists());
+    assert!(Path::new(asset).exists());
+    std::fs::remove_file("data/items/uploads/in_progress.upload").unwrap();
+
+    // There is nothing left to collect
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::OK,
+        r#"{"files":[],"bytes":0}"#
+    );
+
+    // Deleting the last step using the shared asset removes its file
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{id1}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {id1}")
+    );
+    assert!(!Path::new(asset).exists());
+
+    // An orphaned file that an upload found in place is not collected until the upload recorded it
+    std::fs::write(asset, "attached again").unwrap();
+    age(asset);
+    let attaching = crate::models::asset::Attaching::new(&asset["data/items/".len()..]);
+    let collect = || {
+        test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri("/api/maintenance/orphans")
+            .to_request()
+    };
+    assert_eq!(
+        test::call_service(&app, collect()).await.status(),
+        StatusCode::OK
+    );
+    assert!(Path::new(asset).exists());
+    drop(attaching);
+    assert_eq!(
+        test::call_service(&app, collect()).await.status(),
+        StatusCode::OK
+    );
+    assert!(!Path::new(asset).exists());
+}
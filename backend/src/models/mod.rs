@@ -1,10 +1,51 @@
+pub(crate) mod anticheat;
+pub(crate) mod asset;
+pub(crate) mod backup;
 pub(crate) mod crud;
+pub(crate) mod maintenance;
+pub(crate) mod photo;
+pub(crate) mod qrcodes;
 pub(crate) mod step;
 pub(crate) mod user;
+pub(crate) mod version;
 
 #[cfg(test)]
 pub(crate) mod advance_tests;
 #[cfg(test)]
+pub(crate) mod anticheat_tests;
+#[cfg(test)]
+pub(crate) mod asset_tests;
+#[cfg(test)]
+pub(crate) mod backup_tests;
+#[cfg(test)]
+pub(crate) mod errors_tests;
+#[cfg(test)]
+pub(crate) mod integrity_tests;
+#[cfg(test)]
+pub(crate) mod list_tests;
+#[cfg(test)]
+pub(crate) mod load_tests;
+#[cfg(test)]
+pub(crate) mod maintenance_tests;
+#[cfg(test)]
+pub(crate) mod openapi_tests;
+#[cfg(test)]
+pub(crate) mod patch_tests;
+#[cfg(test)]
+pub(crate) mod photo_tests;
+#[cfg(test)]
+pub(crate) mod qrcodes_tests;
+#[cfg(test)]
+pub(crate) mod rank_tests;
+#[cfg(test)]
 pub(crate) mod step_tests;
 #[cfg(test)]
+pub(crate) mod storage_tests;
+#[cfg(test)]
+pub(crate) mod streaming_tests;
+#[cfg(test)]
+pub(crate) mod traversal_tests;
+#[cfg(test)]
 pub(crate) mod user_tests;
+#[cfg(test)]
+pub(crate) mod version_tests;
@@ -0,0 +1,81 @@
+use crate::{auth::AppConfig, create_app, openapi::ApiDoc};
+use serde_json::Value;
+use utoipa::OpenApi;
+
+pub async fn openapi_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let app = test::init_service(create_app!(pool, app_config)).await;
+
+    // The document served is the one generated from the handlers, and the one committed for the
+    // clients: when this fails, run `pistou openapi > openapi.json` and check the Flutter models
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/openapi.json")
+        .to_request();
+    let served: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(served, spec);
+    let committed: Value = serde_json::from_str(include_str!("../../openapi.json")).unwrap();
+    assert!(
+        committed == spec,
+        "openapi.json is out of date, regenerate it with `pistou openapi > openapi.json`"
+    );
+
+    // The docs are served with the server
+    let req = test::TestRequest::get().uri("/api/docs").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+
+    // Every operation of the document is routed: without a token nor a body, it is answered by
+    // a handler, with a JSON error when it is not found, and not by the router
+    let mut ids = Vec::new();
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        // The files are asked for as PNG, and the ids are 1
+        let mut uri = path.replace("{ext}", "png");
+        while let (Some(start), Some(end)) = (uri.find('{'), uri.find('}')) {
+            uri.replace_range(start..=end, "1");
+        }
+        for (method, operation) in item.as_object().unwrap() {
+            ids.push(operation["operationId"].as_str().unwrap().to_string());
+            let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+            let req = test::TestRequest::default()
+                .method(method.clone())
+                .uri(&uri)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_ne!(
+                resp.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{method} {uri} is not routed"
+            );
+            if resp.status() == StatusCode::NOT_FOUND && method != Method::HEAD {
+                let body: Value = test::read_body_json(resp).await;
+                assert!(body["code"].is_string(), "{method} {uri} is not routed");
+            }
+        }
+    }
+
+    // And every handler given to create_app! is documented
+    for line in include_str!("../app.rs").lines() {
+        let Some(handler) = line
+            .trim()
+            .strip_prefix(".service(")
+            .map(|h| h.trim_end_matches([')', ',']))
+        else {
+            continue;
+        };
+        if let Some((module, name)) = handler.split_once("::") {
+            if !handler.contains('(') {
+                let id = format!("{module}_{name}");
+                assert!(ids.contains(&id), "{handler} is not documented");
+            }
+        }
+    }
+}
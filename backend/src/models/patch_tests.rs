@@ -0,0 +1,231 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+use actix_web::dev::ServiceResponse;
+use serde_json::Value;
+
+fn etag<B>(resp: &ServiceResponse<B>) -> String {
+    resp.headers()
+        .get("ETag")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+pub async fn patch_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Create a step and a player, each at its first revision
+    let sid = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue","validation_mode":"AnswerOnly"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let uid = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"patched","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let read = |uri: &str| {
+        test::TestRequest::with_uri(uri)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request()
+    };
+    let step_uri = format!("/api/steps/{sid}");
+    let user_uri = format!("/api/users/{uid}");
+    let resp = test::call_service(&app, read(&step_uri)).await;
+    assert_eq!(etag(&resp), r#""1""#);
+
+    // Patch the step: only the given fields change, and so does its ETag
+    let patch = |uri: &str, if_match: Option<&str>, body: &str| {
+        let mut req = test::TestRequest::patch()
+            .uri(uri)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .insert_header(("content-type", "application/merge-patch+json"))
+            .set_payload(body.to_string());
+        if let Some(tag) = if_match {
+            req = req.insert_header(("If-Match", tag));
+        }
+        req.to_request()
+    };
+    let resp = test::call_service(
+        &app,
+        patch(
+            &step_uri,
+            Some(r#""1""#),
+            r#"{"question":"what is the color of the grass?","shake_message":"shake it"}"#,
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(etag(&resp), r#""2""#);
+    let s: Value = test::read_body_json(resp).await;
+    assert_eq!(s["question"], "what is the color of the grass?");
+    assert_eq!(s["shake_message"], "shake it");
+    assert_eq!(s["answer"], "blue");
+    assert_eq!(s["validation_mode"], "AnswerOnly");
+
+    // An outdated ETag is refused, whether patching or replacing
+    do_test!(
+        app,
+        "0101",
+        Method::PATCH,
+        &step_uri,
+        r#"{"answer":"green"}"#,
+        StatusCode::OK,
+        "{\"id\""
+    );
+    let resp = test::call_service(
+        &app,
+        patch(&step_uri, Some(r#""2""#), r#"{"answer":"red"}"#),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(
+        test::read_body(resp).await,
+        error_json(
+            "precondition_failed",
+            r#"the object was changed since it was read, its ETag is now "3""#
+        )
+    );
+    let req = test::TestRequest::put()
+        .uri(&step_uri)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .insert_header(("If-Match", r#""2""#))
+        .insert_header(("content-type", "application/json"))
+        .set_payload(format!(
+            r#"{{"id":{sid},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what?","answer":"red"}}"#
+        ))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::PRECONDITION_FAILED
+    );
+    let s: Value = test::call_and_read_body_json(&app, read(&step_uri)).await;
+    assert_eq!(s["answer"], "green");
+
+    // A null removes a field, and a patch giving an invalid step is refused
+    let resp = test::call_service(
+        &app,
+        patch(&step_uri, Some("*"), r#"{"shake_message":null}"#),
+    )
+    .await;
+    assert_eq!(etag(&resp), r#""4""#);
+    let s: Value = test::read_body_json(resp).await;
+    assert_eq!(s.get("shake_message"), None);
+    let resp = test::call_service(&app, patch(&step_uri, None, r#"{"rank":"first"}"#)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(test::read_body(resp).await.to_vec())
+        .unwrap()
+        .starts_with(r#"{"code":"bad_request","message":"the patched object is not valid"#));
+
+    // Adding a step after it leaves it as it is
+    let next = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the answer?","answer":"yes","validation_mode":"AnswerOnly"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let resp = test::call_service(&app, read(&step_uri)).await;
+    assert_eq!(etag(&resp), r#""4""#);
+
+    // Patch the player's name, their password stays the same
+    let resp = test::call_service(&app, read(&user_uri)).await;
+    let user_etag = etag(&resp);
+    let resp = test::call_service(
+        &app,
+        patch(&user_uri, Some(&user_etag), r#"{"name":"  renamed  "}"#),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let user_etag = etag(&resp);
+    let u: Value = test::read_body_json(resp).await;
+    assert_eq!(u["name"], "renamed");
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{uid}/advance"),
+        r#"{"password":"pass","answer":"green"}"#,
+        StatusCode::OK,
+        r#"{"type":"Success""#
+    );
+
+    // Advancing changed the player, so the ETag read before is outdated
+    let resp = test::call_service(
+        &app,
+        patch(&user_uri, Some(&user_etag), r#"{"password":"new pass"}"#),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    let resp = test::call_service(&app, read(&user_uri)).await;
+    let user_etag = etag(&resp);
+    let resp = test::call_service(
+        &app,
+        patch(&user_uri, Some(&user_etag), r#"{"password":"new pass"}"#),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{uid}/advance"),
+        r#"{"password":"pass","answer":"no"}"#,
+        StatusCode::FORBIDDEN,
+        error_json("wrong_password", "the password is wrong")
+    );
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{uid}/advance"),
+        r#"{"password":"new pass","answer":"no"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json("wrong_answer", "the answer is wrong")
+    );
+
+    // Moving a step before the first one moves the first one too, which changes it
+    let resp = test::call_service(
+        &app,
+        patch(&format!("/api/steps/{next}"), None, r#"{"rank":1}"#),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&app, read(&step_uri)).await;
+    assert_eq!(etag(&resp), r#""5""#);
+
+    // Delete everything
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+}
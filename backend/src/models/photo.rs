@@ -0,0 +1,342 @@
+use actix_files::NamedFile;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Result};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, remove_file, rename};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::{AppConfig, Authenticated},
+    db::DbConnection,
+    errors::{ErrorBody, ServerError},
+    models::{
+        step::{receive_upload, store_image, Step, ValidationMode},
+        user::{check_password, move_player, Refusal, User},
+        version::{step_at, step_of_player},
+    },
+    schema::photo_submissions,
+    sniff::sniff,
+    utils::now,
+};
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+pub(crate) const PHOTOS_PATH: &str = "data/items/photos";
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow, ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum PhotoStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl PhotoStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PhotoStatus::Pending => "Pending",
+            PhotoStatus::Approved => "Approved",
+            PhotoStatus::Rejected => "Rejected",
+        }
+    }
+}
+
+impl<DB> ToSql<Text, DB> for PhotoStatus
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for PhotoStatus
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        match String::from_sql(bytes)?.as_str() {
+            "Pending" => Ok(PhotoStatus::Pending),
+            "Approved" => Ok(PhotoStatus::Approved),
+            "Rejected" => Ok(PhotoStatus::Rejected),
+            v => Err(format!("unknown photo status: {}", v).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, ToSchema)]
+#[diesel(table_name = photo_submissions)]
+pub struct PhotoSubmission {
+    pub id: i32,
+    pub user_id: i32,
+    pub step_id: i32,
+    pub status: PhotoStatus,
+    pub submitted_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviewed_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = photo_submissions)]
+pub struct NewPhotoSubmission {
+    pub user_id: i32,
+    pub step_id: i32,
+    pub status: PhotoStatus,
+    pub submitted_at: i64,
+}
+
+fn photo_filename(id: i32) -> String {
+    format!("{path}/{id}.jpg", path = PHOTOS_PATH, id = id)
+}
+
+// Tell if a photo from the user for the step is waiting for an organizer
+pub fn is_pending(
+    conn: &mut DbConnection,
+    uid: i32,
+    sid: i32,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::photo_submissions::dsl::*;
+    diesel::select(diesel::dsl::exists(
+        photo_submissions
+            .filter(user_id.eq(uid))
+            .filter(step_id.eq(sid))
+            .filter(status.eq(PhotoStatus::Pending)),
+    ))
+    .get_result(conn)
+}
+
+// The current step of a player, which must expect a photo that is not already awaiting validation
+fn photo_step(conn: &mut DbConnection, u: &User) -> Result<Step, ServerError> {
+    let s = step_of_player(conn, u)
+        .optional()?
+        .ok_or(Refusal::NoMoreSteps)?;
+    if s.validation_mode != ValidationMode::Photo {
+        return Err(ServerError::BadRequest(
+            "the current step does not expect a photo".to_string(),
+        ));
+    }
+    if is_pending(conn, u.id, s.id)? {
+        return Err(Refusal::AwaitingValidation.into());
+    }
+    Ok(s)
+}
+
+// Check the password of a player, and that they may submit a photo, before receiving it
+fn check_submission(conn: &mut DbConnection, oid: i32, password: &str) -> Result<(), ServerError> {
+    use crate::schema::users::dsl::users;
+    let u = users.find(oid).first::<User>(conn)?;
+    check_password(&u, password)?;
+    photo_step(conn, &u)?;
+    Ok(())
+}
+
+fn not_an_image() -> ServerError {
+    ServerError::BadRequest("the uploaded file is not a valid image".to_string())
+}
+
+// Submit a photo as the answer to the current step, the player password is given in the "password" header
+#[utoipa::path(
+    summary = "Submit a photo as the answer to the current step",
+    params(("password" = String, Header)),
+    request_body(content = Vec<u8>, content_type = "image/jpeg"),
+    responses(
+        (status = 201, body = PhotoSubmission),
+        (status = 403, body = ErrorBody, description = "`wrong_password`"),
+        (status = 404, body = ErrorBody, description = "`no_more_steps`"),
+    ),
+)]
+#[post("/{oid}/photo")]
+pub async fn submit(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    oid: web::Path<i32>,
+    body: web::Payload,
+) -> Result<HttpResponse, ServerError> {
+    let password = req
+        .headers()
+        .get("password")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let oid = *oid;
+    crate::db::run(&pool, move |conn| check_submission(conn, oid, &password)).await?;
+
+    // Shrink the photo before recording it, so that no transaction waits for the image processing
+    let upload = receive_upload(body, app_config.media_max_size, |head| {
+        sniff(head).filter(|t| t.mime.starts_with("image/"))
+    })
+    .await
+    .map_err(|e| match e {
+        ServerError::UnsupportedMediaType(_) => not_an_image(),
+        e => e,
+    })?;
+    let shrunk = format!("{}.jpg", upload.temp_filename);
+    let stored = {
+        let (received, shrunk) = (upload.temp_filename.clone(), shrunk.clone());
+        web::block(move || store_image(&std::fs::read(received)?, &shrunk)).await?
+    };
+    let _ = remove_file(&upload.temp_filename);
+    if stored.is_err() {
+        let _ = remove_file(&shrunk);
+        return Err(not_an_image());
+    }
+
+    let recorded = {
+        let shrunk = shrunk.clone();
+        crate::db::run(&pool, move |conn| insert(conn, oid, &shrunk)).await
+    };
+    if recorded.is_err() {
+        let _ = remove_file(&shrunk);
+    }
+    Ok(HttpResponse::Created().json(recorded?))
+}
+
+// Record the shrunk photo of a player for their current step, which may have changed since the
+// photo was received
+fn insert(conn: &mut DbConnection, oid: i32, shrunk: &str) -> Result<PhotoSubmission, ServerError> {
+    conn.transaction(|conn| {
+        use crate::schema::photo_submissions::dsl::*;
+        use crate::schema::users::dsl::users;
+        let u = users.find(oid).first::<User>(conn)?;
+        let s = photo_step(conn, &u)?;
+        let p = diesel::insert_into(photo_submissions)
+            .values(&NewPhotoSubmission {
+                user_id: u.id,
+                step_id: s.id,
+                status: PhotoStatus::Pending,
+                submitted_at: now(),
+            })
+            .get_result::<PhotoSubmission>(conn)?;
+        create_dir_all(PHOTOS_PATH)?;
+        rename(shrunk, photo_filename(p.id))?;
+        Ok(p)
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueueFilter {
+    status: Option<PhotoStatus>,
+}
+
+// List the submitted photos, oldest first, only the pending ones by default
+#[utoipa::path(
+    summary = "List the submitted photos",
+    params(QueueFilter),
+    responses((status = 200, body = Vec<PhotoSubmission>)),
+    security(("token" = [])),
+)]
+#[get("")]
+pub async fn read_all(
+    pool: web::Data<DbPool>,
+    filter: web::Query<QueueFilter>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let wanted = filter.status.unwrap_or(PhotoStatus::Pending);
+    let queue = crate::db::run(&pool, move |conn| find_all(conn, wanted)).await?;
+    Ok(HttpResponse::Ok().json(queue))
+}
+
+fn find_all(conn: &mut DbConnection, wanted: PhotoStatus) -> QueryResult<Vec<PhotoSubmission>> {
+    use crate::schema::photo_submissions::dsl::*;
+    photo_submissions
+        .filter(status.eq(wanted))
+        .order((submitted_at.asc(), id.asc()))
+        .load::<PhotoSubmission>(conn)
+}
+
+#[utoipa::path(
+    summary = "Download a submitted photo",
+    responses((status = 200, body = Vec<u8>, content_type = "image/jpeg")),
+    security(("token" = [])),
+)]
+#[get("/{oid}/image")]
+pub async fn retrieve_photo(
+    oid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<NamedFile, ServerError> {
+    NamedFile::open(photo_filename(*oid))
+        .map_err(|_| ServerError::NotFound("File does not exist".to_owned()))
+}
+
+fn review(
+    conn: &mut DbConnection,
+    oid: i32,
+    new_status: PhotoStatus,
+) -> Result<PhotoSubmission, ServerError> {
+    use crate::schema::photo_submissions::dsl::*;
+    let p = photo_submissions.find(oid).first::<PhotoSubmission>(conn)?;
+    if p.status != PhotoStatus::Pending {
+        return Err(ServerError::BadRequest(
+            "this photo has already been reviewed".to_string(),
+        ));
+    }
+    diesel::update(photo_submissions)
+        .filter(id.eq(oid))
+        .set((status.eq(new_status), reviewed_at.eq(now())))
+        .execute(conn)?;
+    Ok(photo_submissions.find(oid).first::<PhotoSubmission>(conn)?)
+}
+
+// Approve a photo and move the player to the next step
+fn accept(conn: &mut DbConnection, oid: i32) -> Result<PhotoSubmission, ServerError> {
+    conn.transaction(|conn| {
+        use crate::schema::users::dsl::users;
+        let p = review(conn, oid, PhotoStatus::Approved)?;
+        let u = users.find(p.user_id).first::<User>(conn)?;
+        // The player may have been moved by an organizer in the meantime
+        if let Some(s) = step_of_player(conn, &u).optional()? {
+            if s.id == p.step_id {
+                if let Some(next) = step_at(conn, u.version_id, s.rank + 1).optional()? {
+                    move_player(conn, u.id, &next)?;
+                }
+            }
+        }
+        Ok(p)
+    })
+}
+
+#[utoipa::path(
+    summary = "Approve a photo and move the player to the next step",
+    responses((status = 200, body = PhotoSubmission)),
+    security(("token" = [])),
+)]
+#[post("/{oid}/approve")]
+pub async fn approve(
+    pool: web::Data<DbPool>,
+    oid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let oid = *oid;
+    let p = crate::db::run(&pool, move |conn| accept(conn, oid)).await?;
+    Ok(HttpResponse::Ok().json(p))
+}
+
+// Reject a photo, the player will have to submit another one
+#[utoipa::path(
+    summary = "Reject a photo",
+    responses((status = 200, body = PhotoSubmission)),
+    security(("token" = [])),
+)]
+#[post("/{oid}/reject")]
+pub async fn reject(
+    pool: web::Data<DbPool>,
+    oid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let oid = *oid;
+    let p = crate::db::run(&pool, move |conn| review(conn, oid, PhotoStatus::Rejected)).await?;
+    Ok(HttpResponse::Ok().json(p))
+}
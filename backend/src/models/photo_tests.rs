@@ -0,0 +1,312 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+
+pub async fn photo_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/users")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create a user, a photo step and a regular step
+    let id = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"Test name","password":"Test password"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let id1 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"take a selfie with the statue","answer":"","validation_mode":"Photo"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let id2 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"what is the color of the grass?","answer":"green"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+
+    // Try to advance the photo step with an answer (must fail)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","latitude":45.74846,"longitude":4.84671,"answer":"done"}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json("photo_required", "the step is validated with a photo")
+    );
+
+    let img_body = std::fs::read("test_img.jpg").unwrap();
+
+    // Submit a photo with the wrong password (must fail)
+    let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+        .method(Method::POST)
+        .insert_header(("password", "Wrong test password"))
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Submit a photo larger than the configured maximum, with the wrong password then with the
+    // right one (must fail, on the password before the size)
+    let mut small_config = AppConfig::new("0101".to_string(), true);
+    small_config.media_max_size = 8;
+    let small_config = actix_web::web::Data::new(small_config);
+    let small_app = test::init_service(create_app!(pool, &small_config)).await;
+    for (password, status) in [
+        ("Wrong test password", StatusCode::FORBIDDEN),
+        ("Test password", StatusCode::PAYLOAD_TOO_LARGE),
+    ] {
+        let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+            .method(Method::POST)
+            .insert_header(("password", password))
+            .set_payload(img_body.clone())
+            .to_request();
+        let resp = test::call_service(&small_app, req).await;
+        assert_eq!(resp.status(), status);
+    }
+
+    // Submit something that is not a photo (must fail)
+    let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+        .method(Method::POST)
+        .insert_header(("password", "Test password"))
+        .set_payload("not a photo")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Submit a photo
+    let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+        .method(Method::POST)
+        .insert_header(("password", "Test password"))
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    let pid: i32 = regex::Regex::new(r#""id":(\d+)"#)
+        .unwrap()
+        .captures(body)
+        .unwrap()[1]
+        .parse()
+        .unwrap();
+    assert!(body.contains(&format!(
+        r#""user_id":{id},"step_id":{id1},"status":"Pending""#
+    )));
+
+    // The current step must be awaiting validation
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{id}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"take a selfie with the statue","is_end":false,"show_bearing":false,"validation_mode":"Photo","awaiting_validation":true}}"#
+        )
+    );
+
+    // Try to advance or to submit another photo while waiting (must fail)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{id}/advance"),
+        r#"{"password":"Test password","answer":""}"#,
+        StatusCode::NOT_ACCEPTABLE,
+        error_json(
+            "awaiting_validation",
+            "the photo of the step is awaiting validation"
+        )
+    );
+    let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+        .method(Method::POST)
+        .insert_header(("password", "Test password"))
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+
+    // Get the queue without a token (must fail)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        "/api/photos",
+        "",
+        StatusCode::UNAUTHORIZED,
+        error_json("unauthorized", "authorization header is too short")
+    );
+
+    // Get the queue
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/photos",
+        "",
+        StatusCode::OK,
+        format!(r#"[{{"id":{pid},"user_id":{id},"step_id":{id1},"status":"Pending""#)
+    );
+
+    // Get the photo
+    let req = test::TestRequest::with_uri(&format!("/api/photos/{pid}/image"))
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Reject the photo
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        &format!("/api/photos/{pid}/reject"),
+        "",
+        StatusCode::OK,
+        format!(r#"{{"id":{pid},"user_id":{id},"step_id":{id1},"status":"Rejected""#)
+    );
+
+    // Review it again (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        &format!("/api/photos/{pid}/approve"),
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "this photo has already been reviewed")
+    );
+
+    // The queue must be empty, and the rejected photo listed as such
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/photos",
+        "",
+        StatusCode::OK,
+        "[]"
+    );
+    let rejected = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/photos?status=Rejected",
+        "",
+        StatusCode::OK,
+        "["
+    );
+    assert!(rejected.contains(&format!(
+        r#"{{"id":{pid},"user_id":{id},"step_id":{id1},"status":"Rejected""#
+    )));
+
+    // The current step must not be awaiting validation anymore
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{id}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(
+            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"take a selfie with the statue","is_end":false,"show_bearing":false,"validation_mode":"Photo"}}"#
+        )
+    );
+
+    // Submit another photo and approve it
+    let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+        .method(Method::POST)
+        .insert_header(("password", "Test password"))
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body = test::read_body(resp).await;
+    let pid: i32 = regex::Regex::new(r#""id":(\d+)"#)
+        .unwrap()
+        .captures(std::str::from_utf8(&body).unwrap())
+        .unwrap()[1]
+        .parse()
+        .unwrap();
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        &format!("/api/photos/{pid}/approve"),
+        "",
+        StatusCode::OK,
+        format!(r#"{{"id":{pid},"user_id":{id},"step_id":{id1},"status":"Approved""#)
+    );
+
+    // The player must be on the next step
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/users/{id}/current_step"),
+        "",
+        StatusCode::OK,
+        format!(r#"{{"id":{id2},"rank":2"#)
+    );
+
+    // Submit a photo for a step that does not expect one (must fail)
+    let req = test::TestRequest::with_uri(&format!("/api/users/{id}/photo"))
+        .method(Method::POST)
+        .insert_header(("password", "Test password"))
+        .set_payload(img_body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Delete all the users
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/users",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+
+    // Delete all the steps
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/steps",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+}
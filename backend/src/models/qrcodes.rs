@@ -0,0 +1,290 @@
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use image::{imageops::overlay, DynamicImage, GrayImage, ImageFormat, Luma};
+use qrcode::{render::svg, Color, QrCode};
+use std::io::Cursor;
+
+use crate::{
+    auth::Authenticated,
+    db::DbConnection,
+    errors::ServerError,
+    models::{
+        step::{self, Step},
+        user::{self, User},
+    },
+};
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+// Size of a rendered code, in pixels
+const CODE_SIZE: u32 = 256;
+// Number of codes per line on a sheet
+const SHEET_COLUMNS: u32 = 3;
+const SHEET_MARGIN: u32 = 32;
+const CAPTION_HEIGHT: u32 = 24;
+const CAPTION_SIZE: f32 = 16.0;
+// The captions of the PNG sheet are drawn with a font of their own, as no system font can be relied on
+const CAPTION_FONT: &[u8] = include_bytes!("../../fonts/DejaVuSans.ttf");
+
+enum Format {
+    Png,
+    Svg,
+}
+
+impl Format {
+    fn parse(ext: &str) -> Result<Self, ServerError> {
+        match ext {
+            "png" => Ok(Format::Png),
+            "svg" => Ok(Format::Svg),
+            _ => Err(ServerError::BadRequest(format!(
+                "unsupported format: {ext}"
+            ))),
+        }
+    }
+}
+
+// A code to print, with what it is for
+struct Labelled {
+    caption: String,
+    code: QrCode,
+}
+
+fn encode(data: &str) -> Result<QrCode, ServerError> {
+    QrCode::new(data.as_bytes()).map_err(|e| ServerError::Image(e.to_string()))
+}
+
+// The web app served with the API, which plays as the player given by `user`. Passwords are only
+// stored hashed, so the link cannot carry it: the player is asked for it when joining.
+fn join_link(req: &HttpRequest, user: &User) -> String {
+    let info = req.connection_info();
+    format!(
+        "{scheme}://{host}/?user={id}",
+        scheme = info.scheme(),
+        host = info.host(),
+        id = user.id
+    )
+}
+
+fn png_response(img: GrayImage) -> Result<HttpResponse, ServerError> {
+    let mut bytes = Cursor::new(Vec::new());
+    DynamicImage::ImageLuma8(img).write_to(&mut bytes, ImageFormat::Png)?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .body(bytes.into_inner()))
+}
+
+fn svg_response(svg: String) -> HttpResponse {
+    HttpResponse::Ok().content_type("image/svg+xml").body(svg)
+}
+
+fn render(code: &QrCode, format: Format) -> Result<HttpResponse, ServerError> {
+    match format {
+        Format::Png => png_response(
+            code.render::<Luma<u8>>()
+                .min_dimensions(CODE_SIZE, CODE_SIZE)
+                .build(),
+        ),
+        Format::Svg => Ok(svg_response(
+            code.render::<svg::Color>()
+                .min_dimensions(CODE_SIZE, CODE_SIZE)
+                .build(),
+        )),
+    }
+}
+
+// Draw the dark modules of a code as a single path, scaled to the code size and shifted to the given position
+fn svg_path(code: &QrCode, x: u32, y: u32) -> String {
+    let width = code.width();
+    let scale = CODE_SIZE as f64 / width as f64;
+    let mut d = String::new();
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Dark {
+            d.push_str(&format!("M{},{}h1v1h-1z", i % width, i / width));
+        }
+    }
+    format!(r#"<path transform="translate({x},{y}) scale({scale})" d="{d}"/>"#)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Draw a caption centered on the given position of its baseline, with the font of the PNG sheet
+fn draw_caption(page: &mut GrayImage, font: &FontRef, text: &str, center: u32, baseline: u32) {
+    let font = font.as_scaled(PxScale::from(CAPTION_SIZE));
+    let mut glyphs = Vec::new();
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(p) = previous {
+            width += font.kern(p, id);
+        }
+        glyphs.push(id.with_scale_and_position(font.scale(), point(width, 0.0)));
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    let left = center as f32 - width / 2.0;
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, coverage| {
+            let x = left + bounds.min.x + x as f32;
+            let y = baseline as f32 + bounds.min.y + y as f32;
+            if x < 0.0 || y < 0.0 || x >= page.width() as f32 || y >= page.height() as f32 {
+                return;
+            }
+            let pixel = page.get_pixel_mut(x as u32, y as u32);
+            pixel.0[0] = pixel.0[0].min((255.0 * (1.0 - coverage)) as u8);
+        });
+    }
+}
+
+fn render_sheet(codes: &[Labelled], format: Format) -> Result<HttpResponse, ServerError> {
+    let cell_width = CODE_SIZE + SHEET_MARGIN;
+    let cell_height = CODE_SIZE + CAPTION_HEIGHT + SHEET_MARGIN;
+    let lines = (codes.len() as u32).div_ceil(SHEET_COLUMNS).max(1);
+    let width = SHEET_COLUMNS * cell_width + SHEET_MARGIN;
+    let height = lines * cell_height + SHEET_MARGIN;
+    let position = |i: usize| {
+        (
+            SHEET_MARGIN + (i as u32 % SHEET_COLUMNS) * cell_width,
+            SHEET_MARGIN + (i as u32 / SHEET_COLUMNS) * cell_height,
+        )
+    };
+    match format {
+        Format::Png => {
+            let font = FontRef::try_from_slice(CAPTION_FONT)
+                .map_err(|e| ServerError::Image(e.to_string()))?;
+            let mut page = GrayImage::from_pixel(width, height, Luma([255]));
+            for (i, l) in codes.iter().enumerate() {
+                let (x, y) = position(i);
+                let img = l
+                    .code
+                    .render::<Luma<u8>>()
+                    .quiet_zone(false)
+                    .max_dimensions(CODE_SIZE, CODE_SIZE)
+                    .build();
+                overlay(&mut page, &img, x as i64, y as i64);
+                draw_caption(
+                    &mut page,
+                    &font,
+                    &l.caption,
+                    x + CODE_SIZE / 2,
+                    y + CODE_SIZE + CAPTION_HEIGHT - 4,
+                );
+            }
+            png_response(page)
+        }
+        Format::Svg => {
+            let mut svg = format!(
+                r#"<?xml version="1.0" standalone="yes"?><svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{width}" height="{height}" viewBox="0 0 {width} {height}"><rect width="100%" height="100%" fill="white"/>"#
+            );
+            for (i, l) in codes.iter().enumerate() {
+                let (x, y) = position(i);
+                svg.push_str(&svg_path(&l.code, x, y));
+                svg.push_str(&format!(
+                    r#"<text x="{cx}" y="{cy}" font-family="sans-serif" font-size="{CAPTION_SIZE}" text-anchor="middle">{caption}</text>"#,
+                    cx = x + CODE_SIZE / 2,
+                    cy = y + CODE_SIZE + CAPTION_HEIGHT - 4,
+                    caption = escape(&l.caption)
+                ));
+            }
+            svg.push_str("</svg>");
+            Ok(svg_response(svg))
+        }
+    }
+}
+
+#[utoipa::path(
+    summary = "Render the secret code of a step",
+    params(("ext" = String, Path, description = "`png` or `svg`")),
+    responses((status = 200, content((Vec<u8> = "image/png"), (String = "image/svg+xml")))),
+    security(("token" = [])),
+)]
+#[get("/steps/{oid}.{ext}")]
+pub async fn step_code(
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, String)>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let (oid, ext) = path.into_inner();
+    let format = Format::parse(&ext)?;
+    let s = crate::db::run(&pool, move |conn| step::find(conn, oid)).await?;
+    let secret_code = s
+        .secret_code
+        .ok_or(ServerError::NotFound("step has no secret code".to_string()))?;
+    render(&encode(&secret_code)?, format)
+}
+
+#[utoipa::path(
+    summary = "Render the link a player joins the hunt with",
+    params(("ext" = String, Path, description = "`png` or `svg`")),
+    responses((status = 200, content((Vec<u8> = "image/png"), (String = "image/svg+xml")))),
+    security(("token" = [])),
+)]
+#[get("/users/{oid}.{ext}")]
+pub async fn user_code(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, String)>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let (oid, ext) = path.into_inner();
+    let format = Format::parse(&ext)?;
+    let u = crate::db::run(&pool, move |conn| user::find(conn, oid)).await?;
+    render(&encode(&join_link(&req, &u))?, format)
+}
+
+// The steps with a secret code, by rank, and the players
+fn sheet_contents(conn: &mut DbConnection) -> QueryResult<(Vec<Step>, Vec<User>)> {
+    use crate::schema::{steps, users};
+    let s = steps::table
+        .filter(steps::secret_code.is_not_null())
+        .order(steps::rank.asc())
+        .load::<Step>(conn)?;
+    let u = users::table.order(users::id.asc()).load::<User>(conn)?;
+    Ok((s, u))
+}
+
+// Every code of the hunt on one page, each with a caption: the steps secret codes, by rank, then
+// the players join links
+#[utoipa::path(
+    summary = "Render every code of the hunt on one page",
+    params(("ext" = String, Path, description = "`png` or `svg`")),
+    responses((status = 200, content((Vec<u8> = "image/png"), (String = "image/svg+xml")))),
+    security(("token" = [])),
+)]
+#[get("/sheet.{ext}")]
+pub async fn sheet(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    ext: web::Path<String>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let format = Format::parse(&ext)?;
+    let (steps, users) = crate::db::run(&pool, sheet_contents).await?;
+    let mut codes = Vec::new();
+    for s in steps {
+        if let Some(secret_code) = &s.secret_code {
+            codes.push(Labelled {
+                caption: format!("Step {}", s.rank),
+                code: encode(secret_code)?,
+            });
+        }
+    }
+    for u in users {
+        codes.push(Labelled {
+            caption: u.name.clone(),
+            code: encode(&join_link(&req, &u))?,
+        });
+    }
+    render_sheet(&codes, format)
+}
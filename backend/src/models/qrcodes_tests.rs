@@ -0,0 +1,190 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+
+pub async fn qrcodes_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/users")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create a user, a regular step and a secret code step
+    let uid = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"Test name","password":"Test password"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let id1 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let id2 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"find the sticker","question":"what is the code?","answer":"","validation_mode":"SecretCode","secret_code":"ABC123"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+
+    // Get a code without a token (must fail)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/qrcodes/steps/{id2}.png"),
+        "",
+        StatusCode::UNAUTHORIZED,
+        error_json("unauthorized", "authorization header is too short")
+    );
+
+    // Get a code in an unsupported format (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        &format!("/api/qrcodes/steps/{id2}.gif"),
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "unsupported format: gif")
+    );
+
+    // Get the code of a step without secret code (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        &format!("/api/qrcodes/steps/{id1}.png"),
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "step has no secret code")
+    );
+
+    // Get the code of the secret code step as PNG
+    let req = test::TestRequest::with_uri(&format!("/api/qrcodes/steps/{id2}.png"))
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/png");
+    let body = test::read_body(resp).await;
+    let img = image::load_from_memory(&body).unwrap();
+    assert!(img.width() >= 256 && img.width() == img.height());
+
+    // Get the code of the secret code step as SVG
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        &format!("/api/qrcodes/steps/{id2}.svg"),
+        "",
+        StatusCode::OK,
+        r#"<?xml version="1.0" standalone="yes"?><svg"#
+    );
+
+    // Get the join link of the user
+    let req = test::TestRequest::with_uri(&format!("/api/qrcodes/users/{uid}.png"))
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Get the join link of a non existing user (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        &format!("/api/qrcodes/users/{}.png", uid + 1),
+        "",
+        StatusCode::NOT_FOUND,
+        error_json("not_found", "Item not found")
+    );
+
+    // Get the printable sheet as SVG, with a caption for each code
+    let sheet = do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/qrcodes/sheet.svg",
+        "",
+        StatusCode::OK,
+        r#"<?xml version="1.0" standalone="yes"?><svg"#
+    );
+    assert!(sheet.contains(">Step 2</text>"));
+    assert!(!sheet.contains(">Step 1</text>"));
+    assert!(sheet.contains(">Test name</text>"));
+    assert!(sheet.contains(r#"width="896" height="344""#));
+
+    // Get the printable sheet as PNG, with a caption drawn under each code
+    let req = test::TestRequest::with_uri("/api/qrcodes/sheet.png")
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/png");
+    let body = test::read_body(resp).await;
+    let img = image::load_from_memory(&body).unwrap().to_luma8();
+    assert_eq!(img.dimensions(), (896, 344));
+    let caption = (32..288).flat_map(|x| (288..312).map(move |y| (x, y)));
+    assert!(caption
+        .into_iter()
+        .any(|(x, y)| img.get_pixel(x, y).0[0] < 128));
+
+    // Get the printable sheet in an unsupported format (must fail)
+    do_test!(
+        app,
+        "0101",
+        Method::GET,
+        "/api/qrcodes/sheet.pdf",
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "unsupported format: pdf")
+    );
+
+    // Delete all the users
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/users",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+
+    // Delete all the steps
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/steps",
+        "",
+        StatusCode::OK,
+        "Deleted all objects"
+    );
+}
@@ -0,0 +1,237 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+use diesel::prelude::*;
+
+use crate::db::DbPool;
+
+// Ids of the steps, ordered by rank, checking that the ranks go from 1 without gaps nor duplicates
+fn ordered_ids(pool: &DbPool) -> Vec<i32> {
+    use crate::schema::steps::dsl::*;
+    let ranked = steps
+        .order(rank.asc())
+        .select((id, rank))
+        .load::<(i32, i32)>(&mut pool.get().unwrap())
+        .unwrap();
+    for (i, (_, r)) in ranked.iter().enumerate() {
+        assert_eq!(*r, i as i32 + 1, "ranks are not gap free: {ranked:?}");
+    }
+    ranked.into_iter().map(|(i, _)| i).collect()
+}
+
+fn new_step(rank: i32) -> String {
+    format!(
+        r#"{{"rank":{rank},"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}}"#
+    )
+}
+
+pub async fn rank_test(pool: &DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use futures_util::future::join_all;
+
+    let app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create steps concurrently, all at the first rank
+    let created = join_all((0..8).map(|_| {
+        let req = test::TestRequest::with_uri("/api/steps")
+            .method(Method::POST)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(new_step(1))
+            .to_request();
+        test::call_and_read_body_json::<_, _, crate::models::step::Step>(&app, req)
+    }))
+    .await;
+    let ids = ordered_ids(pool);
+    assert_eq!(ids.len(), 8);
+    for s in &created {
+        assert!(ids.contains(&s.id));
+    }
+
+    // Move every step concurrently, the ranks must stay gap free
+    let moved = join_all(created.iter().enumerate().map(|(i, s)| {
+        let mut s = s.clone();
+        s.rank = (i as i32 * 3) % 8 + 1;
+        let req = test::TestRequest::with_uri(&format!("/api/steps/{}", s.id))
+            .method(Method::PUT)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .set_json(s)
+            .to_request();
+        test::call_service(&app, req)
+    }))
+    .await;
+    for resp in moved {
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    assert_eq!(ordered_ids(pool).len(), 8);
+
+    // Delete half of the steps while creating others in the middle
+    let deletes = ids[..4].iter().map(|id| {
+        test::TestRequest::with_uri(&format!("/api/steps/{id}"))
+            .method(Method::DELETE)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request()
+    });
+    let creates = (0..4).map(|_| {
+        test::TestRequest::with_uri("/api/steps")
+            .method(Method::POST)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(new_step(2))
+            .to_request()
+    });
+    let resps = join_all(
+        deletes
+            .chain(creates)
+            .map(|req| test::call_service(&app, req)),
+    )
+    .await;
+    for resp in resps {
+        assert!(resp.status().is_success());
+    }
+    let ids = ordered_ids(pool);
+    assert_eq!(ids.len(), 8);
+
+    // Two steps cannot share a rank
+    {
+        use crate::schema::steps::dsl::*;
+        let duplicated = diesel::update(steps.find(ids[1]))
+            .set(rank.eq(1))
+            .execute(&mut pool.get().unwrap());
+        assert!(matches!(
+            duplicated,
+            Err(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _
+            ))
+        ));
+    }
+
+    // Reorder the steps without a token (must fail)
+    let reorder = |order: &[i32], token: &str| {
+        test::TestRequest::with_uri("/api/steps/order")
+            .method(Method::PUT)
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .set_json(order)
+            .to_request()
+    };
+    let reversed: Vec<i32> = ids.iter().rev().copied().collect();
+    let resp = test::call_service(&app, reorder(&reversed, "")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Reorder the steps with a missing, a duplicate or an unknown id (must fail)
+    let unknown = ids.iter().max().unwrap() + 1;
+    for (order, message) in [
+        (
+            reversed[1..].to_vec(),
+            format!("missing step: {}", reversed[0]),
+        ),
+        (
+            [&reversed[..], &reversed[..1]].concat(),
+            format!("duplicate step: {}", reversed[0]),
+        ),
+        (
+            [&reversed[1..], &[unknown]].concat(),
+            format!("unknown step: {unknown}"),
+        ),
+    ] {
+        let resp = test::call_service(&app, reorder(&order, "0101")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(resp).await,
+            error_json("bad_request", &message)
+        );
+        assert_eq!(ordered_ids(pool), ids);
+    }
+
+    // Reverse the order of the steps, the renumbered steps are returned
+    let reordered: Vec<crate::models::step::Step> =
+        test::call_and_read_body_json(&app, reorder(&reversed, "0101")).await;
+    assert_eq!(
+        reordered.iter().map(|s| (s.id, s.rank)).collect::<Vec<_>>(),
+        reversed
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i as i32 + 1))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(ordered_ids(pool), reversed);
+
+    // Put a player on the third step
+    let req = test::TestRequest::with_uri("/api/users")
+        .method(Method::POST)
+        .set_json(serde_json::json!({"name":"player","password":"pass"}))
+        .to_request();
+    let mut player: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let uid = player["id"].clone();
+    assert_eq!(player["step_id"], reversed[0]);
+    player["password"] = "".into();
+    player["current_step"] = 3.into();
+    let req = test::TestRequest::with_uri(&format!("/api/users/{uid}"))
+        .method(Method::PUT)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_json(&player)
+        .to_request();
+    let player: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let solving = reversed[2];
+    assert_eq!(player["step_id"], solving);
+
+    // The player stays on the same step while the steps are reordered, inserted or deleted around it
+    let progress = |position: usize, sid: i32| {
+        let app = &app;
+        let uid = uid.clone();
+        async move {
+            let req = test::TestRequest::with_uri(&format!("/api/users/{uid}"))
+                .insert_header(("Authorization", "Bearer 0101"))
+                .to_request();
+            let player: serde_json::Value = test::call_and_read_body_json(app, req).await;
+            assert_eq!(player["step_id"], sid);
+            assert_eq!(player["current_step"], position);
+            let req =
+                test::TestRequest::with_uri(&format!("/api/users/{uid}/current_step")).to_request();
+            let s: serde_json::Value = test::call_and_read_body_json(app, req).await;
+            assert_eq!(s["id"], sid);
+        }
+    };
+    test::call_service(&app, reorder(&ids, "0101")).await;
+    let position = ids.iter().position(|i| *i == solving).unwrap() + 1;
+    progress(position, solving).await;
+    let req = test::TestRequest::with_uri("/api/steps")
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .insert_header(("content-type", "application/json"))
+        .set_payload(new_step(1))
+        .to_request();
+    test::call_service(&app, req).await;
+    progress(position + 1, solving).await;
+
+    // When the step is deleted, the player moves to the one that took its place
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{solving}"))
+        .method(Method::DELETE)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    test::call_service(&app, req).await;
+    progress(position + 1, ordered_ids(pool)[position]).await;
+
+    // Delete the player
+    let req = test::TestRequest::with_uri(&format!("/api/users/{uid}"))
+        .method(Method::DELETE)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+}
@@ -1,20 +1,45 @@
-use actix_files::NamedFile;
-
-use actix_web::{head, Result};
+use actix_web::{
+    head,
+    http::header::{self, HeaderValue},
+    HttpRequest, Result,
+};
 use futures_util::StreamExt;
-use image::GenericImageView;
+use image::{DynamicImage, ImageDecoder, ImageReader};
 use std::{
-    cmp::Ordering,
+    collections::{HashMap, HashSet},
     fs::{self, create_dir_all, remove_file, File},
-    io::Write,
-    path::PathBuf,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    process::Command,
 };
 
-use image::imageops::FilterType::Lanczos3;
+use log::{info, warn};
+
+use diesel::{
+    backend::Backend,
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+};
+use image::{imageops::FilterType::Lanczos3, ImageError, ImageFormat};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    crud_delete_all, crud_read, crud_read_all, crud_use, errors::ServerError, schema::steps,
+    auth::AppConfig,
+    crud_read_all, crud_use,
+    db::DbConnection,
+    errors::ServerError,
+    models::{
+        asset::{self, Asset},
+        crud::{etag, merged, Precondition},
+        version,
+    },
+    schema::{medias, steps},
+    sniff::{sniff, MediaType, SNIFF_LEN},
+    storage::{serve, Storage},
+    utils::{now, random_string},
 };
 
 macro_rules! trim {
@@ -24,13 +49,104 @@ macro_rules! trim {
             self.question = self.question.trim().to_string();
             self.answer = self.answer.trim().to_string();
             self.shake_message = self.shake_message.as_ref().map(|v| v.trim().to_string());
+            self.secret_code = self.secret_code.as_ref().map(|v| v.trim().to_string());
             self
         }
     };
 }
 
+macro_rules! validate {
+    () => {
+        fn validate(&self) -> Result<(), ServerError> {
+            if self.validation_mode == ValidationMode::SecretCode
+                && self
+                    .secret_code
+                    .as_deref()
+                    .unwrap_or_default()
+                    .trim()
+                    .is_empty()
+            {
+                return Err(ServerError::BadRequest(
+                    "secret_code cannot be empty for a SecretCode step".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    };
+}
+
+// What a player must provide to validate a step
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+    ToSchema,
+)]
+#[diesel(sql_type = Text)]
+pub enum ValidationMode {
+    #[default]
+    LocationAndAnswer,
+    LocationOnly,
+    AnswerOnly,
+    SecretCode,
+    // Validated by an organizer, from a photo uploaded by the player
+    Photo,
+}
+
+impl ValidationMode {
+    pub fn checks_location(&self) -> bool {
+        matches!(
+            self,
+            ValidationMode::LocationAndAnswer | ValidationMode::LocationOnly
+        )
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ValidationMode::LocationAndAnswer => "LocationAndAnswer",
+            ValidationMode::LocationOnly => "LocationOnly",
+            ValidationMode::AnswerOnly => "AnswerOnly",
+            ValidationMode::SecretCode => "SecretCode",
+            ValidationMode::Photo => "Photo",
+        }
+    }
+}
+
+impl<DB> ToSql<Text, DB> for ValidationMode
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for ValidationMode
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        match String::from_sql(bytes)?.as_str() {
+            "LocationAndAnswer" => Ok(ValidationMode::LocationAndAnswer),
+            "LocationOnly" => Ok(ValidationMode::LocationOnly),
+            "AnswerOnly" => Ok(ValidationMode::AnswerOnly),
+            "SecretCode" => Ok(ValidationMode::SecretCode),
+            "Photo" => Ok(ValidationMode::Photo),
+            v => Err(format!("unknown validation mode: {}", v).into()),
+        }
+    }
+}
+
 #[derive(
-    Debug, Clone, Serialize, Deserialize, Queryable, Insertable, AsChangeset, Identifiable,
+    Debug, Clone, Serialize, Deserialize, Queryable, Insertable, AsChangeset, Identifiable, ToSchema,
 )]
 #[diesel(table_name = steps, treat_none_as_null = true)]
 pub struct Step {
@@ -42,51 +158,107 @@ pub struct Step {
     pub question: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shake_message: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub answer: String,
     #[serde(default)]
     pub is_end: bool,
+    #[serde(default)]
+    pub show_bearing: bool,
+    #[serde(default)]
+    pub validation_mode: ValidationMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_code: Option<String>,
+    // Raised by every change, sent as the ETag
+    #[serde(default, skip_serializing)]
+    pub revision: i32,
 }
 
-fn rerank(
-    conn: &mut r2d2::PooledConnection<ConnectionManager<SqliteConnection>>,
-    priority_id: Option<(i32, Ordering)>,
-) -> Result<(), diesel::result::Error> {
+// Number the steps from 1, optionally moving one of them to a new rank first. Ranks are unique,
+// so the steps are parked on negative ranks while being renumbered. Must run in a transaction.
+fn rerank(conn: &mut DbConnection, moved: Option<(i32, i32)>) -> Result<(), diesel::result::Error> {
     use crate::schema::steps::dsl::*;
-    let mut i = 0;
-    // get the steps
-    let mut steps_vec = steps.order(rank.asc()).load::<Step>(conn)?;
-    // if there is a force rank step, make sure that is before the step with the same rank
-    if let Some((pid, order)) = priority_id {
-        steps_vec.sort_by(|a, b| {
-            if a.rank < b.rank {
-                Ordering::Less
-            } else if a.rank == b.rank {
-                if a.id == pid {
-                    order
-                } else {
-                    order.reverse()
-                }
-            } else {
-                Ordering::Greater
-            }
-        });
+    let mut ids = steps
+        .order((rank.asc(), id.asc()))
+        .select(id)
+        .load::<i32>(conn)?;
+    if let Some((sid, new_rank)) = moved {
+        ids.retain(|i| *i != sid);
+        let index = (new_rank - 1).clamp(0, ids.len() as i32) as usize;
+        ids.insert(index, sid);
     }
-    for v in steps_vec {
-        i = i + 1;
-        // alter the steps
-        diesel::update(steps)
-            .filter(id.eq(v.id))
-            .set(rank.eq(i))
+    apply_ranks(conn, &ids)
+}
+
+// Rank the steps after their position in the list, which must hold them all
+fn apply_ranks(conn: &mut DbConnection, ids: &[i32]) -> Result<(), diesel::result::Error> {
+    use crate::schema::steps::dsl::*;
+    let ranks: HashMap<i32, i32> = steps
+        .select((id, rank))
+        .load::<(i32, i32)>(conn)?
+        .into_iter()
+        .collect();
+    diesel::update(steps).set(rank.eq(id * -1)).execute(conn)?;
+    for (i, sid) in ids.iter().enumerate() {
+        let new_rank = i as i32 + 1;
+        // Only the steps that moved change revision
+        let moved = ranks.get(sid) != Some(&new_rank);
+        diesel::update(steps.find(sid))
+            .set((rank.eq(new_rank), revision.eq(revision + moved as i32)))
             .execute(conn)?;
     }
+    follow_steps(conn)
+}
+
+// Keep the players following the draft on the step they were solving: their position follows the
+// step, and the players of a deleted step go to the one that took its place
+fn follow_steps(conn: &mut DbConnection) -> Result<(), diesel::result::Error> {
+    use crate::schema::users::dsl::*;
+    let ranks: HashMap<i32, i32> = steps::table
+        .select((steps::id, steps::rank))
+        .load::<(i32, i32)>(conn)?
+        .into_iter()
+        .collect();
+    let at_rank: HashMap<i32, i32> = ranks.iter().map(|(sid, r)| (*r, *sid)).collect();
+    let players = users
+        .filter(version_id.is_null())
+        .select((id, step_id, current_step))
+        .load::<(i32, Option<i32>, i32)>(conn)?;
+    for (uid, sid, position) in players {
+        let (new_sid, new_position) = match sid.and_then(|sid| ranks.get(&sid)) {
+            Some(r) => (sid, *r),
+            None => (at_rank.get(&position).copied(), position),
+        };
+        if new_sid != sid || new_position != position {
+            diesel::update(users.find(uid))
+                .set((
+                    step_id.eq(new_sid),
+                    current_step.eq(new_position),
+                    revision.eq(revision + 1),
+                ))
+                .execute(conn)?;
+        }
+    }
     Ok(())
 }
 
+// Whether a step is in the draft
+pub(crate) fn exists(conn: &mut DbConnection, oid: i32) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(steps::table.find(oid))).get_result(conn)
+}
+
 impl Step {
     trim!();
+    validate!();
+
+    // Hide what a player must not see before reaching the step
+    pub fn for_player(mut self) -> Self {
+        self.answer.clear();
+        self.secret_code = None;
+        self
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Insertable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Insertable, ToSchema)]
 #[diesel(table_name = steps)]
 pub struct NewStep {
     pub rank: i32,
@@ -98,227 +270,1224 @@ pub struct NewStep {
     pub answer: String,
     #[serde(default)]
     pub is_end: bool,
+    #[serde(default)]
+    pub show_bearing: bool,
+    #[serde(default)]
+    pub validation_mode: ValidationMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_code: Option<String>,
 }
 
 impl NewStep {
     trim!();
+    validate!();
 }
 
 crud_use!();
 
+pub fn insert(conn: &mut DbConnection, mut o: NewStep) -> QueryResult<Step> {
+    o.trim();
+    conn.immediate_transaction(|conn| {
+        use crate::schema::steps::dsl::*;
+        // Insert the step last, then move it to the wanted rank
+        let wanted_rank = o.rank;
+        o.rank = steps
+            .select(diesel::dsl::max(rank))
+            .first::<Option<i32>>(conn)?
+            .unwrap_or(0)
+            + 1;
+        let s = diesel::insert_into(steps)
+            .values(&o)
+            .get_result::<Step>(conn)?;
+        rerank(conn, Some((s.id, wanted_rank)))?;
+        steps.find(s.id).first::<Step>(conn)
+    })
+}
+
+#[utoipa::path(
+    summary = "Insert a step",
+    description = "The steps from its rank on move one rank down",
+    responses((status = 201, body = Step, headers(("ETag" = String)))),
+    security(("token" = [])),
+)]
 #[post("")]
 pub async fn create(
     pool: web::Data<DbPool>,
-    mut o: web::Json<NewStep>,
+    o: web::Json<NewStep>,
     _: Authenticated,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
-    let s = web::block(move || {
+    o.validate()?;
+    let o = o.into_inner();
+    let s = crate::db::run(&pool, move |conn| insert(conn, o)).await?;
+    Ok(HttpResponse::Created()
+        .insert_header(etag(s.revision))
+        .json(s))
+}
+
+// The media and the assets of a step, whose records are deleted with it
+pub struct StepFiles {
+    media: Option<Media>,
+    assets: Vec<Asset>,
+}
+
+fn step_files_of(conn: &mut DbConnection, oid: i32) -> QueryResult<StepFiles> {
+    Ok(StepFiles {
+        media: medias::table.find(oid).first::<Media>(conn).optional()?,
+        assets: asset::find_all(conn, oid)?,
+    })
+}
+
+// Delete a step and rank the others again, giving its files
+pub fn remove(conn: &mut DbConnection, oid: i32) -> QueryResult<StepFiles> {
+    conn.immediate_transaction(|conn| {
         use crate::schema::steps::dsl::*;
-        o.trim();
-        diesel::insert_into(steps).values(&*o).execute(&mut conn)?;
-        // Renumber the steps
-        let s = steps.order(id.desc()).first::<Step>(&mut conn)?;
-        rerank(&mut conn, Some((s.id, Ordering::Less)))?;
-        steps.order(id.desc()).first::<Step>(&mut conn)
+        let files = step_files_of(conn, oid)?;
+        let deleted = diesel::delete(steps).filter(id.eq(oid)).execute(conn)?;
+        if deleted == 0 {
+            return Err(diesel::result::Error::NotFound);
+        }
+        rerank(conn, None)?;
+        Ok(files)
     })
-    .await??;
-    Ok(HttpResponse::Created().json(s))
 }
 
+// Remove the files of the media and of the assets of a deleted step
+fn remove_files(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    oid: i32,
+    files: &StepFiles,
+) -> Result<(), ServerError> {
+    if let Some(m) = &files.media {
+        let _ = storage.delete(&media_key(oid, &m.extension));
+    }
+    remove_low_bitrate(storage, oid);
+    asset::release(conn, storage, &files.assets)
+}
+
+#[utoipa::path(
+    summary = "Delete a step",
+    description = "The steps after it move one rank up",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
 #[delete("/{oid}")]
 pub async fn delete(
     pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
     oid: web::Path<i32>,
     _: Authenticated,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
     let oid = *oid;
-    web::block(move || {
+    let files = crate::db::run(&pool, move |conn| remove(conn, oid)).await?;
+    // The published versions have their own copy of the files
+    remove_all_files(&pool, &app_config, oid, files).await?;
+    Ok(HttpResponse::Ok().body(format!("Deleted object with id: {}", oid)))
+}
+
+// Remove the images, the media and the assets of a deleted step
+async fn remove_all_files(
+    pool: &DbPool,
+    app_config: &AppConfig,
+    oid: i32,
+    files: StepFiles,
+) -> Result<(), ServerError> {
+    let storage = app_config.storage.clone();
+    let _ = web::block(move || remove_images(storage.as_ref(), oid)).await;
+    let storage = app_config.storage.clone();
+    crate::db::run(pool, move |conn| {
+        remove_files(conn, storage.as_ref(), oid, &files)
+    })
+    .await
+}
+
+// Delete every step, giving their ids and their files
+pub fn remove_all(conn: &mut DbConnection) -> QueryResult<Vec<(i32, StepFiles)>> {
+    conn.immediate_transaction(|conn| {
         use crate::schema::steps::dsl::*;
-        let deleted = diesel::delete(steps)
-            .filter(id.eq(oid))
-            .execute(&mut conn)?;
-        rerank(&mut conn, None)?;
-        match deleted {
-            0 => Err(diesel::result::Error::NotFound),
-            _ => Ok(deleted),
+        let ids = steps.select(id).load::<i32>(conn)?;
+        if ids.is_empty() {
+            return Err(diesel::result::Error::NotFound);
         }
+        let files = ids
+            .into_iter()
+            .map(|oid| Ok((oid, step_files_of(conn, oid)?)))
+            .collect::<QueryResult<Vec<_>>>()?;
+        diesel::delete(steps).execute(conn)?;
+        Ok(files)
     })
-    .await??;
-    let _ = web::block(move || remove_file(image_filename(oid))).await;
-    if let Some(media_filename) = media_filename_out(oid) {
-        let _ = web::block(move || remove_file(media_filename)).await;
+}
+
+#[utoipa::path(
+    summary = "Delete all",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
+#[delete("")]
+pub async fn delete_all(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let deleted = crate::db::run(&pool, remove_all).await?;
+    for (oid, files) in deleted {
+        remove_all_files(&pool, &app_config, oid, files).await?;
     }
-    Ok(HttpResponse::Ok().body(format!("Deleted object with id: {}", oid)))
+    Ok(HttpResponse::Ok().body("Deleted all objects"))
 }
 
-#[put("/{oid}")]
-pub async fn update(
+// Rank the steps from the list of their ids, which must hold every step exactly once
+pub fn reorder_steps(conn: &mut DbConnection, order: &[i32]) -> Result<Vec<Step>, ServerError> {
+    conn.immediate_transaction(|conn| {
+        use crate::schema::steps::dsl::*;
+        let existing: HashSet<i32> = steps.select(id).load::<i32>(conn)?.into_iter().collect();
+        let mut seen = HashSet::new();
+        for sid in order.iter() {
+            if !existing.contains(sid) {
+                return Err(ServerError::BadRequest(format!("unknown step: {sid}")));
+            }
+            if !seen.insert(*sid) {
+                return Err(ServerError::BadRequest(format!("duplicate step: {sid}")));
+            }
+        }
+        if let Some(sid) = existing.iter().filter(|sid| !seen.contains(sid)).min() {
+            return Err(ServerError::BadRequest(format!("missing step: {sid}")));
+        }
+        apply_ranks(conn, order)?;
+        Ok(steps.order(rank.asc()).load::<Step>(conn)?)
+    })
+}
+
+// Reorder all the steps at once from the list of their ids, which must hold every step exactly once
+#[utoipa::path(
+    summary = "Reorder all the steps",
+    request_body(content = Vec<i32>, description = "The ids of every step, in their new order"),
+    responses((status = 200, body = Vec<Step>, description = "Every step, in their new order")),
+    security(("token" = [])),
+)]
+#[put("/order")]
+pub async fn reorder(
     pool: web::Data<DbPool>,
-    mut o: web::Json<Step>,
-    oid: web::Path<i32>,
+    order: web::Json<Vec<i32>>,
     _: Authenticated,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
-    o.trim();
-    let put_o = web::block(move || {
+    let ordered = crate::db::run(&pool, move |conn| reorder_steps(conn, &order)).await?;
+    Ok(HttpResponse::Ok().json(ordered))
+}
+
+// Replace a step by what the change makes of it, unless it changed since the client read it
+fn change(
+    conn: &mut DbConnection,
+    oid: i32,
+    precondition: &Precondition,
+    change: impl FnOnce(&Step) -> Result<Step, ServerError>,
+) -> Result<Step, ServerError> {
+    conn.immediate_transaction(|conn| {
         use crate::schema::steps::dsl::*;
-        // Get the initial rank to work out the ordering of the
-        let initial_rank = steps.filter(id.eq(*oid)).first::<Step>(&mut conn)?.rank;
-        let ordering = if o.rank > initial_rank {
-            Ordering::Greater
-        } else {
-            Ordering::Less
-        };
-        println!("=============================O: {:?}", o);
+        let s = steps.find(oid).first::<Step>(conn)?;
+        precondition.check(s.revision)?;
+        let mut o = change(&s)?;
+        o.trim();
+        o.validate()?;
+        o.id = oid;
+        o.revision = s.revision + 1;
+        // Keep the current rank, which is unique, until the step is moved to the wanted one
+        let wanted_rank = o.rank;
+        o.rank = s.rank;
         diesel::update(steps)
-            .filter(id.eq(*oid))
-            .set(&*o)
-            .execute(&mut conn)?;
-        rerank(&mut conn, Some((*oid, ordering)))?;
-        steps.filter(id.eq(*oid)).first::<Step>(&mut conn)
+            .filter(id.eq(oid))
+            .set(&o)
+            .execute(conn)?;
+        rerank(conn, Some((oid, wanted_rank)))?;
+        Ok(steps.find(oid).first::<Step>(conn)?)
     })
-    .await??;
-    Ok(HttpResponse::Ok().json(put_o))
 }
 
-crud_read_all!(Step, steps);
-crud_read!(Step, steps);
-crud_delete_all!(Step, steps);
+pub fn save(
+    conn: &mut DbConnection,
+    oid: i32,
+    o: Step,
+    precondition: &Precondition,
+) -> Result<Step, ServerError> {
+    change(conn, oid, precondition, |_| Ok(o))
+}
+
+// Apply a JSON merge patch to a step
+pub fn patch_step(
+    conn: &mut DbConnection,
+    oid: i32,
+    p: &serde_json::Value,
+    precondition: &Precondition,
+) -> Result<Step, ServerError> {
+    change(conn, oid, precondition, |s| merged(s, p))
+}
+
+#[utoipa::path(
+    summary = "Replace a step",
+    params(("If-Match" = Option<String>, Header)),
+    responses((status = 200, body = Step, headers(("ETag" = String)))),
+    security(("token" = [])),
+)]
+#[put("/{oid}")]
+pub async fn update(
+    pool: web::Data<DbPool>,
+    o: web::Json<Step>,
+    oid: web::Path<i32>,
+    precondition: Precondition,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let (oid, o) = (*oid, o.into_inner());
+    let updated = crate::db::run(&pool, move |conn| save(conn, oid, o, &precondition)).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(updated.revision))
+        .json(updated))
+}
+
+#[utoipa::path(
+    summary = "Change some fields of a step",
+    description = "A JSON merge patch, where `null` removes a field",
+    params(("If-Match" = Option<String>, Header)),
+    request_body = Object,
+    responses((status = 200, body = Step, headers(("ETag" = String)))),
+    security(("token" = [])),
+)]
+#[patch("/{oid}")]
+pub async fn patch(
+    pool: web::Data<DbPool>,
+    p: web::Json<serde_json::Value>,
+    oid: web::Path<i32>,
+    precondition: Precondition,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let oid = *oid;
+    let patched =
+        crate::db::run(&pool, move |conn| patch_step(conn, oid, &p, &precondition)).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(patched.revision))
+        .json(patched))
+}
+
+crud_read_all!(Step, steps, sort: [id, rank], contains: [question, location_hint], range: [rank]);
+
+pub fn find(conn: &mut DbConnection, oid: i32) -> Result<Step, diesel::result::Error> {
+    use crate::schema::steps::dsl::*;
+    steps.filter(id.eq(oid)).first::<Step>(conn)
+}
+
+// Steps hold their answer and their secret code, so only the organizers may read them whole
+#[utoipa::path(
+    summary = "Read one",
+    responses((status = 200, body = Step, headers(("ETag" = String)))),
+    security(("token" = [])),
+)]
+#[get("/{oid}")]
+pub async fn read(
+    pool: web::Data<DbPool>,
+    oid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let oid = *oid;
+    let object = crate::db::run(&pool, move |conn| find(conn, oid)).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(object.revision))
+        .json(object))
+}
 
 ///////////////////////
 // IMAGES MANAGEMENT //
 ///////////////////////
 
-const IMAGES_PATH: &str = "data/items/images";
+pub(crate) const IMAGES_DIR: &str = "images";
+// Images may be replaced by the organizers while the game is being set up
+const IMAGES_CACHE_CONTROL: &str = "public, max-age=3600, must-revalidate";
 
+// Stored renditions of each step image, by largest dimension
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageSize {
+    Thumbnail,
+    Medium,
+    #[default]
+    Full,
+}
+
+impl ImageSize {
+    const ALL: [ImageSize; 3] = [ImageSize::Thumbnail, ImageSize::Medium, ImageSize::Full];
+
+    fn max_dimension(&self) -> u32 {
+        match self {
+            ImageSize::Thumbnail => 320,
+            ImageSize::Medium => 800,
+            ImageSize::Full => 1280,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            ImageSize::Thumbnail => "_thumbnail",
+            ImageSize::Medium => "_medium",
+            ImageSize::Full => "",
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImageQuery {
+    #[serde(default)]
+    size: ImageSize,
+    // The version whose copy of the image is served, rather than the image of the draft
+    version: Option<i32>,
+}
+
+#[utoipa::path(
+    summary = "Upload the image of a step",
+    request_body(content = Vec<u8>, content_type = "image/*"),
+    responses((status = 200, body = String, description = "The name of the stored image")),
+    security(("token" = [])),
+)]
 #[post("/images/{oid}")]
 async fn upload_image(
+    app_config: web::Data<AppConfig>,
     oid: web::Path<i32>,
     mut body: web::Payload,
     _: Authenticated,
 ) -> Result<HttpResponse, ServerError> {
-    create_dir_all(IMAGES_PATH)?;
-    let filename = image_filename(*oid);
+    let oid = *oid;
     let mut bytes = web::BytesMut::new();
     while let Some(item) = body.next().await {
         bytes.extend_from_slice(&item?);
     }
-    let r = web::block(move || image::load_from_memory(&bytes)).await?;
+    let storage = app_config.storage.clone();
+    web::block(move || store_renditions(storage.as_ref(), &bytes, oid))
+        .await?
+        .map_err(|e| match e {
+            ImageError::Decoding(_) | ImageError::Unsupported(_) => {
+                ServerError::BadRequest(format!("the uploaded file is not a valid image: {e}"))
+            }
+            e => e.into(),
+        })?;
+    Ok(HttpResponse::Ok().body(image_key(oid, ImageSize::Full, ImageFormat::Jpeg)))
+}
 
-    if let Ok(r) = r {
-        r.resize(
-            std::cmp::min(1280, r.dimensions().0),
-            std::cmp::min(1280, r.dimensions().1),
-            Lanczos3,
-        )
-        .save_with_format(
-            &filename,
-            image::ImageFormat::from_extension("jpg").unwrap(),
-        )?;
-        Ok(HttpResponse::Ok().body(filename))
+// Decode an uploaded image and turn it upright according to its EXIF orientation.
+// The metadata, GPS position included, is not kept when the image is encoded again.
+fn load_image(bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+// Shrink an image so that it fits in a square, keeping its aspect ratio
+fn fit(img: &DynamicImage, max_dimension: u32) -> DynamicImage {
+    if img.width() > max_dimension || img.height() > max_dimension {
+        img.resize(max_dimension, max_dimension, Lanczos3)
     } else {
-        let res = HttpResponse::InternalServerError().body("Error uploading image");
-        Ok(res)
+        img.clone()
+    }
+}
+
+// Decode an uploaded image, shrink it if needed and store it as JPEG
+pub(crate) fn store_image(bytes: &[u8], filename: &str) -> Result<(), ImageError> {
+    let img = load_image(bytes)?;
+    fit(&img, ImageSize::Full.max_dimension())
+        .into_rgb8()
+        .save_with_format(filename, ImageFormat::Jpeg)
+}
+
+// Store every rendition of a step image as JPEG, and as WebP when it is smaller: WebP is only
+// encoded losslessly, which makes photos larger than their JPEG
+fn store_renditions(storage: &dyn Storage, bytes: &[u8], id: i32) -> Result<(), ImageError> {
+    let img = load_image(bytes)?;
+    for size in ImageSize::ALL {
+        let rendition = fit(&img, size.max_dimension()).into_rgb8();
+        let [jpeg, webp] = [ImageFormat::Jpeg, ImageFormat::WebP].map(|format| {
+            let mut encoded = Cursor::new(Vec::new());
+            rendition.write_to(&mut encoded, format)?;
+            Ok::<_, ImageError>(encoded.into_inner())
+        });
+        let (jpeg, webp) = (jpeg?, webp?);
+        storage.put(
+            &image_key(id, size, ImageFormat::Jpeg),
+            &jpeg,
+            ImageFormat::Jpeg.to_mime_type(),
+        )?;
+        let webp_key = image_key(id, size, ImageFormat::WebP);
+        if webp.len() < jpeg.len() {
+            storage.put(&webp_key, &webp, ImageFormat::WebP.to_mime_type())?;
+        } else {
+            // The rendition of a previous image must not be served instead
+            storage.delete(&webp_key)?;
+        }
     }
+    Ok(())
 }
 
+fn accepts_webp(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains("image/webp"))
+}
+
+// Serve the asked rendition, as WebP if the client accepts it and it is the smaller one. Images
+// uploaded before the renditions existed only have the full size JPEG, which is served instead.
+#[utoipa::path(
+    summary = "Download the image of a step",
+    params(ImageQuery),
+    responses((status = 200, content((Vec<u8> = "image/webp"), (Vec<u8> = "image/jpeg")))),
+)]
 #[get("/images/{oid}")]
-async fn retrieve_image(oid: web::Path<i32>) -> Result<NamedFile> {
-    Ok(NamedFile::open(image_filename(*oid))?)
+async fn retrieve_image(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    oid: web::Path<i32>,
+    query: web::Query<ImageQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let mut candidates = Vec::new();
+    if accepts_webp(&req) {
+        candidates.push((query.size, ImageFormat::WebP));
+    }
+    candidates.push((query.size, ImageFormat::Jpeg));
+    candidates.push((ImageSize::Full, ImageFormat::Jpeg));
+    let candidates: Vec<(String, String)> = candidates
+        .into_iter()
+        .map(|(size, format)| {
+            let mime = format.to_mime_type().to_string();
+            (image_key(*oid, size, format), mime)
+        })
+        .collect();
+    let found = match query.version {
+        Some(vid) => {
+            crate::db::run(&pool, move |conn| {
+                for (key, _) in candidates {
+                    if let Some(f) = version::published_file(conn, vid, &key)? {
+                        return Ok(Some((f.file_key, f.mime)));
+                    }
+                }
+                Ok::<_, ServerError>(None)
+            })
+            .await?
+        }
+        None => {
+            let storage = app_config.storage.clone();
+            web::block(move || {
+                for (key, mime) in candidates {
+                    if storage.exists(&key)? {
+                        return Ok(Some((key, mime)));
+                    }
+                }
+                Ok::<_, std::io::Error>(None)
+            })
+            .await??
+        }
+    };
+    let (key, mime) = found.ok_or(ServerError::NotFound("File does not exist".to_owned()))?;
+    let mut res = serve(
+        &req,
+        app_config.storage.as_ref(),
+        &key,
+        &mime,
+        IMAGES_CACHE_CONTROL,
+    )?;
+    res.headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Accept"));
+    Ok(res)
 }
 
+#[utoipa::path(
+    summary = "Delete the image of a step",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
 #[delete("/images/{oid}")]
-async fn delete_image(oid: web::Path<i32>, _: Authenticated) -> Result<HttpResponse, ServerError> {
-    let d = web::block(move || remove_file(image_filename(*oid))).await?;
-    if let Ok(_) = d {
-        Ok(HttpResponse::Ok().body("File deleted"))
-    } else {
-        let res = HttpResponse::NotFound().body("File not found");
-        Ok(res)
+async fn delete_image(
+    app_config: web::Data<AppConfig>,
+    oid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let storage = app_config.storage.clone();
+    web::block(move || remove_images(storage.as_ref(), *oid))
+        .await?
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ServerError::NotFound("File not found".to_owned()),
+            _ => e.into(),
+        })?;
+    Ok(HttpResponse::Ok().body("File deleted"))
+}
+
+fn image_key(id: i32, size: ImageSize, format: ImageFormat) -> String {
+    let ext = match format {
+        ImageFormat::WebP => "webp",
+        _ => "jpg",
+    };
+    format!(
+        "{dir}/{id}{suffix}.{ext}",
+        dir = IMAGES_DIR,
+        id = id,
+        suffix = size.suffix()
+    )
+}
+
+// A file of a draft step, copied when the step is published
+pub(crate) struct StepFile {
+    pub step_id: i32,
+    pub key: String,
+    pub mime: String,
+}
+
+// The files a step may have: the renditions of its image, its media and the lighter copy of it
+pub(crate) fn step_files(conn: &mut DbConnection, id: i32) -> Result<Vec<StepFile>, ServerError> {
+    let file = |key: String, mime: &str| StepFile {
+        step_id: id,
+        key,
+        mime: mime.to_string(),
+    };
+    let mut files = Vec::new();
+    for size in ImageSize::ALL {
+        for format in [ImageFormat::Jpeg, ImageFormat::WebP] {
+            files.push(file(image_key(id, size, format), format.to_mime_type()));
+        }
+    }
+    if let Some(m) = find_media(conn, id)? {
+        files.push(file(media_key(id, &m.extension), &m.mime));
+        if let Some((key, low_mime)) = low_bitrate_key(id, &m.mime) {
+            files.push(file(key, low_mime));
+        }
     }
+    Ok(files)
 }
 
-fn image_filename(id: i32) -> String {
-    format!("{path}/{id}.jpg", path = IMAGES_PATH, id = id)
+// Remove every rendition of a step image, fails if there was no full size image
+fn remove_images(storage: &dyn Storage, id: i32) -> std::io::Result<()> {
+    let full = image_key(id, ImageSize::Full, ImageFormat::Jpeg);
+    if !storage.exists(&full)? {
+        return Err(std::io::ErrorKind::NotFound.into());
+    }
+    for size in ImageSize::ALL {
+        for format in [ImageFormat::Jpeg, ImageFormat::WebP] {
+            storage.delete(&image_key(id, size, format))?;
+        }
+    }
+    Ok(())
 }
 
 ///////////////////////
 // MEDIAS MANAGEMENT //
 ///////////////////////
 
-const MEDIAS_PATH: &str = "data/items/medias";
+pub(crate) const MEDIAS_DIR: &str = "medias";
+pub(crate) const MEDIAS_LOW_DIR: &str = "medias/low";
+// Uploads are always received on the local disk before being moved to the storage
+pub(crate) const UPLOADS_PATH: &str = "data/items/uploads";
+// Medias are large and rarely change, they are revalidated once a day
+pub(crate) const MEDIAS_CACHE_CONTROL: &str = "public, max-age=86400, must-revalidate";
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[diesel(table_name = medias)]
+pub struct Media {
+    pub step_id: i32,
+    pub mime: String,
+    pub extension: String,
+    pub size: i64,
+    pub uploaded_at: i64,
+}
+
+// Parse a media name given by a client, which must be a step id optionally followed by a plain extension
+fn parse_media_name(name: &str) -> Result<(i32, Option<&str>), ServerError> {
+    let not_found = || ServerError::NotFound("File does not exist".to_owned());
+    let (id, ext) = match name.split_once('.') {
+        Some((id, ext)) => (id, Some(ext)),
+        None => (name, None),
+    };
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(not_found());
+    }
+    if let Some(ext) = ext {
+        if ext.is_empty() || ext.len() > 5 || !ext.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(not_found());
+        }
+    }
+    Ok((id.parse().map_err(|_| not_found())?, ext))
+}
 
-#[post("/medias/{oid}.{ext}")]
+// Upload the media of a step, the format is detected from the content and the extension given in the URL is ignored
+#[utoipa::path(
+    summary = "Upload the sound or the video of a step",
+    description = "The name is the id of the step, the format is found from the content",
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses((status = 200, body = String, description = "The name of the stored media")),
+    security(("token" = [])),
+)]
+#[post("/medias/{name}")]
 async fn upload_media(
-    path: web::Path<(i32, String)>,
-    mut body: web::Payload,
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    name: web::Path<String>,
+    body: web::Payload,
     _: Authenticated,
 ) -> Result<HttpResponse, ServerError> {
-    create_dir_all(MEDIAS_PATH)?;
-    let (oid, ext) = path.into_inner();
-    let filename = media_filename_in(oid, &ext);
-    let mut file = File::create(&filename)?;
-    while let Some(item) = body.next().await {
-        file.write(&item?)?;
+    let (oid, _) = parse_media_name(&name)?;
+    crate::db::run(&pool, move |conn| find(conn, oid)).await?;
+
+    let upload = receive_upload(body, app_config.media_max_size, sniff).await?;
+    let media_type = upload.media_type;
+    let key = media_key(oid, media_type.extension);
+    let m = Media {
+        step_id: oid,
+        mime: media_type.mime.to_string(),
+        extension: media_type.extension.to_string(),
+        size: upload.size as i64,
+        uploaded_at: now(),
+    };
+    let storage = app_config.storage.clone();
+    let stored = {
+        let key = key.clone();
+        let temp_filename = upload.temp_filename.clone();
+        crate::db::run(&pool, move |conn| {
+            store_media(conn, storage.as_ref(), &key, Path::new(&temp_filename), &m)
+        })
+        .await
+    };
+    if let Err(e) = stored {
+        let _ = remove_file(&upload.temp_filename);
+        return Err(e);
+    }
+    if app_config.low_bitrate_renditions {
+        let (storage, source, mime) = (app_config.storage.clone(), key.clone(), media_type.mime);
+        actix_web::rt::spawn(async move {
+            match web::block(move || encode_low_bitrate(storage.as_ref(), oid, &source, mime)).await
+            {
+                Ok(Ok(())) => info!("Low bitrate rendition of media {} created", oid),
+                Ok(Err(e)) => warn!("Low bitrate rendition of media {} failed: {}", oid, e),
+                Err(e) => warn!("Low bitrate rendition of media {} failed: {}", oid, e),
+            }
+        });
+    }
+    Ok(HttpResponse::Ok().body(key))
+}
+
+// Move an uploaded media to the storage and record it, in place of the previous one
+fn store_media(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    key: &str,
+    source: &Path,
+    m: &Media,
+) -> Result<(), ServerError> {
+    let previous = find_media(conn, m.step_id)?;
+    storage.put_file(key, source, &m.mime)?;
+    save_media(conn, m)?;
+    // A previous media with another format would be left behind
+    if let Some(previous) = previous.filter(|p| p.extension != m.extension) {
+        storage.delete(&media_key(m.step_id, &previous.extension))?;
+    }
+    remove_low_bitrate(storage, m.step_id);
+    Ok(())
+}
+
+// Encode a lighter copy of an audio or video media for players on slow connections, using ffmpeg.
+// A media that is not on the local disk is downloaded first.
+fn encode_low_bitrate(
+    storage: &dyn Storage,
+    id: i32,
+    source: &str,
+    mime: &str,
+) -> std::io::Result<()> {
+    let Some((key, low_mime)) = low_bitrate_key(id, mime) else {
+        return Ok(());
+    };
+    create_dir_all(UPLOADS_PATH)?;
+    let (input, downloaded) = match storage.local_path(source) {
+        Some(path) => (path, false),
+        None => {
+            let filename = PathBuf::from(format!("{UPLOADS_PATH}/{}.upload", random_string()));
+            fs::write(&filename, storage.get(source)?)?;
+            (filename, true)
+        }
+    };
+    let temp_filename = format!("{UPLOADS_PATH}/{}.upload", random_string());
+    let mut command = Command::new("ffmpeg");
+    command.args(["-y", "-loglevel", "error", "-i"]).arg(&input);
+    if mime.starts_with("video/") {
+        command.args([
+            "-vf",
+            "scale=-2:'min(360,ih)'",
+            "-c:v",
+            "libx264",
+            "-b:v",
+            "400k",
+        ]);
+    } else {
+        command.arg("-vn");
+    }
+    command.args(["-ac", "1", "-b:a", "48k", "-f"]);
+    command.arg(if mime.starts_with("video/") {
+        "mp4"
+    } else {
+        "mp3"
+    });
+    let status = command.arg(&temp_filename).status();
+    if downloaded {
+        let _ = remove_file(&input);
+    }
+    let status = status?;
+    if !status.success() {
+        let _ = remove_file(&temp_filename);
+        return Err(std::io::Error::other(format!(
+            "ffmpeg exited with {status}"
+        )));
+    }
+    storage
+        .put_file(&key, Path::new(&temp_filename), low_mime)
+        .inspect_err(|_| {
+            let _ = remove_file(&temp_filename);
+        })
+}
+
+// Lighter copies are MP3 for sounds and MP4 for videos, other medias have none
+fn low_bitrate_key(id: i32, mime: &str) -> Option<(String, &'static str)> {
+    let (ext, low_mime) = match mime.split('/').next() {
+        Some("audio") => ("mp3", "audio/mpeg"),
+        Some("video") => ("mp4", "video/mp4"),
+        _ => return None,
+    };
+    Some((format!("{dir}/{id}.{ext}", dir = MEDIAS_LOW_DIR), low_mime))
+}
+
+fn remove_low_bitrate(storage: &dyn Storage, id: i32) {
+    for mime in ["audio/mpeg", "video/mp4"] {
+        if let Some((key, _)) = low_bitrate_key(id, mime) {
+            let _ = storage.delete(&key);
+        }
+    }
+}
+
+// Find the media of a step, from its stored metadata only
+fn find_media(conn: &mut DbConnection, id: i32) -> Result<Option<Media>, ServerError> {
+    Ok(medias::table.find(id).first::<Media>(conn).optional()?)
+}
+
+// Get the media designated by a client given name, whose extension must match the stored one if given
+async fn requested_media(pool: web::Data<DbPool>, name: &str) -> Result<Media, ServerError> {
+    let (oid, ext) = parse_media_name(name)?;
+    let ext = ext.map(|e| e.to_string());
+    crate::db::run(&pool, move |conn| find_media(conn, oid))
+        .await?
+        .filter(|m| {
+            ext.as_ref()
+                .is_none_or(|ext| ext.eq_ignore_ascii_case(&m.extension))
+        })
+        .ok_or(ServerError::NotFound("File does not exist".to_owned()))
+}
+
+// A media as it is served, from the draft or from a version, with the key and the type of its file
+// and of its lighter copy if it may have one
+struct ServedMedia {
+    step_id: i32,
+    mime: String,
+    extension: String,
+    key: String,
+    low: Option<(String, String)>,
+}
+
+impl ServedMedia {
+    fn of_draft(m: Media) -> Self {
+        ServedMedia {
+            key: media_key(m.step_id, &m.extension),
+            low: low_bitrate_key(m.step_id, &m.mime).map(|(k, mime)| (k, mime.to_string())),
+            step_id: m.step_id,
+            mime: m.mime,
+            extension: m.extension,
+        }
     }
-    Ok(HttpResponse::Ok().body(filename))
 }
 
-#[get("/medias/{filename}")]
-async fn retrieve_media(filename: web::Path<String>) -> Result<NamedFile> {
-    Ok(NamedFile::open(format!(
-        "{path}/{filename}",
-        path = MEDIAS_PATH,
-        filename = filename
-    ))?)
+// The copy of the media of a step in a version, found from the key of the draft media
+fn find_published_media(
+    conn: &mut DbConnection,
+    vid: i32,
+    oid: i32,
+) -> Result<Option<ServedMedia>, ServerError> {
+    let files = version::published_files_of(conn, vid, oid)?;
+    let media = files.iter().find_map(|f| {
+        let name = f.name.strip_prefix(MEDIAS_DIR)?.strip_prefix('/')?;
+        let (_, ext) = parse_media_name(name).ok()?;
+        Some((f, ext?.to_string()))
+    });
+    let Some((media, extension)) = media else {
+        return Ok(None);
+    };
+    let low = low_bitrate_key(oid, &media.mime).and_then(|(key, _)| {
+        let f = files.iter().find(|f| f.name == key)?;
+        Some((f.file_key.clone(), f.mime.clone()))
+    });
+    Ok(Some(ServedMedia {
+        step_id: oid,
+        mime: media.mime.clone(),
+        extension,
+        key: media.file_key.clone(),
+        low,
+    }))
 }
 
-#[head("/medias/{oid}")]
-async fn check_media(oid: web::Path<i32>) -> Result<HttpResponse, ServerError> {
-    let filename = media_filename_out(*oid)
-        .ok_or(ServerError::NotFound("File does not exist".to_owned()))?
-        .to_owned();
-    let filename = filename.file_name().unwrap().to_string_lossy().to_string();
+// Get the media designated by a client given name, from the draft or from a version
+async fn served_media(
+    pool: web::Data<DbPool>,
+    name: &str,
+    version: Option<i32>,
+) -> Result<ServedMedia, ServerError> {
+    let (oid, ext) = parse_media_name(name)?;
+    let ext = ext.map(|e| e.to_string());
+    crate::db::run(&pool, move |conn| match version {
+        Some(vid) => find_published_media(conn, vid, oid),
+        None => Ok(find_media(conn, oid)?.map(ServedMedia::of_draft)),
+    })
+    .await?
+    .filter(|m| {
+        ext.as_ref()
+            .is_none_or(|ext| ext.eq_ignore_ascii_case(&m.extension))
+    })
+    .ok_or(ServerError::NotFound("File does not exist".to_owned()))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaQuality {
+    #[default]
+    Original,
+    Low,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MediaQuery {
+    #[serde(default)]
+    quality: MediaQuality,
+    // The version whose copy of the media is served, rather than the media of the draft
+    version: Option<i32>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VersionQuery {
+    // The version whose copy of the file is asked for, rather than the file of the draft
+    pub version: Option<i32>,
+}
+
+// Serve the media, or its lighter copy if asked and available. Range requests and conditional
+// requests (ETag and Last-Modified) are handled by NamedFile, or by the storage service the
+// client is redirected to.
+#[utoipa::path(
+    summary = "Download the media of a step",
+    params(MediaQuery),
+    description = "Ranges are supported, the media may be served from the storage by a redirection",
+    responses(
+        (status = 200, body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 206, body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 307, description = "The media is served by the storage"),
+    ),
+)]
+#[get("/medias/{name}")]
+async fn retrieve_media(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    name: web::Path<String>,
+    query: web::Query<MediaQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let m = served_media(pool, &name, query.version).await?;
+    let low = match m.low {
+        Some((key, low_mime)) if query.quality == MediaQuality::Low => {
+            let storage = app_config.storage.clone();
+            let exists = {
+                let key = key.clone();
+                web::block(move || storage.exists(&key)).await??
+            };
+            exists.then_some((key, low_mime))
+        }
+        _ => None,
+    };
+    let (key, mime) = low.unwrap_or((m.key, m.mime));
+    serve(
+        &req,
+        app_config.storage.as_ref(),
+        &key,
+        &mime,
+        MEDIAS_CACHE_CONTROL,
+    )
+}
+
+#[utoipa::path(
+    summary = "Tell the file name of the media of a step",
+    params(VersionQuery),
+    responses((status = 200, headers(("filename" = String)))),
+)]
+#[head("/medias/{name}")]
+async fn check_media(
+    pool: web::Data<DbPool>,
+    name: web::Path<String>,
+    query: web::Query<VersionQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let m = served_media(pool, &name, query.version).await?;
+    let filename = format!("{}.{}", m.step_id, m.extension);
     Ok(HttpResponse::Ok()
         .insert_header(("filename", filename.clone()))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
         .body(filename))
 }
 
-#[delete("/medias/{oid}")]
-async fn delete_media(oid: web::Path<i32>, _: Authenticated) -> Result<HttpResponse, ServerError> {
-    let filename = media_filename_out(*oid)
-        .ok_or(ServerError::NotFound("File does not exist".to_owned()))?
-        .to_owned();
-    let d = web::block(move || remove_file(filename)).await?;
-    if let Ok(_) = d {
-        Ok(HttpResponse::Ok().body("File deleted"))
-    } else {
-        let res = HttpResponse::NotFound().body("File not found");
-        Ok(res)
+#[utoipa::path(
+    summary = "Delete the media of a step",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
+#[delete("/medias/{name}")]
+async fn delete_media(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    name: web::Path<String>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let m = requested_media(pool.clone(), &name).await?;
+    let storage = app_config.storage.clone();
+    let existed =
+        crate::db::run(&pool, move |conn| remove_media(conn, storage.as_ref(), &m)).await?;
+    if !existed {
+        return Err(ServerError::NotFound("File not found".to_owned()));
     }
+    Ok(HttpResponse::Ok().body("File deleted"))
 }
 
-fn media_filename_in(id: i32, ext: &String) -> String {
-    if ext.is_empty() {
-        format!("{path}/{id}", path = MEDIAS_PATH, id = id)
-    } else {
-        format!("{path}/{id}.{ext}", path = MEDIAS_PATH, id = id)
+// Remove a media and its record, telling if its file existed
+fn remove_media(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    m: &Media,
+) -> Result<bool, ServerError> {
+    remove_media_row(conn, m.step_id)?;
+    remove_low_bitrate(storage, m.step_id);
+    let key = media_key(m.step_id, &m.extension);
+    let existed = storage.exists(&key)?;
+    storage.delete(&key)?;
+    Ok(existed)
+}
+
+fn remove_media_row(conn: &mut DbConnection, id: i32) -> Result<usize, diesel::result::Error> {
+    diesel::delete(medias::table.find(id)).execute(conn)
+}
+
+// Record the media of a step, replacing the previous one
+fn save_media(conn: &mut DbConnection, m: &Media) -> Result<usize, diesel::result::Error> {
+    conn.transaction(|conn| {
+        remove_media_row(conn, m.step_id)?;
+        diesel::insert_into(medias::table).values(m).execute(conn)
+    })
+}
+
+fn media_key(id: i32, ext: &str) -> String {
+    format!("{dir}/{id}.{ext}", dir = MEDIAS_DIR, id = id)
+}
+
+// Record the medias uploaded before their type was stored, so that they can be found from their
+// metadata. The files that are not an allowed media or whose step is deleted are left untouched
+// and will not be served.
+pub fn record_legacy_medias(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+) -> Result<usize, ServerError> {
+    let mut recorded = 0;
+    for object in storage.list(&format!("{MEDIAS_DIR}/"))? {
+        let Some(id) = object
+            .key
+            .rsplit('/')
+            .next()
+            .and_then(|n| parse_media_name(n).ok())
+            .map(|(id, _)| id)
+        else {
+            continue;
+        };
+        if find_media(conn, id)?.is_some() || !exists(conn, id)? {
+            continue;
+        }
+        let data = storage.get(&object.key)?;
+        let Some(media_type) = sniff(&data[..data.len().min(SNIFF_LEN)]) else {
+            continue;
+        };
+        let key = media_key(id, media_type.extension);
+        if key != object.key {
+            storage.put(&key, &data, media_type.mime)?;
+            storage.delete(&object.key)?;
+        }
+        save_media(
+            conn,
+            &Media {
+                step_id: id,
+                mime: media_type.mime.to_string(),
+                extension: media_type.extension.to_string(),
+                size: object.size as i64,
+                uploaded_at: now(),
+            },
+        )?;
+        recorded += 1;
     }
+    Ok(recorded)
 }
 
-fn media_filename_out(id: i32) -> Option<PathBuf> {
-    let entries = fs::read_dir(MEDIAS_PATH).ok()?;
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let file_name = entry.file_name();
-            let file_name = file_name.to_str()?;
-            let file_id = file_name.split(".").collect::<Vec<&str>>()[0];
-            if file_id == format!("{id}") {
-                return Some(entry.path());
+// A payload streamed to a temporary file, whose type was recognized
+pub(crate) struct Upload {
+    pub temp_filename: String,
+    pub media_type: MediaType,
+    pub size: usize,
+    pub checksum: String,
+}
+
+// Stream a payload to a temporary file, so that the final file is only replaced once the upload
+// is complete and valid. The size limit is enforced while streaming and the type is recognized
+// from the first bytes.
+pub(crate) async fn receive_upload(
+    mut body: web::Payload,
+    max_size: usize,
+    recognize: fn(&[u8]) -> Option<MediaType>,
+) -> Result<Upload, ServerError> {
+    create_dir_all(UPLOADS_PATH)?;
+    let temp_filename = format!(
+        "{path}/{random}.upload",
+        path = UPLOADS_PATH,
+        random = random_string()
+    );
+    let mut file = File::create(&temp_filename)?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let mut size = 0;
+    let mut hasher = Sha256::new();
+    let streamed = async {
+        while let Some(item) = body.next().await {
+            let item = item?;
+            size += item.len();
+            if size > max_size {
+                return Err(ServerError::PayloadTooLarge(format!(
+                    "media is larger than {} bytes",
+                    max_size
+                )));
+            }
+            if head.len() < SNIFF_LEN {
+                let missing = (SNIFF_LEN - head.len()).min(item.len());
+                head.extend_from_slice(&item[..missing]);
             }
+            hasher.update(&item);
+            file.write_all(&item)?;
+        }
+        file.sync_all()?;
+        recognize(&head).ok_or(ServerError::UnsupportedMediaType(
+            "unsupported media type".to_string(),
+        ))
+    }
+    .await;
+    match streamed {
+        Ok(media_type) => Ok(Upload {
+            temp_filename,
+            media_type,
+            size,
+            checksum: format!("{:x}", hasher.finalize()),
+        }),
+        Err(e) => {
+            let _ = remove_file(&temp_filename);
+            Err(e)
         }
     }
-    None
+}
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+    use image::RgbImage;
+
+    // A 4x2 JPEG with an EXIF block telling to rotate it by 90° and holding a GPS position
+    fn phone_photo() -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 2))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        #[rustfmt::skip]
+        let tiff: &[u8] = &[
+            b'M', b'M', 0, 42, 0, 0, 0, 8,
+            // IFD0: orientation (rotate 90° clockwise) and GPS IFD pointer
+            0, 2,
+            0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0,
+            0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 38,
+            0, 0, 0, 0,
+            // GPS IFD: latitude reference
+            0, 1,
+            0x00, 0x01, 0, 2, 0, 0, 0, 2, b'N', 0, 0, 0,
+            0, 0, 0, 0,
+        ];
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(tiff);
+        let len = (app1.len() + 2) as u16;
+        let mut photo = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        photo.extend_from_slice(&len.to_be_bytes());
+        photo.extend_from_slice(&app1);
+        photo.extend_from_slice(&jpeg[2..]);
+        photo
+    }
+
+    #[test]
+    fn test_exif_orientation() {
+        let img = load_image(&phone_photo()).unwrap();
+        assert_eq!((img.width(), img.height()), (2, 4));
+    }
+
+    #[test]
+    fn test_metadata_stripping() {
+        let photo = phone_photo();
+        assert!(photo.windows(4).any(|w| w == b"Exif"));
+        let filename = std::env::temp_dir().join("pistou_test_metadata_stripping.jpg");
+        store_image(&photo, filename.to_str().unwrap()).unwrap();
+        let stored = std::fs::read(&filename).unwrap();
+        let _ = remove_file(&filename);
+        assert!(!stored.windows(4).any(|w| w == b"Exif"));
+    }
+
+    #[test]
+    fn test_webp_only_when_smaller() {
+        let storage =
+            crate::storage::FileStorage::new(std::env::temp_dir().join("pistou_test_renditions"));
+        let encode = |img: RgbImage| {
+            let mut bytes = Vec::new();
+            DynamicImage::ImageRgb8(img)
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+                .unwrap();
+            bytes
+        };
+        let key = image_key(1, ImageSize::Full, ImageFormat::WebP);
+        // A flat image is smaller as lossless WebP
+        store_renditions(&storage, &encode(RgbImage::new(400, 300)), 1).unwrap();
+        assert!(storage.exists(&key).unwrap());
+        // A noisy one, like a photo, is smaller as JPEG, and replaces the previous WebP
+        let mut seed = 1u32;
+        let noise = RgbImage::from_fn(400, 300, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            image::Rgb([(seed >> 16) as u8, (seed >> 8) as u8, (seed >> 24) as u8])
+        });
+        store_renditions(&storage, &encode(noise), 1).unwrap();
+        assert!(!storage.exists(&key).unwrap());
+        assert!(storage
+            .exists(&image_key(1, ImageSize::Full, ImageFormat::Jpeg))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_fit() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(2000, 500));
+        let fitted = fit(&img, 800);
+        assert_eq!((fitted.width(), fitted.height()), (800, 200));
+        let small = DynamicImage::ImageRgb8(RgbImage::new(300, 200));
+        let fitted = fit(&small, 800);
+        assert_eq!((fitted.width(), fitted.height()), (300, 200));
+    }
 }
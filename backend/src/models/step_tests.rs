@@ -1,9 +1,6 @@
-use crate::{auth::AppConfig, create_app};
+use crate::{auth::AppConfig, create_app, tester::error_json};
 
-pub async fn step_test(
-    pool: &r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
-    app_config: &actix_web::web::Data<AppConfig>,
-) {
+pub async fn step_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
     use crate::{do_test, do_test_extract_id};
     use actix_web::{
         http::{Method, StatusCode},
@@ -39,7 +36,7 @@ pub async fn step_test(
         "",
         StatusCode::OK,
         format!(
-            r#"{{"id":{id},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue","is_end":false}}"#
+            r#"{{"id":{id},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
 
@@ -51,7 +48,18 @@ pub async fn step_test(
         &format!("/api/steps/{}", id + 1),
         "",
         StatusCode::NOT_FOUND,
-        "Item not found"
+        error_json("not_found", "Item not found")
+    );
+
+    // Get the step without the token, which would give away its answer (must fail)
+    do_test!(
+        app,
+        "",
+        Method::GET,
+        &format!("/api/steps/{}", id),
+        "",
+        StatusCode::UNAUTHORIZED,
+        r#"{"code":"unauthorized""#
     );
 
     // Patch the step
@@ -61,11 +69,11 @@ pub async fn step_test(
         Method::PUT,
         &format!("/api/steps/{}", id),
         &format!(
-            r#"{{"id":{id},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the grass?","answer":"green","is_end":false}}"#
+            r#"{{"id":{id},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the grass?","answer":"green","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         ),
         StatusCode::OK,
         format!(
-            r#"{{"id":{id},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the grass?","answer":"green","is_end":false}}"#
+            r#"{{"id":{id},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the grass?","answer":"green","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
 
@@ -88,7 +96,7 @@ pub async fn step_test(
         &format!("/api/steps/{}", id + 1),
         "",
         StatusCode::NOT_FOUND,
-        "Item not found"
+        error_json("not_found", "Item not found")
     );
 
     // Delete all the steps
@@ -125,7 +133,7 @@ pub async fn step_test(
         "",
         StatusCode::OK,
         format!(
-            r#"[{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","shake_message":"shaked!","answer":"blue","is_end":false}},{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"what is the color of the grass?","answer":"green","is_end":false}}]"#
+            r#"[{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","shake_message":"shaked!","answer":"blue","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}},{{"id":{id2},"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"what is the color of the grass?","answer":"green","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}]"#
         )
     );
 
@@ -161,7 +169,7 @@ pub async fn step_test(
         "",
         StatusCode::OK,
         format!(
-            r#"[{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","shake_message":"shaked!","answer":"blue","is_end":false}},{{"id":{id3},"rank":2,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false}}]"#
+            r#"[{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","shake_message":"shaked!","answer":"blue","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}},{{"id":{id3},"rank":2,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}]"#
         )
     );
 
@@ -172,11 +180,11 @@ pub async fn step_test(
         Method::PUT,
         &format!("/api/steps/{}", id1),
         &format!(
-            r#"{{"id":{id1},"rank":10,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false}}"#
+            r#"{{"id":{id1},"rank":10,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         ),
         StatusCode::OK,
         format!(
-            r#"{{"id":{id1},"rank":2,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false}}"#
+            r#"{{"id":{id1},"rank":2,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
 
@@ -189,7 +197,7 @@ pub async fn step_test(
         "",
         StatusCode::OK,
         format!(
-            r#"[{{"id":{id1},"rank":2,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false}},{{"id":{id3},"rank":1,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false}}]"#
+            r#"[{{"id":{id1},"rank":2,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}},{{"id":{id3},"rank":1,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}]"#
         )
     );
 
@@ -200,11 +208,11 @@ pub async fn step_test(
         Method::PUT,
         &format!("/api/steps/{}", id1),
         &format!(
-            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false}}"#
+            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         ),
         StatusCode::OK,
         format!(
-            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false}}"#
+            r#"{{"id":{id1},"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the city?","answer":"grey","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
 
@@ -215,11 +223,11 @@ pub async fn step_test(
         Method::PUT,
         &format!("/api/steps/{}", id3),
         &format!(
-            r#"{{"id":{id3},"rank":1,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false}}"#
+            r#"{{"id":{id3},"rank":1,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         ),
         StatusCode::OK,
         format!(
-            r#"{{"id":{id3},"rank":1,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false}}"#
+            r#"{{"id":{id3},"rank":1,"latitude":45.366669,"longitude":5.58333,"location_hint":"go there after","question":"what is the color of the sun?","answer":"yellow","is_end":false,"show_bearing":false,"validation_mode":"LocationAndAnswer"}}"#
         )
     );
 
@@ -268,7 +276,84 @@ pub async fn step_test(
     let body = test::read_body(resp).await;
     assert_eq!(body, img_body);
 
-    // Upload a sound for this step (we use the same image file, as we only want to test the upload, retrieving, and deletion)
+    // Upload a larger image
+    let mut large_img = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::new(1600, 900))
+        .write_to(
+            &mut std::io::Cursor::new(&mut large_img),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    let req = test::TestRequest::with_uri(format!("/api/steps/images/{}", id).as_str())
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(large_img)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Retrieve the full image, shrunk while keeping its aspect ratio
+    let req = test::TestRequest::with_uri(format!("/api/steps/images/{}", id).as_str())
+        .method(Method::GET)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let body = test::read_body(resp).await;
+    let full = image::load_from_memory(&body).unwrap();
+    assert_eq!((full.width(), full.height()), (1280, 720));
+
+    // Retrieve the thumbnail
+    let req =
+        test::TestRequest::with_uri(format!("/api/steps/images/{}?size=thumbnail", id).as_str())
+            .method(Method::GET)
+            .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/jpeg");
+    let body = test::read_body(resp).await;
+    let thumbnail = image::load_from_memory(&body).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (320, 180));
+
+    // Retrieve the medium image as WebP
+    let req = test::TestRequest::with_uri(format!("/api/steps/images/{}?size=medium", id).as_str())
+        .method(Method::GET)
+        .insert_header(("Accept", "image/avif,image/webp,*/*"))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/webp");
+    assert!(resp
+        .headers()
+        .get("vary")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .split(", ")
+        .any(|v| v == "Accept"));
+    let body = test::read_body(resp).await;
+    let medium = image::load_from_memory(&body).unwrap();
+    assert_eq!((medium.width(), medium.height()), (800, 450));
+
+    // Upload a file that is not an allowed media (must fail)
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.mp3", id).as_str())
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload("<script>alert(1)</script>")
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // Upload a media for a non existing step (must fail)
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.mp3", id + 1).as_str())
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Upload an image as the media of this step, the extension of the URL must be ignored
     let img_body = std::fs::read("test_img.jpg").unwrap();
     let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.mp3", id).as_str())
         .method(Method::POST)
@@ -277,6 +362,22 @@ pub async fn step_test(
         .to_request();
     let resp = test::call_service(&mut app, req).await;
     assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}", id).as_str())
+        .method(Method::HEAD)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, format!("{id}.jpg"));
+
+    // Replace it with a sound
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.jpg", id).as_str())
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
 
     // Check if the sound exists
     let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}", id).as_str())
@@ -287,6 +388,13 @@ pub async fn step_test(
     let body = test::read_body(resp).await;
     assert_eq!(body, format!("{id}.mp3"));
 
+    // Check that the previous image is gone
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.jpg", id).as_str())
+        .method(Method::GET)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
     // Retrieve the sound with full file name
     let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.mp3", id).as_str())
         .method(Method::GET)
@@ -294,7 +402,26 @@ pub async fn step_test(
     let resp = test::call_service(&mut app, req).await;
     assert_eq!(resp.status(), StatusCode::OK);
     let body = test::read_body(resp).await;
-    assert_eq!(body, img_body);
+    assert_eq!(body, sound_body);
+
+    // Upload a media larger than the configured maximum (must fail and keep the previous media)
+    let mut small_config = AppConfig::new("0101".to_string(), true);
+    small_config.media_max_size = 8;
+    let small_config = actix_web::web::Data::new(small_config);
+    let small_app = test::init_service(create_app!(pool, &small_config)).await;
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}.jpg", id).as_str())
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(img_body)
+        .to_request();
+    let resp = test::call_service(&small_app, req).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}", id).as_str())
+        .method(Method::HEAD)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let body = test::read_body(resp).await;
+    assert_eq!(body, format!("{id}.mp3"));
 
     // Delete the step
     do_test!(
@@ -307,12 +434,19 @@ pub async fn step_test(
         format!("Deleted object with id: {}", id)
     );
 
-    // Check that the image is gone too
+    // Check that the image is gone too, with all its renditions
     let req = test::TestRequest::with_uri(format!("/api/steps/images/{}", id).as_str())
         .method(Method::GET)
         .to_request();
     let resp = test::call_service(&mut app, req).await;
     assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let req =
+        test::TestRequest::with_uri(format!("/api/steps/images/{}?size=thumbnail", id).as_str())
+            .method(Method::GET)
+            .insert_header(("Accept", "image/webp"))
+            .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
     // Check that the sound does not exist
     let req = test::TestRequest::with_uri(format!("/api/steps/medias/{}", id).as_str())
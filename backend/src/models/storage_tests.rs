@@ -0,0 +1,334 @@
+use crate::{
+    auth::AppConfig,
+    create_app,
+    storage::{FileStorage, S3Storage, Storage},
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::{collections::HashMap, io::Read, sync::Mutex};
+
+const BUCKET: &str = "pistou";
+
+// A minimal stand-in for MinIO: a single bucket kept in memory, accepting only correctly presigned requests
+struct StandIn {
+    s3: S3Storage,
+    objects: Mutex<HashMap<String, (Vec<u8>, String)>>,
+}
+
+fn s3_storage(endpoint: &str) -> S3Storage {
+    S3Storage {
+        endpoint: endpoint.to_string(),
+        bucket: BUCKET.to_string(),
+        region: "us-east-1".to_string(),
+        access_key: "minioadmin".to_string(),
+        secret_key: "minioadmin".to_string(),
+        path_style: true,
+    }
+}
+
+async fn bucket(
+    req: HttpRequest,
+    query: web::Query<Vec<(String, String)>>,
+    body: web::Bytes,
+    stand_in: web::Data<StandIn>,
+) -> HttpResponse {
+    let Some(key) = req.path().strip_prefix(&format!("/{BUCKET}/")) else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !stand_in.s3.verify(req.method().as_str(), key, &query) {
+        return HttpResponse::Forbidden().body("SignatureDoesNotMatch");
+    }
+    let mut objects = stand_in.objects.lock().unwrap();
+    match (req.method().as_str(), key) {
+        ("GET", "") => {
+            let param = |name: &str| {
+                query
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default()
+            };
+            let prefix = param("prefix");
+            let contents: String = objects
+                .iter()
+                .filter(|(k, _)| {
+                    k.strip_prefix(&prefix)
+                        .is_some_and(|name| !name.contains('/'))
+                })
+                .map(|(k, (data, _))| {
+                    format!(
+                        "<Contents><Key>{k}</Key><LastModified>2000-01-01T00:00:00.000Z</LastModified><Size>{}</Size></Contents>",
+                        data.len()
+                    )
+                })
+                .collect();
+            HttpResponse::Ok().content_type("application/xml").body(format!(
+                "<ListBucketResult><Name>{BUCKET}</Name><Prefix>{prefix}</Prefix><IsTruncated>false</IsTruncated>{contents}</ListBucketResult>"
+            ))
+        }
+        ("PUT", key) => {
+            let content_type = req
+                .headers()
+                .get("content-type")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("binary/octet-stream")
+                .to_string();
+            objects.insert(key.to_string(), (body.to_vec(), content_type));
+            HttpResponse::Ok().finish()
+        }
+        ("GET", key) | ("HEAD", key) => match objects.get(key) {
+            Some((data, content_type)) => HttpResponse::Ok()
+                .content_type(content_type.as_str())
+                .body(data.clone()),
+            None => HttpResponse::NotFound().body("NoSuchKey"),
+        },
+        ("DELETE", key) => {
+            objects.remove(key);
+            HttpResponse::NoContent().finish()
+        }
+        _ => HttpResponse::MethodNotAllowed().finish(),
+    }
+}
+
+// Download a presigned URL as a browser following the redirection would
+async fn fetch(url: &str) -> Result<(String, Vec<u8>), u16> {
+    let url = url.to_string();
+    web::block(move || match ureq::get(&url).call() {
+        Ok(resp) => {
+            let content_type = resp.content_type().to_string();
+            let mut data = Vec::new();
+            resp.into_reader().read_to_end(&mut data).unwrap();
+            Ok((content_type, data))
+        }
+        Err(ureq::Error::Status(code, _)) => Err(code),
+        Err(e) => panic!("{e}"),
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn storage_test(pool: &crate::db::DbPool, _: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{header, Method, StatusCode},
+        test, App, HttpServer,
+    };
+    use std::{
+        net::TcpListener,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    // Start the stand-in on a free port, it checks the signatures against its actual address
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let stand_in = web::Data::new(StandIn {
+        s3: s3_storage(&endpoint),
+        objects: Mutex::new(HashMap::new()),
+    });
+    let server = {
+        let stand_in = stand_in.clone();
+        HttpServer::new(move || {
+            App::new()
+                .app_data(stand_in.clone())
+                .default_service(web::to(bucket))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+    };
+    let handle = server.run();
+    let server_handle = handle.handle();
+    actix_web::rt::spawn(handle);
+
+    let mut s3_config = AppConfig::new("0101".to_string(), true);
+    s3_config.storage = Arc::new(s3_storage(&endpoint));
+    let s3_config = web::Data::new(s3_config);
+    let mut app = test::init_service(create_app!(pool, &s3_config)).await;
+
+    // Create a step
+    let id = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+
+    // Upload an image, its renditions go to the bucket and not to the local disk
+    let img_body = std::fs::read("test_img.jpg").unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{id}"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(img_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    for suffix in ["", "_thumbnail", "_medium"] {
+        for ext in ["jpg", "webp"] {
+            let key = format!("images/{id}{suffix}.{ext}");
+            assert!(stand_in.objects.lock().unwrap().contains_key(&key));
+            assert!(!Path::new(&format!("data/items/{key}")).exists());
+        }
+    }
+    assert_eq!(
+        stand_in.objects.lock().unwrap()[&format!("images/{id}_thumbnail.webp")].1,
+        "image/webp"
+    );
+
+    // Get the image: the client is redirected to a presigned URL of the bucket
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{id}?size=thumbnail"))
+        .insert_header((header::ACCEPT, "image/webp,*/*"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        resp.headers().get(header::CACHE_CONTROL).unwrap(),
+        "no-store"
+    );
+    assert!(resp
+        .headers()
+        .get(header::VARY)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("Accept"));
+    let location = resp
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(location.starts_with(&format!(
+        "{endpoint}/{BUCKET}/images/{id}_thumbnail.webp?X-Amz-Algorithm=AWS4-HMAC-SHA256&"
+    )));
+    let (content_type, data) = fetch(&location).await.unwrap();
+    assert_eq!(content_type, "image/webp");
+    assert_eq!(
+        data,
+        stand_in.objects.lock().unwrap()[&format!("images/{id}_thumbnail.webp")].0
+    );
+
+    // A tampered URL is refused by the bucket
+    assert_eq!(
+        fetch(&location.replace("_thumbnail.webp", ".webp"))
+            .await
+            .unwrap_err(),
+        403
+    );
+
+    // Upload a sound and get it, asking for a lighter copy that does not exist
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(stand_in
+        .objects
+        .lock()
+        .unwrap()
+        .contains_key(&format!("medias/{id}.mp3")));
+    assert!(!Path::new(&format!("data/items/medias/{id}.mp3")).exists());
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3?quality=low"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+    let location = resp
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(location.starts_with(&format!("{endpoint}/{BUCKET}/medias/{id}.mp3?")));
+    let (content_type, data) = fetch(location).await.unwrap();
+    assert_eq!(content_type, "audio/mpeg");
+    assert_eq!(data, sound_body);
+
+    // Attach the same sound as an asset
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{id}/assets"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body = test::read_body(resp).await;
+    let aid: i32 = regex::Regex::new(r#""id":(\d+)"#)
+        .unwrap()
+        .captures(std::str::from_utf8(&body).unwrap())
+        .unwrap()[1]
+        .parse()
+        .unwrap();
+    let asset_key = "assets/affa2f429722c547275b64721fec3ccf0e72c896d52a2a05207212ea51b5817b.mp3";
+    assert!(stand_in.objects.lock().unwrap().contains_key(asset_key));
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/{id}/assets/{aid}/file")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+    let location = resp
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert_eq!(fetch(location).await.unwrap().1, sound_body);
+
+    // An object left in the bucket is collected, the ones in use are not
+    stand_in.objects.lock().unwrap().insert(
+        "images/999999998.jpg".to_string(),
+        (img_body.clone(), "image/jpeg".to_string()),
+    );
+    let report = do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        "/api/maintenance/orphans",
+        "",
+        StatusCode::OK,
+        r#"{"files":["#
+    );
+    assert!(report.contains(r#""images/999999998.jpg""#));
+    assert!(!report.contains(&format!("images/{id}.jpg")));
+    assert!(!report.contains(asset_key));
+    assert!(!stand_in
+        .objects
+        .lock()
+        .unwrap()
+        .contains_key("images/999999998.jpg"));
+
+    // Delete the step, everything it used is removed from the bucket
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{id}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {id}")
+    );
+    assert!(stand_in.objects.lock().unwrap().is_empty());
+
+    server_handle.stop(true).await;
+
+    // A finished upload is moved to the local disk, even from another file system
+    let dir = if Path::new("/dev/shm").is_dir() {
+        PathBuf::from("/dev/shm")
+    } else {
+        std::env::temp_dir()
+    };
+    let source = dir.join("pistou_storage_test.upload");
+    std::fs::write(&source, b"moved").unwrap();
+    let local = FileStorage::new("data/test_storage");
+    local
+        .put_file("uploads/moved.bin", &source, "application/octet-stream")
+        .unwrap();
+    assert!(!source.exists());
+    assert_eq!(local.get("uploads/moved.bin").unwrap(), b"moved");
+    std::fs::remove_dir_all("data/test_storage").unwrap();
+}
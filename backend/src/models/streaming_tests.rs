@@ -0,0 +1,214 @@
+use crate::{auth::AppConfig, create_app};
+
+pub async fn streaming_test(
+    pool: &crate::db::DbPool,
+    app_config: &actix_web::web::Data<AppConfig>,
+) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{header, Method, StatusCode},
+        test,
+    };
+    use std::path::Path;
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create a step with a sound and an image
+    let id = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend((0..1000).map(|i| (i % 251) as u8));
+    let len = sound_body.len();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let img_body = std::fs::read("test_img.jpg").unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{id}"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(img_body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Get the whole sound, with its validators and caching headers
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let headers = resp.headers().clone();
+    assert_eq!(headers.get(header::ACCEPT_RANGES).unwrap(), "bytes");
+    assert_eq!(
+        headers.get(header::CACHE_CONTROL).unwrap(),
+        "public, max-age=86400, must-revalidate"
+    );
+    let etag = headers.get(header::ETAG).unwrap().clone();
+    let last_modified = headers.get(header::LAST_MODIFIED).unwrap().clone();
+    let body = test::read_body(resp).await;
+    assert_eq!(body, sound_body);
+
+    // Seek in the middle of the sound
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3"))
+        .insert_header((header::RANGE, "bytes=100-199"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_RANGE).unwrap(),
+        &format!("bytes 100-199/{len}")
+    );
+    let body = test::read_body(resp).await;
+    assert_eq!(body, sound_body[100..200]);
+
+    // Seek from a position to the end, then get the last bytes only
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .insert_header((header::RANGE, "bytes=900-"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, sound_body[900..]);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .insert_header((header::RANGE, "bytes=-10"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, sound_body[len - 10..]);
+
+    // Seek after the end (must fail)
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .insert_header((header::RANGE, format!("bytes={}-", len + 10)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    // Resume a download of the same version of the sound
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .insert_header((header::RANGE, "bytes=500-"))
+        .insert_header((header::IF_RANGE, etag.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+
+    // Revalidate the cached sound
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .insert_header((header::IF_NONE_MATCH, etag.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .insert_header((header::IF_MODIFIED_SINCE, last_modified))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    // Checking the sound must not be cached
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}"))
+        .method(Method::HEAD)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(
+        resp.headers().get(header::CACHE_CONTROL).unwrap(),
+        "no-cache"
+    );
+
+    // Without a lighter copy, the original sound is served
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/medias/{id}?quality=low")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, sound_body);
+
+    // With a lighter copy, it is served instead, and seeking works the same
+    let low_body = b"ID3\x04\x00\x00\x00\x00\x00\x00light".to_vec();
+    std::fs::create_dir_all("data/items/medias/low").unwrap();
+    std::fs::write(format!("data/items/medias/low/{id}.mp3"), &low_body).unwrap();
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/medias/{id}?quality=low")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "audio/mpeg"
+    );
+    let body = test::read_body(resp).await;
+    assert_eq!(body, low_body);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}?quality=low"))
+        .insert_header((header::RANGE, "bytes=10-"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "light");
+
+    // Ask for an unknown quality (must fail)
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/medias/{id}?quality=best")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Replacing the sound must drop its outdated lighter copy
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!Path::new(&format!("data/items/medias/low/{id}.mp3")).exists());
+
+    // Get the image, with its validators and caching headers, and seek in it
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{id}")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CACHE_CONTROL).unwrap(),
+        "public, max-age=3600, must-revalidate"
+    );
+    assert!(resp.headers().get(header::LAST_MODIFIED).is_some());
+    let etag = resp.headers().get(header::ETAG).unwrap().clone();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{id}"))
+        .insert_header((header::IF_NONE_MATCH, etag))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{id}"))
+        .insert_header((header::RANGE, "bytes=0-1"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, [0xFF, 0xD8][..]);
+
+    // Delete the step, with its lighter copy
+    std::fs::write(format!("data/items/medias/low/{id}.mp3"), &low_body).unwrap();
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{id}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {id}")
+    );
+    assert!(!Path::new(&format!("data/items/medias/low/{id}.mp3")).exists());
+}
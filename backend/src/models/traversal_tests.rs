@@ -0,0 +1,196 @@
+use crate::{auth::AppConfig, create_app};
+
+pub async fn traversal_test(
+    pool: &crate::db::DbPool,
+    app_config: &actix_web::web::Data<AppConfig>,
+) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use std::path::Path;
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the steps
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&app, req).await;
+
+    // Create two steps, the first one with a sound
+    let id = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":1,"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the color of the sky?","answer":"blue"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let id2 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        r#"{"rank":2,"latitude":45.16667,"longitude":5.71667,"location_hint":"go there after","question":"what is the color of the grass?","answer":"green"}"#,
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let mut sound_body = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    sound_body.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id}.mp3"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(sound_body.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Names that must never reach a file outside of the stored media of a step
+    let names = [
+        "..%2F..%2FCargo.toml".to_string(),
+        "%2E%2E%2F%2E%2E%2FCargo.toml".to_string(),
+        "..%5C..%5CCargo.toml".to_string(),
+        "%252E%252E%252FCargo.toml".to_string(),
+        "%2Fetc%2Fpasswd".to_string(),
+        "..%2F..%2Fdb%2Ftest_db.sqlite".to_string(),
+        "..%2Fimages%2F1.jpg".to_string(),
+        format!("{id}%2F..%2F..%2F..%2FCargo.toml"),
+        format!("{id}.mp3%2F..%2F..%2F..%2FCargo.toml"),
+        format!("{id}.mp3%00.jpg"),
+        format!("{id}.mp3.jpg"),
+        format!("{id}..mp3"),
+        format!("{id}."),
+        format!("{id}.toml"),
+        format!("{id}.verylongext"),
+        format!("{id}.mp3%20"),
+        format!("-{id}"),
+        format!("+{id}"),
+        format!("0x{id:x}"),
+        format!("{id2}.mp3"),
+    ];
+
+    // Every file serving route must answer not found
+    for name in &names {
+        for uri in [
+            format!("/api/steps/medias/{name}"),
+            format!("/api/steps/images/{name}"),
+            format!("/api/steps/images/{name}?size=thumbnail"),
+        ] {
+            for method in [Method::GET, Method::HEAD] {
+                let req = test::TestRequest::with_uri(&uri)
+                    .method(method.clone())
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert!(
+                    resp.status() == StatusCode::NOT_FOUND
+                        || resp.status() == StatusCode::METHOD_NOT_ALLOWED,
+                    "{method} {uri} answered {}",
+                    resp.status()
+                );
+                let body = test::read_body(resp).await;
+                assert!(!body.starts_with(b"[package]"));
+            }
+        }
+    }
+
+    // Non normalized paths must not be resolved either
+    for uri in [
+        "/api/steps/medias/../../Cargo.toml",
+        "/api/steps/medias/./../../Cargo.toml",
+        "/api/steps/medias//etc/passwd",
+    ] {
+        let req = test::TestRequest::with_uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(resp.status(), StatusCode::OK, "GET {uri} was served");
+    }
+
+    // Uploads and deletions must not be able to reach other files (the names that designate a step are legitimate for uploads)
+    for name in names
+        .iter()
+        .filter(|n| **n != format!("{id}.toml") && **n != format!("{id2}.mp3"))
+    {
+        let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{name}"))
+            .method(Method::POST)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .set_payload(sound_body.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(resp.status(), StatusCode::OK, "upload to {name} succeeded");
+        let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{name}"))
+            .method(Method::DELETE)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(
+            resp.status(),
+            StatusCode::OK,
+            "deletion of {name} succeeded"
+        );
+    }
+    assert!(Path::new("Cargo.toml").is_file());
+    assert!(!Path::new("data/items/Cargo.toml").exists());
+
+    // The stored media must still be there, served with its stored type
+    for name in [format!("{id}"), format!("{id}.mp3"), format!("{id}.MP3")] {
+        let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{name}")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "audio/mpeg");
+        assert_eq!(
+            resp.headers().get("x-content-type-options").unwrap(),
+            "nosniff"
+        );
+        let body = test::read_body(resp).await;
+        assert_eq!(body, sound_body);
+    }
+
+    // A media left by a previous version without metadata is only recorded if it is an allowed type
+    std::fs::write(
+        format!("data/items/medias/{id2}.html"),
+        "<script>alert(1)</script>",
+    )
+    .unwrap();
+    crate::models::step::record_legacy_medias(
+        &mut pool.get().unwrap(),
+        app_config.storage.as_ref(),
+    )
+    .unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id2}.html")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    std::fs::remove_file(format!("data/items/medias/{id2}.html")).unwrap();
+    std::fs::write(format!("data/items/medias/{id2}.bin"), &sound_body).unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id2}"))
+        .method(Method::HEAD)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    crate::models::step::record_legacy_medias(
+        &mut pool.get().unwrap(),
+        app_config.storage.as_ref(),
+    )
+    .unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/medias/{id2}"))
+        .method(Method::HEAD)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, format!("{id2}.mp3"));
+    assert!(!Path::new(&format!("data/items/medias/{id2}.bin")).exists());
+
+    // Delete all the steps, with their medias
+    for oid in [id, id2] {
+        let req = test::TestRequest::with_uri(&format!("/api/steps/{oid}"))
+            .method(Method::DELETE)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    assert!(!Path::new(&format!("data/items/medias/{id2}.mp3")).exists());
+}
@@ -1,9 +1,22 @@
-use log::{debug, info};
+use actix_web::http::StatusCode;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::{
-    auth::AppConfig, crud_delete, crud_delete_all, crud_read, crud_read_all, crud_use,
-    errors::ServerError, models::step::Step, schema::users,
+    auth::AppConfig,
+    crud_delete, crud_delete_all, crud_read, crud_read_all, crud_use,
+    db::DbConnection,
+    errors::{ErrorBody, ServerError},
+    models::{
+        anticheat,
+        crud::{etag, merged, Precondition},
+        photo,
+        step::{self, Step, ValidationMode},
+        version::{self, step_at, step_of_player},
+    },
+    schema::users,
+    utils::{get_bearing, get_dist},
 };
 
 use argon2::{
@@ -15,7 +28,7 @@ macro_rules! trim {
         fn trim(&mut self) -> Result<&Self, ServerError> {
             self.name = self.name.trim().to_string();
             if self.password.is_empty() {
-                return Err(ServerError::NotAcceptable(
+                return Err(ServerError::BadRequest(
                     "password cannot be empty".to_string(),
                 ));
             }
@@ -27,7 +40,7 @@ macro_rules! trim {
                     self.password = password.to_string();
                     Ok(self)
                 }
-                Err(_) => Err(ServerError::NotAcceptable(
+                Err(_) => Err(ServerError::BadRequest(
                     "the provided password is not acceptable".to_string(),
                 )),
             }
@@ -36,25 +49,39 @@ macro_rules! trim {
 }
 
 #[derive(
-    Debug, Clone, Serialize, Deserialize, Queryable, Insertable, AsChangeset, Identifiable,
+    Debug, Clone, Serialize, Deserialize, Queryable, Insertable, AsChangeset, Identifiable, ToSchema,
 )]
 #[diesel(table_name = users)]
 pub struct User {
     pub id: i32,
     pub name: String,
-    #[serde(skip_serializing)]
+    // An empty password keeps the current one
+    #[serde(default, skip_serializing)]
     pub password: String,
     pub current_step: i32,
+    // Published version of the hunt the user plays, none while they follow the draft
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<i32>,
+    // Step the user is solving, current_step being its position in their route
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_id: Option<i32>,
+    // Raised by every change, sent as the ETag
+    #[serde(default, skip_serializing)]
+    pub revision: i32,
 }
 impl User {
     trim!();
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Insertable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Insertable, ToSchema)]
 #[diesel(table_name = users)]
 pub struct NewUser {
     pub name: String,
     pub password: String,
+    #[serde(skip)]
+    pub version_id: Option<i32>,
+    #[serde(skip)]
+    pub step_id: Option<i32>,
 }
 impl NewUser {
     trim!();
@@ -62,80 +89,382 @@ impl NewUser {
 
 crud_use!();
 
+// New players start on the first step of the latest published version
+pub fn insert(conn: &mut DbConnection, mut o: NewUser) -> Result<User, ServerError> {
+    use crate::schema::users::dsl::*;
+    o.trim()?;
+    o.version_id = version::latest(conn)?;
+    let first = step_at(conn, o.version_id, 1).optional()?;
+    o.step_id = draft_step(conn, first.as_ref())?;
+    Ok(diesel::insert_into(users)
+        .values(&o)
+        .get_result::<User>(conn)?)
+}
+
+#[utoipa::path(
+    summary = "Register a player",
+    responses((status = 201, body = User, headers(("ETag" = String)))),
+)]
 #[post("")]
 pub async fn create(
     pool: web::Data<DbPool>,
-    mut o: web::Json<NewUser>,
+    o: web::Json<NewUser>,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
-    let created_o: Result<User, ServerError> = web::block(move || {
-        use crate::schema::users::dsl::*;
-        o.trim()?;
-        diesel::insert_into(users).values(&*o).execute(&mut conn)?;
-        let o = users.order(id.desc()).first::<User>(&mut conn)?;
-        Ok(o)
-    })
-    .await?;
-    Ok(HttpResponse::Created().json(created_o?))
+    let o = o.into_inner();
+    let created = crate::db::run(&pool, move |conn| insert(conn, o)).await?;
+    Ok(HttpResponse::Created()
+        .insert_header(etag(created.revision))
+        .json(created))
 }
 
-crud_read_all!(User, users);
+crud_read_all!(User, users, sort: [id, name, current_step], contains: [name], range: [current_step]);
 crud_read!(User, users);
 
-#[put("/{oid}")]
-pub async fn update(
-    pool: web::Data<DbPool>,
-    mut o: web::Json<User>,
-    oid: web::Path<i32>,
-    _: Authenticated,
-) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
-    let put_o: Result<User, ServerError> = web::block(move || {
+// Replace a player by what the change makes of them, unless they changed since the client read them
+fn change(
+    conn: &mut DbConnection,
+    oid: i32,
+    precondition: &Precondition,
+    change: impl FnOnce(&User) -> Result<User, ServerError>,
+) -> Result<User, ServerError> {
+    conn.immediate_transaction(|conn| {
         use crate::schema::users::dsl::*;
+        let u = users.filter(id.eq(oid)).first::<User>(conn)?;
+        precondition.check(u.revision)?;
+        let mut o = change(&u)?;
 
         // Do not update password if the given password is empty
-        let u = users.filter(id.eq(*oid)).first::<User>(&mut conn)?;
         if o.password.is_empty() {
-            o.password = u.password;
+            o.password = u.password.clone();
             o.name = o.name.trim().to_string();
         } else {
             o.trim()?;
         }
+        o.id = oid;
+        o.revision = u.revision + 1;
 
         diesel::update(users)
-            .filter(id.eq(*oid))
-            .set(&*o)
-            .execute(&mut conn)?;
+            .filter(id.eq(oid))
+            .set(&o)
+            .execute(conn)?;
+        // Organizers move players by position, to the step found there in their route
+        let s = step_at(conn, o.version_id.or(u.version_id), o.current_step).optional()?;
+        let sid = draft_step(conn, s.as_ref())?;
+        diesel::update(users)
+            .filter(id.eq(oid))
+            .set(step_id.eq(sid))
+            .execute(conn)?;
 
-        let u = users.filter(id.eq(*oid)).first::<User>(&mut conn)?;
-        Ok(u)
+        Ok(users.filter(id.eq(oid)).first::<User>(conn)?)
     })
-    .await?;
+}
 
-    Ok(HttpResponse::Ok().json(put_o?))
+pub fn save(
+    conn: &mut DbConnection,
+    oid: i32,
+    o: User,
+    precondition: &Precondition,
+) -> Result<User, ServerError> {
+    change(conn, oid, precondition, |_| Ok(o))
+}
+
+// Apply a JSON merge patch to a player, a password in it replaces the current one
+pub fn patch_player(
+    conn: &mut DbConnection,
+    oid: i32,
+    p: &serde_json::Value,
+    precondition: &Precondition,
+) -> Result<User, ServerError> {
+    change(conn, oid, precondition, |u| merged(u, p))
+}
+
+#[utoipa::path(
+    summary = "Replace a player",
+    description = "An empty `password` keeps the current one, another one replaces it",
+    params(("If-Match" = Option<String>, Header)),
+    request_body = User,
+    responses((status = 200, body = User, headers(("ETag" = String)))),
+    security(("token" = [])),
+)]
+#[put("/{oid}")]
+pub async fn update(
+    pool: web::Data<DbPool>,
+    o: web::Json<User>,
+    oid: web::Path<i32>,
+    precondition: Precondition,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let (oid, o) = (*oid, o.into_inner());
+    let updated = crate::db::run(&pool, move |conn| save(conn, oid, o, &precondition)).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(updated.revision))
+        .json(updated))
+}
+
+#[utoipa::path(
+    summary = "Change some fields of a player",
+    description = "A JSON merge patch, where `null` removes a field",
+    params(("If-Match" = Option<String>, Header)),
+    request_body = Object,
+    responses((status = 200, body = User, headers(("ETag" = String)))),
+    security(("token" = [])),
+)]
+#[patch("/{oid}")]
+pub async fn patch(
+    pool: web::Data<DbPool>,
+    p: web::Json<serde_json::Value>,
+    oid: web::Path<i32>,
+    precondition: Precondition,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let oid = *oid;
+    let patched = crate::db::run(&pool, move |conn| {
+        patch_player(conn, oid, &p, &precondition)
+    })
+    .await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(patched.revision))
+        .json(patched))
 }
 
 crud_delete!(User, users);
+
+// The step a player is on, if it is still in the draft: the steps deleted from the draft are only
+// found in the version of their players, by their position
+fn draft_step(conn: &mut DbConnection, s: Option<&Step>) -> QueryResult<Option<i32>> {
+    match s {
+        Some(s) if step::exists(conn, s.id)? => Ok(Some(s.id)),
+        _ => Ok(None),
+    }
+}
+
+// Put a player on a step of their route
+pub(crate) fn move_player(
+    conn: &mut DbConnection,
+    uid: i32,
+    s: &Step,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::users::dsl::*;
+    let sid = draft_step(conn, Some(s))?;
+    diesel::update(users.find(uid))
+        .set((
+            step_id.eq(sid),
+            current_step.eq(s.rank),
+            revision.eq(revision + 1),
+        ))
+        .execute(conn)
+}
 crud_delete_all!(User, users);
 
-#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, ToSchema)]
 pub struct Answer {
     pub password: String,
+    // The position may be omitted for steps that do not check it
+    #[serde(default)]
     pub latitude: f64,
+    #[serde(default)]
     pub longitude: f64,
     pub answer: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(tag = "type")]
-enum Message {
+pub(crate) enum Message {
+    Success(CurrentStep),
+}
+
+// Why a player cannot advance, or submit a photo
+pub(crate) enum Refusal {
     WrongPassword,
     WrongPlace { distance: f64 },
     WrongAnswer,
-    Success(Step),
+    Suspicious,
+    PhotoRequired,
+    AwaitingValidation,
+    NoMoreSteps,
+}
+
+impl From<Refusal> for ServerError {
+    fn from(r: Refusal) -> Self {
+        let (status, code, message) = match r {
+            Refusal::WrongPassword => (
+                StatusCode::FORBIDDEN,
+                "wrong_password",
+                "the password is wrong".to_string(),
+            ),
+            Refusal::WrongPlace { distance } => {
+                return ServerError::Refused {
+                    status: StatusCode::NOT_ACCEPTABLE,
+                    code: "wrong_place",
+                    message: format!("the step is {distance:.0} meters away"),
+                    details: Some(serde_json::json!({ "distance": distance })),
+                }
+            }
+            Refusal::WrongAnswer => (
+                StatusCode::NOT_ACCEPTABLE,
+                "wrong_answer",
+                "the answer is wrong".to_string(),
+            ),
+            Refusal::Suspicious => (
+                StatusCode::NOT_ACCEPTABLE,
+                "suspicious",
+                "the reported position is not plausible".to_string(),
+            ),
+            Refusal::PhotoRequired => (
+                StatusCode::NOT_ACCEPTABLE,
+                "photo_required",
+                "the step is validated with a photo".to_string(),
+            ),
+            Refusal::AwaitingValidation => (
+                StatusCode::NOT_ACCEPTABLE,
+                "awaiting_validation",
+                "the photo of the step is awaiting validation".to_string(),
+            ),
+            Refusal::NoMoreSteps => (
+                StatusCode::NOT_FOUND,
+                "no_more_steps",
+                "there are no more steps".to_string(),
+            ),
+        };
+        ServerError::Refused {
+            status,
+            code,
+            message,
+            details: None,
+        }
+    }
+}
+
+// Maximum distance from the step location for the location check to pass, in meters
+const MAX_DISTANCE: f64 = 50.0;
+
+pub(crate) fn check_password(u: &User, password: &str) -> Result<(), ServerError> {
+    let parsed_hash =
+        PasswordHash::new(&u.password).map_err(|_| ServerError::from(Refusal::WrongPassword))?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .map_err(|_| ServerError::from(Refusal::WrongPassword))
+}
+
+fn answer_matches(given: &str, good: &str) -> bool {
+    let remove_accents = |x| match x {
+        'é' => 'e',
+        'ê' => 'e',
+        'è' => 'e',
+        'É' => 'E',
+        'Ê' => 'E',
+        'È' => 'E',
+        c => c,
+    };
+    let good_answer: String = good
+        .chars()
+        .map(remove_accents)
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect();
+
+    let given_answer: String = given
+        .chars()
+        .map(remove_accents)
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect();
+
+    debug!("given answer: {}", given_answer);
+    debug!("good answer:  {}", good_answer);
+
+    sublime_fuzzy::best_match(&given_answer, &good_answer).is_some()
+}
+
+// Advance a player to their next step if their answer, and their position when it is checked, are
+// right
+pub fn advance_player(
+    conn: &mut DbConnection,
+    oid: i32,
+    answer: &Answer,
+    location_check: bool,
+) -> Result<CurrentStep, ServerError> {
+    use crate::schema::users::dsl::*;
+    // Get the user with that id
+    let u = users.find(oid).first::<User>(conn)?;
+
+    // Check if the given password is correct
+    check_password(&u, &answer.password)?;
+
+    // Get the user's current step
+    let s = step_of_player(conn, &u)
+        .optional()?
+        .ok_or(Refusal::NoMoreSteps)?;
+
+    // Check that the location is close enough
+    if location_check && s.validation_mode.checks_location() {
+        // Check that the reported position is plausible
+        let settings = anticheat::settings(conn)?;
+        let flags = anticheat::check(conn, &settings, u.id, &s, answer.latitude, answer.longitude)?;
+        if !flags.is_empty() {
+            warn!("Suspicious advance from user {}: {:?}", u.id, flags);
+            if settings.reject {
+                return Err(Refusal::Suspicious.into());
+            }
+        }
+
+        let dist = get_dist(answer.latitude, answer.longitude, s.latitude, s.longitude);
+        info!("Distance: {}", dist);
+        if dist > MAX_DISTANCE {
+            return Err(Refusal::WrongPlace { distance: dist }.into());
+        }
+    }
+
+    // Check that the given answer or code is correct
+    let correct = match s.validation_mode {
+        ValidationMode::LocationAndAnswer | ValidationMode::AnswerOnly => {
+            answer_matches(&answer.answer, &s.answer)
+        }
+        ValidationMode::SecretCode => s
+            .secret_code
+            .as_deref()
+            .is_some_and(|c| c.eq_ignore_ascii_case(answer.answer.trim())),
+        ValidationMode::LocationOnly => true,
+        // Photos are validated by an organizer, not by advancing
+        ValidationMode::Photo => {
+            return Err(match photo::is_pending(conn, u.id, s.id)? {
+                true => Refusal::AwaitingValidation,
+                false => Refusal::PhotoRequired,
+            }
+            .into());
+        }
+    };
+    if !correct {
+        return Err(Refusal::WrongAnswer.into());
+    }
+
+    // If so, search the next step...
+    let s = step_at(conn, u.version_id, s.rank + 1)
+        .optional()?
+        .ok_or(Refusal::NoMoreSteps)?;
+    // ... update the user's step if the step exists...
+    move_player(conn, u.id, &s)?;
+    // ... and return the step
+    Ok(CurrentStep {
+        step: s.for_player(),
+        awaiting_validation: false,
+        version_id: u.version_id,
+    })
 }
 
 // Advance step if all is ok
+#[utoipa::path(
+    summary = "Validate the current step and go to the next one",
+    responses(
+        (status = 200, body = Message),
+        (status = 403, body = ErrorBody, description = "`wrong_password`"),
+        (status = 404, body = ErrorBody, description = "`no_more_steps`"),
+        (
+            status = 406,
+            body = ErrorBody,
+            description = "`wrong_place` with the `distance` in the details, `wrong_answer`, \
+                           `suspicious`, `photo_required` or `awaiting_validation`"
+        ),
+    ),
+)]
 #[post("/{oid}/advance")]
 pub async fn advance(
     pool: web::Data<DbPool>,
@@ -143,122 +472,156 @@ pub async fn advance(
     answer: web::Json<Answer>,
     config: web::Data<AppConfig>,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
-    let step = web::block(move || {
-        use crate::schema::steps::dsl::rank;
-        use crate::schema::steps::dsl::steps;
-        use crate::schema::users::dsl::*;
-        // Get the user with that id
-        let u = users.find(*oid).first::<User>(&mut conn)?;
-
-        // Check if the given password is correct
-        let parsed_hash = PasswordHash::new(&u.password).map_err(|_| {
-            ServerError::Forbidden(serde_json::to_string(&Message::WrongPassword).unwrap())
-        })?;
-        if !Argon2::default()
-            .verify_password(answer.password.as_bytes(), &parsed_hash)
-            .is_ok()
-        {
-            return Err(ServerError::Forbidden(
-                serde_json::to_string(&Message::WrongPassword).unwrap(),
-            ));
-        }
-
-        // Get the user's current step
-        let s = steps
-            .filter(rank.eq(u.current_step))
-            .first::<Step>(&mut conn)?;
-
-        // Check that the location is close enough
-        if config.location_check {
-            let dist = get_dist(answer.latitude, answer.longitude, s.latitude, s.longitude);
-            info!("Distance: {}", dist);
-            if dist > 50.0 {
-                return Err(ServerError::NotAcceptable(
-                    serde_json::to_string(&Message::WrongPlace { distance: dist }).unwrap(),
-                ));
-            }
-        }
+    let (oid, location_check) = (*oid, config.location_check);
+    let step = crate::db::run(&pool, move |conn| {
+        advance_player(conn, oid, &answer, location_check)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(&Message::Success(step)))
+}
 
-        // Check that the given answer is correct
-        let remove_accents = |x| match x {
-            'é' => 'e',
-            'ê' => 'e',
-            'è' => 'e',
-            'É' => 'E',
-            'Ê' => 'E',
-            'È' => 'E',
-            c => c,
-        };
-        let good_answer: String = s
-            .answer
-            .chars()
-            .map(remove_accents)
-            .filter(|c| c.is_alphanumeric() || c.is_whitespace())
-            .collect();
-
-        let given_answer: String = answer
-            .answer
-            .chars()
-            .map(remove_accents)
-            .filter(|c| c.is_alphanumeric() || c.is_whitespace())
-            .collect();
-
-        debug!("given answer: {}", given_answer);
-        debug!("good answer:  {}", good_answer);
-
-        if sublime_fuzzy::best_match(&given_answer, &good_answer).is_none() {
-            return Err(ServerError::NotAcceptable(
-                serde_json::to_string(&Message::WrongAnswer).unwrap(),
-            ));
-        }
+#[derive(Serialize, ToSchema)]
+pub(crate) struct CurrentStep {
+    #[serde(flatten)]
+    step: Step,
+    // A photo was submitted for this step and an organizer has not reviewed it yet
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    awaiting_validation: bool,
+    // The version the step belongs to, whose files are asked for with it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_id: Option<i32>,
+}
 
-        // If so, search the next step...
-        let s = steps
-            .filter(rank.eq(u.current_step + 1))
-            .first::<Step>(&mut conn)?;
-        // ... update the user's step if the step exists...
-        diesel::update(users)
-            .filter(id.eq(*oid))
-            .set(current_step.eq(s.rank))
-            .execute(&mut conn)?;
-        // ... and return the step
-        Ok(s)
+fn find_current_step(conn: &mut DbConnection, oid: i32) -> QueryResult<CurrentStep> {
+    use crate::schema::users::dsl::*;
+    // Get the user with that id
+    let u = users.find(oid).first::<User>(conn)?;
+    // ...and respond with their current step
+    let s = step_of_player(conn, &u)?;
+    let awaiting_validation =
+        s.validation_mode == ValidationMode::Photo && photo::is_pending(conn, u.id, s.id)?;
+    Ok(CurrentStep {
+        step: s.for_player(),
+        awaiting_validation,
+        version_id: u.version_id,
     })
-    .await??;
-    Ok(HttpResponse::Ok().json(&Message::Success(step)))
 }
 
 // Get current step
+#[utoipa::path(summary = "Read the current step of a player", responses((status = 200, body = CurrentStep)))]
 #[get("/{oid}/current_step")]
 pub async fn current_step(
     pool: web::Data<DbPool>,
     oid: web::Path<i32>,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = pool.get()?;
-    let step = web::block(move || {
-        use crate::schema::steps::dsl::rank;
-        use crate::schema::steps::dsl::steps;
-        use crate::schema::users::dsl::*;
-        // Get the user with that id
-        let u = users.find(*oid).first::<User>(&mut conn)?;
-        // ...and respond with his current step
-        match steps
-            .filter(rank.eq(u.current_step))
-            .first::<Step>(&mut conn)
-        {
-            Ok(s) => Ok(s),
-            Err(e) => Err(e),
+    let oid = *oid;
+    let step = crate::db::run(&pool, move |conn| find_current_step(conn, oid)).await?;
+    Ok(HttpResponse::Ok().json(step))
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, ToSchema)]
+pub struct Ping {
+    pub password: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Serialize, PartialEq, ToSchema)]
+pub(crate) enum Band {
+    Cold,
+    Warm,
+    Hot,
+    Here,
+}
+
+impl Band {
+    fn from_distance(distance: f64) -> Self {
+        if distance <= MAX_DISTANCE {
+            Band::Here
+        } else if distance <= 200.0 {
+            Band::Hot
+        } else if distance <= 1000.0 {
+            Band::Warm
+        } else {
+            Band::Cold
         }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Proximity {
+    band: Band,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bearing: Option<f64>,
+    location_ok: bool,
+}
+
+fn proximity(
+    conn: &mut DbConnection,
+    oid: i32,
+    position: &Ping,
+    location_check: bool,
+) -> Result<Proximity, ServerError> {
+    use crate::schema::users::dsl::*;
+    // Get the user with that id
+    let u = users.find(oid).first::<User>(conn)?;
+    check_password(&u, &position.password)?;
+
+    // Get the user's current step...
+    let s = step_of_player(conn, &u)
+        .optional()?
+        .ok_or(Refusal::NoMoreSteps)?;
+
+    // ... and work out where it is from the user
+    let dist = get_dist(
+        position.latitude,
+        position.longitude,
+        s.latitude,
+        s.longitude,
+    );
+    let band = Band::from_distance(dist);
+    let bearing = if s.show_bearing && band != Band::Here {
+        Some(
+            get_bearing(
+                position.latitude,
+                position.longitude,
+                s.latitude,
+                s.longitude,
+            )
+            .round(),
+        )
+    } else {
+        None
+    };
+    Ok(Proximity {
+        band,
+        bearing,
+        location_ok: !location_check
+            || !s.validation_mode.checks_location()
+            || dist <= MAX_DISTANCE,
     })
-    .await??;
-    Ok(HttpResponse::Ok().json(step))
 }
 
-fn get_dist(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
-    let part_one: f64 = (90.0 - lat1).to_radians().cos() * (90.0 - lat2).to_radians().cos();
-    let part_two: f64 = (90.0 - lat1).to_radians().sin()
-        * (90.0 - lat2).to_radians().sin()
-        * (lng1 - lng2).to_radians().cos();
-    (part_one + part_two).acos() * 6371.0 * 1000.0
+// Tell how close the user is from their current step
+#[utoipa::path(
+    summary = "Tell how close a player is from their current step",
+    responses(
+        (status = 200, body = Proximity),
+        (status = 403, body = ErrorBody, description = "`wrong_password`"),
+        (status = 404, body = ErrorBody, description = "`no_more_steps`"),
+    ),
+)]
+#[post("/{oid}/ping")]
+pub async fn ping(
+    pool: web::Data<DbPool>,
+    oid: web::Path<i32>,
+    ping: web::Json<Ping>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, ServerError> {
+    let (oid, location_check) = (*oid, config.location_check);
+    let proximity = crate::db::run(&pool, move |conn| {
+        proximity(conn, oid, &ping, location_check)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(proximity))
 }
@@ -1,9 +1,6 @@
-use crate::{auth::AppConfig, create_app};
+use crate::{auth::AppConfig, create_app, tester::error_json};
 
-pub async fn user_test(
-    pool: &r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
-    app_config: &actix_web::web::Data<AppConfig>,
-) {
+pub async fn user_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
     use crate::{do_test, do_test_extract_id};
     use actix_web::{
         http::{Method, StatusCode},
@@ -12,6 +9,13 @@ pub async fn user_test(
 
     let mut app = test::init_service(create_app!(pool, app_config)).await;
 
+    // Delete all the steps, so that the users are on none
+    let req = test::TestRequest::delete()
+        .insert_header(("Authorization", "Bearer 0101"))
+        .uri("/api/steps")
+        .to_request();
+    test::call_service(&mut app, req).await;
+
     // Delete all the users with no token
     do_test!(
         app,
@@ -20,7 +24,7 @@ pub async fn user_test(
         "/api/users",
         "",
         StatusCode::UNAUTHORIZED,
-        "authorization header is too short"
+        error_json("unauthorized", "authorization header is too short")
     );
 
     // Delete all the users with a wrong token
@@ -31,7 +35,7 @@ pub async fn user_test(
         "/api/users",
         "",
         StatusCode::FORBIDDEN,
-        "wrong token"
+        error_json("forbidden", "wrong token")
     );
 
     // Delete all the users
@@ -48,8 +52,8 @@ pub async fn user_test(
         Method::POST,
         "/api/users",
         r#"{"name":"  Test name  ","password":""}"#,
-        StatusCode::NOT_ACCEPTABLE,
-        "password cannot be empty"
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "password cannot be empty")
     );
 
     // Create a user
@@ -82,7 +86,7 @@ pub async fn user_test(
         &format!("/api/users/{}", id + 1),
         "",
         StatusCode::NOT_FOUND,
-        "Item not found"
+        error_json("not_found", "Item not found")
     );
 
     // Patch the user
@@ -117,7 +121,7 @@ pub async fn user_test(
         &format!("/api/users/{}", id + 1),
         "",
         StatusCode::NOT_FOUND,
-        "Item not found"
+        error_json("not_found", "Item not found")
     );
 
     // Delete all the users
@@ -0,0 +1,613 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{AppConfig, Authenticated},
+    db::DbConnection,
+    errors::ServerError,
+    models::{
+        asset::{self, release_file, Asset, AssetKind, Attaching},
+        step::{step_files, Step, StepFile, ValidationMode},
+        user::{move_player, User},
+    },
+    schema::{published_assets, published_files, published_steps, step_assets, versions},
+    storage::Storage,
+    utils::now,
+};
+
+type DbPool = r2d2::Pool<ConnectionManager<DbConnection>>;
+
+// Where the copies of the files of the published steps are kept
+pub(crate) const PUBLISHED_DIR: &str = "published";
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, ToSchema)]
+#[diesel(table_name = versions)]
+pub struct Version {
+    pub id: i32,
+    pub published_at: i64,
+    // The versions published before their files were copied have them copied when the server starts
+    #[serde(skip)]
+    pub files_copied: bool,
+}
+
+// A step as it was published in a version
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = published_steps)]
+pub struct PublishedStep {
+    pub version_id: i32,
+    pub step_id: i32,
+    pub rank: i32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub location_hint: String,
+    pub question: String,
+    pub shake_message: Option<String>,
+    pub answer: String,
+    pub is_end: bool,
+    pub show_bearing: bool,
+    pub validation_mode: ValidationMode,
+    pub secret_code: Option<String>,
+}
+
+impl PublishedStep {
+    fn of(version_id: i32, s: Step) -> Self {
+        PublishedStep {
+            version_id,
+            step_id: s.id,
+            rank: s.rank,
+            latitude: s.latitude,
+            longitude: s.longitude,
+            location_hint: s.location_hint,
+            question: s.question,
+            shake_message: s.shake_message,
+            answer: s.answer,
+            is_end: s.is_end,
+            show_bearing: s.show_bearing,
+            validation_mode: s.validation_mode,
+            secret_code: s.secret_code,
+        }
+    }
+}
+
+// The copy of a file of a draft step in a version, found by the key of the draft file
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = published_files)]
+pub struct PublishedFile {
+    pub version_id: i32,
+    pub step_id: i32,
+    pub name: String,
+    pub file_key: String,
+    pub mime: String,
+}
+
+// An asset as it was published in a version, its file is shared with the draft
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = published_assets)]
+pub struct PublishedAsset {
+    pub version_id: i32,
+    pub id: i32,
+    pub step_id: i32,
+    pub position: i32,
+    pub kind: AssetKind,
+    pub caption: String,
+    pub mime: String,
+    pub extension: String,
+    pub checksum: String,
+    pub size: i64,
+    pub uploaded_at: i64,
+}
+
+impl PublishedAsset {
+    fn of(version_id: i32, a: Asset) -> Self {
+        PublishedAsset {
+            version_id,
+            id: a.id,
+            step_id: a.step_id,
+            position: a.position,
+            kind: a.kind,
+            caption: a.caption,
+            mime: a.mime,
+            extension: a.extension,
+            checksum: a.checksum,
+            size: a.size,
+            uploaded_at: a.uploaded_at,
+        }
+    }
+}
+
+impl From<PublishedAsset> for Asset {
+    fn from(p: PublishedAsset) -> Self {
+        Asset {
+            id: p.id,
+            step_id: p.step_id,
+            position: p.position,
+            kind: p.kind,
+            caption: p.caption,
+            mime: p.mime,
+            extension: p.extension,
+            checksum: p.checksum,
+            size: p.size,
+            uploaded_at: p.uploaded_at,
+        }
+    }
+}
+
+// Published steps are handed out as the draft step they come from, their files are then asked for
+// with their version
+impl From<PublishedStep> for Step {
+    fn from(p: PublishedStep) -> Self {
+        Step {
+            id: p.step_id,
+            rank: p.rank,
+            latitude: p.latitude,
+            longitude: p.longitude,
+            location_hint: p.location_hint,
+            question: p.question,
+            shake_message: p.shake_message,
+            answer: p.answer,
+            is_end: p.is_end,
+            show_bearing: p.show_bearing,
+            validation_mode: p.validation_mode,
+            secret_code: p.secret_code,
+            // Published steps never change
+            revision: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct Migration {
+    // Version the players are moved from
+    from: i32,
+    // Steps of the new version replacing the steps of the old one, by id. Steps that are in both
+    // versions need no mapping.
+    #[serde(default)]
+    mapping: HashMap<i32, i32>,
+}
+
+// The step at a rank of a version, or of the draft for the players who are not pinned to one
+pub fn step_at(conn: &mut DbConnection, version: Option<i32>, r: i32) -> QueryResult<Step> {
+    match version {
+        Some(vid) => {
+            use crate::schema::published_steps::dsl::*;
+            Ok(published_steps
+                .filter(version_id.eq(vid))
+                .filter(rank.eq(r))
+                .first::<PublishedStep>(conn)?
+                .into())
+        }
+        None => {
+            use crate::schema::steps::dsl::*;
+            steps.filter(rank.eq(r)).first::<Step>(conn)
+        }
+    }
+}
+
+// A step of a version, or of the draft, from its id
+pub fn step_of(conn: &mut DbConnection, version: Option<i32>, sid: i32) -> QueryResult<Step> {
+    match version {
+        Some(vid) => {
+            use crate::schema::published_steps::dsl::*;
+            Ok(published_steps
+                .filter(version_id.eq(vid))
+                .filter(step_id.eq(sid))
+                .first::<PublishedStep>(conn)?
+                .into())
+        }
+        None => {
+            use crate::schema::steps::dsl::*;
+            steps.find(sid).first::<Step>(conn)
+        }
+    }
+}
+
+// The step a player is solving, or the one at their position for the players who joined before
+// the steps were created
+pub fn step_of_player(conn: &mut DbConnection, u: &User) -> QueryResult<Step> {
+    match u.step_id {
+        Some(sid) => step_of(conn, u.version_id, sid),
+        None => step_at(conn, u.version_id, u.current_step),
+    }
+}
+
+// The latest version, that new players start on
+pub fn latest(conn: &mut DbConnection) -> QueryResult<Option<i32>> {
+    use crate::schema::versions::dsl::*;
+    versions
+        .select(id)
+        .order(id.desc())
+        .first::<i32>(conn)
+        .optional()
+}
+
+// The copy of a draft file in a version, from the key of the draft file
+pub fn published_file(
+    conn: &mut DbConnection,
+    vid: i32,
+    key: &str,
+) -> QueryResult<Option<PublishedFile>> {
+    use crate::schema::published_files::dsl::*;
+    published_files
+        .find((vid, key))
+        .first::<PublishedFile>(conn)
+        .optional()
+}
+
+// The copies of the files of a step in a version
+pub fn published_files_of(
+    conn: &mut DbConnection,
+    vid: i32,
+    sid: i32,
+) -> QueryResult<Vec<PublishedFile>> {
+    use crate::schema::published_files::dsl::*;
+    published_files
+        .filter(version_id.eq(vid))
+        .filter(step_id.eq(sid))
+        .order(name.asc())
+        .load::<PublishedFile>(conn)
+}
+
+// The assets of a step in a version, in their order
+pub fn assets_of(conn: &mut DbConnection, vid: i32, sid: i32) -> QueryResult<Vec<Asset>> {
+    use crate::schema::published_assets::dsl::*;
+    Ok(published_assets
+        .filter(version_id.eq(vid))
+        .filter(step_id.eq(sid))
+        .order(position.asc())
+        .load::<PublishedAsset>(conn)?
+        .into_iter()
+        .map(Asset::from)
+        .collect())
+}
+
+// An asset of a step in a version
+pub fn asset_of(conn: &mut DbConnection, vid: i32, sid: i32, aid: i32) -> QueryResult<Asset> {
+    use crate::schema::published_assets::dsl::*;
+    Ok(published_assets
+        .filter(version_id.eq(vid))
+        .filter(step_id.eq(sid))
+        .filter(id.eq(aid))
+        .first::<PublishedAsset>(conn)?
+        .into())
+}
+
+// The files the draft steps may have
+fn files_of(conn: &mut DbConnection, sids: &[i32]) -> Result<Vec<StepFile>, ServerError> {
+    let mut files = Vec::new();
+    for sid in sids {
+        files.extend(step_files(conn, *sid)?);
+    }
+    Ok(files)
+}
+
+// Copy the files that exist under a key made from their content, so that the versions with the same
+// file share its copy. The copies are attached until they are recorded.
+fn copy_files(
+    storage: &dyn Storage,
+    files: Vec<StepFile>,
+) -> std::io::Result<(Vec<PublishedFile>, Vec<Attaching>)> {
+    let (mut copies, mut attaching) = (Vec::new(), Vec::new());
+    for f in files {
+        if !storage.exists(&f.key)? {
+            continue;
+        }
+        let data = storage.get(&f.key)?;
+        let extension = f.key.rsplit_once('.').map_or("", |(_, ext)| ext);
+        let file_key = format!("{PUBLISHED_DIR}/{:x}.{extension}", Sha256::digest(&data));
+        attaching.push(Attaching::new(&file_key));
+        if !storage.exists(&file_key)? {
+            storage.put(&file_key, &data, &f.mime)?;
+        }
+        copies.push(PublishedFile {
+            version_id: 0,
+            step_id: f.step_id,
+            name: f.key,
+            file_key,
+            mime: f.mime,
+        });
+    }
+    Ok((copies, attaching))
+}
+
+// Record the copies of the files of the steps of a version, and their assets
+fn record_files(
+    conn: &mut DbConnection,
+    vid: i32,
+    sids: &[i32],
+    copies: &[PublishedFile],
+) -> QueryResult<()> {
+    for f in copies.iter().filter(|f| sids.contains(&f.step_id)) {
+        diesel::insert_into(published_files::table)
+            .values(&PublishedFile {
+                version_id: vid,
+                ..f.clone()
+            })
+            .execute(conn)?;
+    }
+    let assets = step_assets::table
+        .filter(step_assets::step_id.eq_any(sids))
+        .load::<Asset>(conn)?;
+    for a in assets {
+        diesel::insert_into(published_assets::table)
+            .values(&PublishedAsset::of(vid, a))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+// Tell if a version uses the copy of a file
+pub(crate) fn is_copy_used(conn: &mut DbConnection, key: &str) -> QueryResult<bool> {
+    use crate::schema::published_files::dsl::*;
+    diesel::select(diesel::dsl::exists(
+        published_files.filter(file_key.eq(key)),
+    ))
+    .get_result(conn)
+}
+
+// Remove the copies of files that no version uses anymore
+fn release_copies(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+    keys: &[String],
+) -> Result<(), ServerError> {
+    for key in keys {
+        release_file(storage, key, || is_copy_used(conn, key))?;
+    }
+    Ok(())
+}
+
+// Copy the files of the versions published before their files were copied, from the files of the
+// draft steps they used until then
+pub fn copy_legacy_files(
+    conn: &mut DbConnection,
+    storage: &dyn Storage,
+) -> Result<usize, ServerError> {
+    let legacy = versions::table
+        .filter(versions::files_copied.eq(false))
+        .select(versions::id)
+        .load::<i32>(conn)?;
+    for vid in &legacy {
+        let sids = published_steps::table
+            .filter(published_steps::version_id.eq(vid))
+            .select(published_steps::step_id)
+            .load::<i32>(conn)?;
+        let files = files_of(conn, &sids)?;
+        let (copies, _attaching) = copy_files(storage, files)?;
+        conn.immediate_transaction(|conn| {
+            record_files(conn, *vid, &sids, &copies)?;
+            diesel::update(versions::table.find(vid))
+                .set(versions::files_copied.eq(true))
+                .execute(conn)
+        })?;
+    }
+    Ok(legacy.len())
+}
+
+// Publish the draft steps as a new version, with the copies of their files. The players following
+// the draft are pinned to it, as it is what they were playing.
+pub fn insert(conn: &mut DbConnection, copies: &[PublishedFile]) -> Result<Version, ServerError> {
+    conn.immediate_transaction(|conn| {
+        let draft = crate::schema::steps::table
+            .order(crate::schema::steps::rank.asc())
+            .load::<Step>(conn)?;
+        if draft.is_empty() {
+            return Err(ServerError::BadRequest(
+                "there are no steps to publish".to_string(),
+            ));
+        }
+        let v = diesel::insert_into(versions::table)
+            .values((
+                versions::published_at.eq(now()),
+                versions::files_copied.eq(true),
+            ))
+            .get_result::<Version>(conn)?;
+        let sids: Vec<i32> = draft.iter().map(|s| s.id).collect();
+        for s in draft {
+            diesel::insert_into(published_steps::table)
+                .values(&PublishedStep::of(v.id, s))
+                .execute(conn)?;
+        }
+        record_files(conn, v.id, &sids, copies)?;
+        {
+            use crate::schema::users::dsl::*;
+            diesel::update(users.filter(version_id.is_null()))
+                .set((version_id.eq(v.id), revision.eq(revision + 1)))
+                .execute(conn)?;
+        }
+        Ok(v)
+    })
+}
+
+#[utoipa::path(
+    summary = "Publish the steps as a new version",
+    description = "New players play the latest version, the others stay on theirs",
+    responses((status = 201, body = Version)),
+    security(("token" = [])),
+)]
+#[post("")]
+pub async fn publish(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    // The files are copied before the version is recorded, so that no transaction waits for the storage
+    let files = crate::db::run(&pool, |conn| {
+        let sids = crate::schema::steps::table
+            .select(crate::schema::steps::id)
+            .load::<i32>(conn)?;
+        files_of(conn, &sids)
+    })
+    .await?;
+    let storage = app_config.storage.clone();
+    let (copies, attaching) = web::block(move || copy_files(storage.as_ref(), files)).await??;
+    let keys: Vec<String> = copies.iter().map(|f| f.file_key.clone()).collect();
+    let published = crate::db::run(&pool, move |conn| insert(conn, &copies)).await;
+    drop(attaching);
+
+    // The copies are removed again if the version could not be recorded, unless they are used
+    if published.is_err() {
+        let storage = app_config.storage.clone();
+        let _ = crate::db::run(&pool, move |conn| {
+            release_copies(conn, storage.as_ref(), &keys)
+        })
+        .await;
+    }
+    Ok(HttpResponse::Created().json(published?))
+}
+
+pub fn find_all(conn: &mut DbConnection) -> QueryResult<Vec<Version>> {
+    use crate::schema::versions::dsl::*;
+    versions.order(id.asc()).load::<Version>(conn)
+}
+
+#[utoipa::path(
+    summary = "List the versions",
+    responses((status = 200, body = Vec<Version>)),
+    security(("token" = [])),
+)]
+#[get("")]
+pub async fn read_all(
+    pool: web::Data<DbPool>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let all = crate::db::run(&pool, find_all).await?;
+    Ok(HttpResponse::Ok().json(all))
+}
+
+// The steps of a version, in their order
+pub fn steps_of(conn: &mut DbConnection, vid: i32) -> QueryResult<Vec<Step>> {
+    versions::table.find(vid).first::<Version>(conn)?;
+    use crate::schema::published_steps::dsl::*;
+    Ok(published_steps
+        .filter(version_id.eq(vid))
+        .order(rank.asc())
+        .load::<PublishedStep>(conn)?
+        .into_iter()
+        .map(Step::from)
+        .collect())
+}
+
+#[utoipa::path(
+    summary = "List the steps of a version",
+    responses((status = 200, body = Vec<Step>)),
+    security(("token" = [])),
+)]
+#[get("/{vid}/steps")]
+pub async fn read_steps(
+    pool: web::Data<DbPool>,
+    vid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let vid = *vid;
+    let published = crate::db::run(&pool, move |conn| steps_of(conn, vid)).await?;
+    Ok(HttpResponse::Ok().json(published))
+}
+
+// Move all the players of a version to this one, each staying on the same step or on the step it
+// is mapped to. Nobody is moved if a step has no counterpart in this version.
+pub fn migrate_players(
+    conn: &mut DbConnection,
+    vid: i32,
+    m: &Migration,
+) -> Result<Vec<User>, ServerError> {
+    conn.immediate_transaction(|conn| {
+        use crate::schema::users::dsl::*;
+        versions::table.find(vid).first::<Version>(conn)?;
+        versions::table.find(m.from).first::<Version>(conn)?;
+        let players = users
+            .filter(version_id.eq(m.from))
+            .order(id.asc())
+            .load::<User>(conn)?;
+        for u in &players {
+            let s = step_of_player(conn, u)?;
+            let mapped = m.mapping.get(&s.id).copied().unwrap_or(s.id);
+            let new_step =
+                step_of(conn, Some(vid), mapped)
+                    .optional()?
+                    .ok_or(ServerError::BadRequest(format!(
+                        "no step of version {} for step {}",
+                        vid, s.id
+                    )))?;
+            diesel::update(users.find(u.id))
+                .set((version_id.eq(vid), revision.eq(revision + 1)))
+                .execute(conn)?;
+            move_player(conn, u.id, &new_step)?;
+        }
+        Ok(users
+            .filter(id.eq_any(players.iter().map(|u| u.id)))
+            .order(id.asc())
+            .load::<User>(conn)?)
+    })
+}
+
+#[utoipa::path(
+    summary = "Move the players of a version to this one",
+    responses((status = 200, body = Vec<User>, description = "The players that moved")),
+    security(("token" = [])),
+)]
+#[post("/{vid}/migrate")]
+pub async fn migrate(
+    pool: web::Data<DbPool>,
+    vid: web::Path<i32>,
+    m: web::Json<Migration>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let vid = *vid;
+    let migrated = crate::db::run(&pool, move |conn| migrate_players(conn, vid, &m)).await?;
+    Ok(HttpResponse::Ok().json(migrated))
+}
+
+// Delete a version that no player plays anymore, and the copies of its files that no other version uses
+pub fn remove(conn: &mut DbConnection, storage: &dyn Storage, vid: i32) -> Result<(), ServerError> {
+    let (keys, assets) = conn.immediate_transaction(|conn| {
+        versions::table.find(vid).first::<Version>(conn)?;
+        let players: i64 = crate::schema::users::table
+            .filter(crate::schema::users::version_id.eq(vid))
+            .count()
+            .get_result(conn)?;
+        if players > 0 {
+            return Err(ServerError::BadRequest(format!(
+                "{players} players still play version {vid}"
+            )));
+        }
+        let keys = published_files::table
+            .filter(published_files::version_id.eq(vid))
+            .select(published_files::file_key)
+            .distinct()
+            .load::<String>(conn)?;
+        let assets: Vec<Asset> = published_assets::table
+            .filter(published_assets::version_id.eq(vid))
+            .load::<PublishedAsset>(conn)?
+            .into_iter()
+            .map(Asset::from)
+            .collect();
+        // Its steps and their files go with it
+        diesel::delete(versions::table.find(vid)).execute(conn)?;
+        Ok::<_, ServerError>((keys, assets))
+    })?;
+    release_copies(conn, storage, &keys)?;
+    asset::release(conn, storage, &assets)
+}
+
+#[utoipa::path(
+    summary = "Delete a version that no player plays anymore",
+    responses((status = 200, body = String)),
+    security(("token" = [])),
+)]
+#[delete("/{vid}")]
+pub async fn delete(
+    pool: web::Data<DbPool>,
+    app_config: web::Data<AppConfig>,
+    vid: web::Path<i32>,
+    _: Authenticated,
+) -> Result<HttpResponse, ServerError> {
+    let vid = *vid;
+    let storage = app_config.storage.clone();
+    crate::db::run(&pool, move |conn| remove(conn, storage.as_ref(), vid)).await?;
+    Ok(HttpResponse::Ok().body(format!("Deleted object with id: {}", vid)))
+}
@@ -0,0 +1,408 @@
+use crate::{auth::AppConfig, create_app, tester::error_json};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+fn new_step(rank: i32, answer: &str) -> String {
+    format!(
+        r#"{{"rank":{rank},"latitude":45.74846,"longitude":4.84671,"location_hint":"go there","question":"what is the answer?","answer":"{answer}","validation_mode":"AnswerOnly"}}"#
+    )
+}
+
+pub async fn version_test(pool: &crate::db::DbPool, app_config: &actix_web::web::Data<AppConfig>) {
+    use crate::{do_test, do_test_extract_id};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    let mut app = test::init_service(create_app!(pool, app_config)).await;
+
+    // Delete all the users and the steps
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    // Publish without a token (must fail), then without any step (must fail)
+    do_test!(
+        app,
+        "",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::UNAUTHORIZED,
+        ""
+    );
+    do_test!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", "there are no steps to publish")
+    );
+
+    // Create two steps and a player following the draft
+    let a = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        &new_step(1, "a"),
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let b = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        &new_step(2, "b"),
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let u1 = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"first","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let img_body = std::fs::read("test_img.jpg").unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{a}"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(img_body)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::with_uri(&format!("/api/steps/{a}/assets"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(b"%PDF-1.7\n%%EOF\n".to_vec())
+        .to_request();
+    let asset: Value = test::call_and_read_body_json(&app, req).await;
+    let asset_file = format!(
+        "data/items/assets/{}.pdf",
+        asset["checksum"].as_str().unwrap()
+    );
+
+    // Publish the first version, the player is pinned to it and so are new players
+    let v1 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let player = |id: i32| {
+        test::TestRequest::with_uri(&format!("/api/users/{id}"))
+            .insert_header(("Authorization", "Bearer 0101"))
+            .to_request()
+    };
+    let u: Value = test::call_and_read_body_json(&app, player(u1)).await;
+    assert_eq!(u["version_id"], v1);
+    let u2 = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"second","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let u: Value = test::call_and_read_body_json(&app, player(u2)).await;
+    assert_eq!(u["version_id"], v1);
+
+    // The files of the version are copies named after their content
+    let published_image = std::fs::read(format!("data/items/images/{a}.jpg")).unwrap();
+    let copy = format!(
+        "data/items/published/{:x}.jpg",
+        Sha256::digest(&published_image)
+    );
+    assert!(Path::new(&copy).exists());
+
+    // A version published before the files were copied has them copied from the draft
+    {
+        use crate::schema::{published_assets, published_files, versions};
+        use diesel::prelude::*;
+        let mut conn = pool.get().unwrap();
+        diesel::delete(published_files::table.filter(published_files::version_id.eq(v1)))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::delete(published_assets::table.filter(published_assets::version_id.eq(v1)))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::update(versions::table.find(v1))
+            .set(versions::files_copied.eq(false))
+            .execute(&mut conn)
+            .unwrap();
+        let mut copy_legacy_files = || {
+            crate::models::version::copy_legacy_files(&mut conn, app_config.storage.as_ref())
+                .unwrap()
+        };
+        assert_eq!(copy_legacy_files(), 1);
+        assert_eq!(copy_legacy_files(), 0);
+    }
+
+    // Change the image and remove the asset of the draft step, the pinned players still have theirs
+    let mut other_image = std::io::Cursor::new(Vec::new());
+    image::RgbImage::from_pixel(64, 64, image::Rgb([200, 30, 30]))
+        .write_to(&mut other_image, image::ImageFormat::Png)
+        .unwrap();
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{a}"))
+        .method(Method::POST)
+        .insert_header(("Authorization", "Bearer 0101"))
+        .set_payload(other_image.into_inner())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{a}/assets/{}", asset["id"]),
+        "",
+        StatusCode::OK,
+        "Deleted object with id: "
+    );
+    let body = |uri: String| {
+        let req = test::TestRequest::with_uri(&uri).to_request();
+        test::call_and_read_body(&app, req)
+    };
+    assert_ne!(
+        body(format!("/api/steps/images/{a}")).await,
+        published_image
+    );
+    assert_eq!(
+        body(format!("/api/steps/images/{a}?version={v1}")).await,
+        published_image
+    );
+    assert_eq!(body(format!("/api/steps/{a}/assets")).await, "[]");
+    let assets: Vec<Value> =
+        serde_json::from_slice(&body(format!("/api/steps/{a}/assets?version={v1}")).await).unwrap();
+    assert_eq!(assets, vec![asset.clone()]);
+    assert_eq!(
+        body(format!(
+            "/api/steps/{a}/assets/{}/file?version={v1}",
+            asset["id"]
+        ))
+        .await,
+        "%PDF-1.7\n%%EOF\n"
+    );
+    assert!(Path::new(&asset_file).exists());
+
+    // Insert a draft step before the others, the pinned players do not see it
+    let c = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/steps",
+        &new_step(1, "c"),
+        StatusCode::CREATED,
+        "{\"id\""
+    );
+    let current = |id: i32| {
+        test::TestRequest::with_uri(&format!("/api/users/{id}/current_step")).to_request()
+    };
+    let s: Value = test::call_and_read_body_json(&app, current(u1)).await;
+    assert_eq!(s["id"], a);
+    assert_eq!(s["version_id"], v1);
+    let s = do_test!(
+        app,
+        "",
+        Method::POST,
+        &format!("/api/users/{u1}/advance"),
+        r#"{"password":"pass","answer":"a"}"#,
+        StatusCode::OK,
+        r#"{"type":"Success""#
+    );
+    assert!(s.contains(&format!(r#""id":{b},"rank":2,"#)));
+    assert!(s.contains(&format!(r#""version_id":{v1}"#)));
+
+    // Publish the second version, new players start on its first step
+    let v2 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let u3 = do_test_extract_id!(
+        app,
+        "",
+        Method::POST,
+        "/api/users",
+        r#"{"name":"third","password":"pass"}"#,
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+    let s: Value = test::call_and_read_body_json(&app, current(u3)).await;
+    assert_eq!(s["id"], c);
+    let req = test::TestRequest::with_uri(&format!("/api/versions/{v2}/steps"))
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let published: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        published
+            .iter()
+            .map(|s| (s["id"].clone(), s["rank"].clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (c.into(), 1.into()),
+            (a.into(), 2.into()),
+            (b.into(), 3.into())
+        ]
+    );
+
+    // Move the players of the first version to the second one, they stay on the same steps
+    let migrate = |to: i32, body: String| {
+        test::TestRequest::with_uri(&format!("/api/versions/{to}/migrate"))
+            .method(Method::POST)
+            .insert_header(("Authorization", "Bearer 0101"))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body)
+            .to_request()
+    };
+    let migrated: Vec<Value> =
+        test::call_and_read_body_json(&app, migrate(v2, format!(r#"{{"from":{v1}}}"#))).await;
+    assert_eq!(
+        migrated
+            .iter()
+            .map(|u| (
+                u["id"].clone(),
+                u["version_id"].clone(),
+                u["current_step"].clone()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (u1.into(), v2.into(), 3.into()),
+            (u2.into(), v2.into(), 2.into())
+        ]
+    );
+
+    // Delete a step from the draft and publish the third version
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/steps/{a}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {a}")
+    );
+    let v3 = do_test_extract_id!(
+        app,
+        "0101",
+        Method::POST,
+        "/api/versions",
+        "",
+        StatusCode::CREATED,
+        r#"{"id":"#
+    );
+
+    // A player is on the deleted step, nobody moves until it is mapped to another one
+    let resp = test::call_service(&app, migrate(v3, format!(r#"{{"from":{v2}}}"#))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        test::read_body(resp).await,
+        error_json(
+            "bad_request",
+            &format!("no step of version {v3} for step {a}")
+        )
+    );
+    let u: Value = test::call_and_read_body_json(&app, player(u1)).await;
+    assert_eq!(u["version_id"], v2);
+    let migrated: Vec<Value> = test::call_and_read_body_json(
+        &app,
+        migrate(v3, format!(r#"{{"from":{v2},"mapping":{{"{a}":{c}}}}}"#)),
+    )
+    .await;
+    assert_eq!(
+        migrated
+            .iter()
+            .map(|u| (
+                u["id"].clone(),
+                u["version_id"].clone(),
+                u["current_step"].clone()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (u1.into(), v3.into(), 2.into()),
+            (u2.into(), v3.into(), 1.into()),
+            (u3.into(), v3.into(), 1.into())
+        ]
+    );
+
+    // The image of the deleted step is gone from the draft, the versions that still have it keep it
+    let req = test::TestRequest::with_uri(&format!("/api/steps/images/{a}")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let req =
+        test::TestRequest::with_uri(&format!("/api/steps/images/{a}?version={v2}")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // A version cannot be deleted while players play it
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/versions/{v3}"),
+        "",
+        StatusCode::BAD_REQUEST,
+        error_json("bad_request", &format!("3 players still play version {v3}"))
+    );
+    for v in [v1, v2] {
+        do_test!(
+            app,
+            "0101",
+            Method::DELETE,
+            &format!("/api/versions/{v}"),
+            "",
+            StatusCode::OK,
+            format!("Deleted object with id: {v}")
+        );
+    }
+    let req = test::TestRequest::with_uri("/api/versions")
+        .insert_header(("Authorization", "Bearer 0101"))
+        .to_request();
+    let versions: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        versions.iter().map(|v| v["id"].clone()).collect::<Vec<_>>(),
+        vec![Value::from(v3)]
+    );
+
+    // The files that only the deleted versions used are gone with them
+    assert!(!Path::new(&copy).exists());
+    assert!(!Path::new(&asset_file).exists());
+
+    // Delete everything
+    for uri in ["/api/users", "/api/steps"] {
+        let req = test::TestRequest::delete()
+            .insert_header(("Authorization", "Bearer 0101"))
+            .uri(uri)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+    do_test!(
+        app,
+        "0101",
+        Method::DELETE,
+        &format!("/api/versions/{v3}"),
+        "",
+        StatusCode::OK,
+        format!("Deleted object with id: {v3}")
+    );
+}
@@ -0,0 +1,186 @@
+use actix_web::{get, HttpResponse};
+use utoipa::{
+    openapi::{
+        path::Operation,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        RefOr, Response, ResponseBuilder,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    errors::ErrorBody,
+    models::{anticheat, asset, backup, maintenance, photo, qrcodes, step, user, version},
+};
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    user::advance,
+    user::current_step,
+    user::ping,
+    photo::submit,
+    user::read,
+    user::create,
+    user::read_all,
+    user::update,
+    user::patch,
+    user::delete_all,
+    user::delete,
+))]
+struct Users;
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    step::read,
+    step::retrieve_image,
+    step::retrieve_media,
+    step::check_media,
+    step::read_all,
+    step::create,
+    step::reorder,
+    step::update,
+    step::patch,
+    step::delete_all,
+    step::delete,
+    step::upload_image,
+    step::delete_image,
+    step::upload_media,
+    step::delete_media,
+    asset::read_all,
+    asset::create,
+    asset::read,
+    asset::retrieve_file,
+    asset::update,
+    asset::delete,
+))]
+struct Steps;
+
+#[derive(OpenApi)]
+#[openapi(paths(maintenance::read_orphans, maintenance::delete_orphans))]
+struct Maintenance;
+
+#[derive(OpenApi)]
+#[openapi(paths(backup::create, backup::read_all, backup::restore, backup::retrieve))]
+struct Backups;
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    anticheat::read_settings,
+    anticheat::update_settings,
+    anticheat::report,
+    anticheat::clear_report,
+))]
+struct AntiCheat;
+
+#[derive(OpenApi)]
+#[openapi(paths(photo::read_all, photo::retrieve_photo, photo::approve, photo::reject))]
+struct Photos;
+
+#[derive(OpenApi)]
+#[openapi(paths(qrcodes::step_code, qrcodes::user_code, qrcodes::sheet))]
+struct QrCodes;
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    version::publish,
+    version::read_all,
+    version::read_steps,
+    version::migrate,
+    version::delete,
+))]
+struct Versions;
+
+// The scopes mirror the ones of create_app!
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Pistou", description = "Treasure hunt game server"),
+    nest(
+        (path = "/api/users", api = Users),
+        (path = "/api/steps", api = Steps),
+        (path = "/api/maintenance", api = Maintenance),
+        (path = "/api/backups", api = Backups),
+        (path = "/api/anticheat", api = AntiCheat),
+        (path = "/api/photos", api = Photos),
+        (path = "/api/qrcodes", api = QrCodes),
+        (path = "/api/versions", api = Versions),
+    ),
+    paths(spec, docs),
+    components(schemas(ErrorBody)),
+    modifiers(&Conventions),
+)]
+pub struct ApiDoc;
+
+// What every operation shares: the organizers token, the errors, and an id made of the module
+// and the function of its handler, as they are given to create_app!
+struct Conventions;
+
+impl Modify for Conventions {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        for item in openapi.paths.paths.values_mut() {
+            for operation in operations(item) {
+                let tag = operation.tags.iter().flatten().next().cloned();
+                if let (Some(tag), Some(id)) = (tag, operation.operation_id.as_mut()) {
+                    *id = format!("{}_{}", tag, id);
+                }
+                operation
+                    .responses
+                    .responses
+                    .entry("default".to_string())
+                    .or_insert_with(error_response);
+            }
+        }
+    }
+}
+
+fn operations(item: &mut utoipa::openapi::PathItem) -> impl Iterator<Item = &mut Operation> {
+    [
+        &mut item.get,
+        &mut item.put,
+        &mut item.post,
+        &mut item.delete,
+        &mut item.head,
+        &mut item.patch,
+    ]
+    .into_iter()
+    .filter_map(Option::as_mut)
+}
+
+fn error_response() -> RefOr<Response> {
+    ResponseBuilder::new()
+        .description("An error, told apart by its code")
+        .content(
+            "application/json",
+            utoipa::openapi::ContentBuilder::new()
+                .schema(Some(utoipa::openapi::Ref::from_schema_name("ErrorBody")))
+                .build(),
+        )
+        .build()
+        .into()
+}
+
+#[utoipa::path(
+    tag = "openapi",
+    summary = "Describe the API",
+    responses((status = 200, body = Object))
+)]
+#[get("/api/openapi.json")]
+pub async fn spec() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[utoipa::path(
+    tag = "openapi",
+    summary = "Browse the API",
+    responses((status = 200, body = String, content_type = "text/html"))
+)]
+#[get("/api/docs")]
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("docs.html"))
+}
@@ -1,5 +1,117 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    anticheat_settings (id) {
+        id -> Integer,
+        reject -> Bool,
+        max_speed -> Double,
+        repeat_threshold -> Integer,
+        history_size -> Integer,
+    }
+}
+
+diesel::table! {
+    cheat_flags (id) {
+        id -> Integer,
+        user_id -> Integer,
+        step_id -> Integer,
+        kind -> Text,
+        details -> Text,
+        rejected -> Bool,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    medias (step_id) {
+        step_id -> Integer,
+        mime -> Text,
+        extension -> Text,
+        size -> BigInt,
+        uploaded_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    photo_submissions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        step_id -> Integer,
+        status -> Text,
+        submitted_at -> BigInt,
+        reviewed_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    positions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        latitude -> Double,
+        longitude -> Double,
+        recorded_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    step_assets (id) {
+        id -> Integer,
+        step_id -> Integer,
+        position -> Integer,
+        kind -> Text,
+        caption -> Text,
+        mime -> Text,
+        extension -> Text,
+        checksum -> Text,
+        size -> BigInt,
+        uploaded_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    published_assets (version_id, id) {
+        version_id -> Integer,
+        id -> Integer,
+        step_id -> Integer,
+        position -> Integer,
+        kind -> Text,
+        caption -> Text,
+        mime -> Text,
+        extension -> Text,
+        checksum -> Text,
+        size -> BigInt,
+        uploaded_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    published_files (version_id, name) {
+        version_id -> Integer,
+        step_id -> Integer,
+        name -> Text,
+        file_key -> Text,
+        mime -> Text,
+    }
+}
+
+diesel::table! {
+    published_steps (version_id, rank) {
+        version_id -> Integer,
+        step_id -> Integer,
+        rank -> Integer,
+        latitude -> Double,
+        longitude -> Double,
+        location_hint -> Text,
+        question -> Text,
+        shake_message -> Nullable<Text>,
+        answer -> Text,
+        is_end -> Bool,
+        show_bearing -> Bool,
+        validation_mode -> Text,
+        secret_code -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     steps (id) {
         id -> Integer,
@@ -11,6 +123,10 @@ diesel::table! {
         shake_message -> Nullable<Text>,
         answer -> Text,
         is_end -> Bool,
+        show_bearing -> Bool,
+        validation_mode -> Text,
+        secret_code -> Nullable<Text>,
+        revision -> Integer,
     }
 }
 
@@ -20,10 +136,31 @@ diesel::table! {
         name -> Text,
         password -> Text,
         current_step -> Integer,
+        version_id -> Nullable<Integer>,
+        step_id -> Nullable<Integer>,
+        revision -> Integer,
+    }
+}
+
+diesel::table! {
+    versions (id) {
+        id -> Integer,
+        published_at -> BigInt,
+        files_copied -> Bool,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    anticheat_settings,
+    cheat_flags,
+    medias,
+    photo_submissions,
+    positions,
+    published_assets,
+    published_files,
+    published_steps,
+    step_assets,
     steps,
     users,
+    versions,
 );
@@ -0,0 +1,93 @@
+// Number of leading bytes needed to recognize every allowed format
+pub const SNIFF_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MediaType {
+    pub mime: &'static str,
+    pub extension: &'static str,
+}
+
+const fn media_type(mime: &'static str, extension: &'static str) -> Option<MediaType> {
+    Some(MediaType { mime, extension })
+}
+
+// Recognize an allowed audio, video or image format from the first bytes of a file
+pub fn sniff(head: &[u8]) -> Option<MediaType> {
+    match head {
+        [b'I', b'D', b'3', ..] => media_type("audio/mpeg", "mp3"),
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 && b & 0x06 != 0 => media_type("audio/mpeg", "mp3"),
+        [b'O', b'g', b'g', b'S', ..] => media_type("audio/ogg", "ogg"),
+        [b'f', b'L', b'a', b'C', ..] => media_type("audio/flac", "flac"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => {
+            media_type("audio/wav", "wav")
+        }
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            media_type("image/webp", "webp")
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', b'M', b'4', b'A', ..] => {
+            media_type("audio/mp4", "m4a")
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', b'q', b't', ..] => {
+            media_type("video/quicktime", "mov")
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => media_type("video/mp4", "mp4"),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => media_type("video/webm", "webm"),
+        [0xFF, 0xD8, 0xFF, ..] => media_type("image/jpeg", "jpg"),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => media_type("image/png", "png"),
+        [b'G', b'I', b'F', b'8', ..] => media_type("image/gif", "gif"),
+        _ => None,
+    }
+}
+
+// Recognize an allowed document format from the first bytes of a file
+pub fn sniff_document(head: &[u8]) -> Option<MediaType> {
+    match head {
+        [b'%', b'P', b'D', b'F', b'-', ..] => media_type("application/pdf", "pdf"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod sniff_tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_formats() {
+        assert_eq!(sniff(b"ID3\x04\x00\x00").unwrap().extension, "mp3");
+        assert_eq!(sniff(&[0xFF, 0xFB, 0x90, 0x00]).unwrap().extension, "mp3");
+        assert_eq!(sniff(b"OggS\x00\x02").unwrap().mime, "audio/ogg");
+        assert_eq!(
+            sniff(b"RIFF\x24\x08\x00\x00WAVEfmt ").unwrap().mime,
+            "audio/wav"
+        );
+        assert_eq!(
+            sniff(b"\x00\x00\x00\x20ftypM4A ").unwrap().mime,
+            "audio/mp4"
+        );
+        assert_eq!(
+            sniff(b"\x00\x00\x00\x18ftypisom").unwrap().mime,
+            "video/mp4"
+        );
+        assert_eq!(
+            sniff(&[0x1A, 0x45, 0xDF, 0xA3, 0x01]).unwrap().extension,
+            "webm"
+        );
+        assert_eq!(sniff(&[0xFF, 0xD8, 0xFF, 0xE0]).unwrap().mime, "image/jpeg");
+    }
+
+    #[test]
+    fn test_rejected_formats() {
+        assert!(sniff(b"").is_none());
+        assert!(sniff(b"<script>alert(1)</script>").is_none());
+        assert!(sniff(b"MZ\x90\x00\x03\x00").is_none());
+        assert!(sniff(b"%PDF-1.7").is_none());
+        assert!(sniff(b"RIFF\x24\x08\x00\x00AVI LIST").is_none());
+    }
+
+    #[test]
+    fn test_documents() {
+        assert_eq!(sniff_document(b"%PDF-1.7\n").unwrap().extension, "pdf");
+        assert!(sniff_document(b"%!PS-Adobe").is_none());
+        assert!(sniff_document(b"ID3\x04\x00\x00").is_none());
+    }
+}
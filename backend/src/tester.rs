@@ -50,3 +50,11 @@ macro_rules! do_test_extract_id {
 pub fn type_of<T>(_: T) -> &'static str {
     std::any::type_name::<T>()
 }
+
+// The body of an error response without details
+pub fn error_json(code: &str, message: &str) -> String {
+    format!(
+        r#"{{"code":"{code}","message":{}}}"#,
+        serde_json::to_string(message).unwrap()
+    )
+}
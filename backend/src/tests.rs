@@ -4,25 +4,38 @@ mod tests {
 
     use crate::{
         auth::AppConfig,
-        models::{advance_tests::advance_test, step_tests::step_test, user_tests::user_test},
+        models::{
+            advance_tests::advance_test, anticheat_tests::anticheat_test, asset_tests::asset_test,
+            backup_tests::backup_test, errors_tests::errors_test, integrity_tests::integrity_test,
+            list_tests::list_test, load_tests::load_test, maintenance_tests::maintenance_test,
+            openapi_tests::openapi_test, patch_tests::patch_test, photo_tests::photo_test,
+            qrcodes_tests::qrcodes_test, rank_tests::rank_test, step_tests::step_test,
+            storage_tests::storage_test, streaming_tests::streaming_test,
+            traversal_tests::traversal_test, user_tests::user_test, version_tests::version_test,
+        },
     };
     #[actix_rt::test]
     async fn test_models() {
+        use crate::db::{self, DbConnection};
         use diesel::r2d2::{self, ConnectionManager};
-        use diesel::SqliteConnection;
-        use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
         std::env::set_var("RUST_LOG", "debug");
         env_logger::init();
 
-        // set up database connection pool
-        let manager = ConnectionManager::<SqliteConnection>::new("db/test_db.sqlite");
+        // set up database connection pool, TEST_DATABASE_URL runs the tests against another database
+        let manager = ConnectionManager::<DbConnection>::new(
+            std::env::var("TEST_DATABASE_URL").unwrap_or("db/test_db.sqlite".to_string()),
+        );
         let pool = r2d2::Pool::builder()
+            .connection_customizer(Box::new(crate::db_options::ConnectionOptions {
+                enable_foreign_keys: true,
+                busy_timeout: Some(std::time::Duration::from_secs(30)),
+                enable_wal: true,
+                synchronous: Some(crate::db_options::Synchronous::Normal),
+                cache_size: None,
+            }))
             .build(manager)
             .expect("Failed to create pool.");
-        pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations");
-        pool.get()
-            .expect("couldn't get db connection from pool")
-            .run_pending_migrations(MIGRATIONS)
+        db::run_migrations(&mut pool.get().expect("couldn't get db connection from pool"))
             .expect("couldn't run migrations");
 
         // Set up authorization token
@@ -30,7 +43,24 @@ mod tests {
         let app_data = Data::new(app_config);
 
         user_test(&pool, &app_data).await;
+        list_test(&pool, &app_data).await;
+        patch_test(&pool, &app_data).await;
+        errors_test(&pool, &app_data).await;
         step_test(&pool, &app_data).await;
+        rank_test(&pool, &app_data).await;
         advance_test(&pool, &app_data).await;
+        anticheat_test(&pool, &app_data).await;
+        qrcodes_test(&pool, &app_data).await;
+        photo_test(&pool, &app_data).await;
+        traversal_test(&pool, &app_data).await;
+        streaming_test(&pool, &app_data).await;
+        asset_test(&pool, &app_data).await;
+        maintenance_test(&pool, &app_data).await;
+        storage_test(&pool, &app_data).await;
+        version_test(&pool, &app_data).await;
+        integrity_test(&pool, &app_data).await;
+        backup_test(&pool, &app_data).await;
+        openapi_test(&pool, &app_data).await;
+        load_test(&pool, &app_data).await;
     }
 }
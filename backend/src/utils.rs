@@ -7,3 +7,28 @@ pub fn random_string() -> std::string::String {
         .map(char::from)
         .collect()
 }
+
+pub fn get_dist(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let part_one: f64 = (90.0 - lat1).to_radians().cos() * (90.0 - lat2).to_radians().cos();
+    let part_two: f64 = (90.0 - lat1).to_radians().sin()
+        * (90.0 - lat2).to_radians().sin()
+        * (lng1 - lng2).to_radians().cos();
+    (part_one + part_two).acos() * 6371.0 * 1000.0
+}
+
+// Initial bearing from the first point to the second one, in degrees clockwise from the north
+pub fn get_bearing(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let delta_lng = (lng2 - lng1).to_radians();
+    let y = delta_lng.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lng.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+// Milliseconds since the unix epoch
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
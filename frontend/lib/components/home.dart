@@ -48,7 +48,7 @@ class _MyHomePageState extends State<MyHomePage> {
   @override
   void initState() {
     super.initState();
-    if (App().hasUser) {
+    if (App().hasUser && App().prefs.userPassword != "") {
       _getCurrentStep(false);
     } else {
       WidgetsBinding.instance.addPostFrameCallback(openSettings);
@@ -81,7 +81,7 @@ class _MyHomePageState extends State<MyHomePage> {
       });
       try {
         var headResp = await http.head(Uri.parse(
-            '${App().prefs.hostname}/api/steps/medias/${s.id.toString()}'));
+            '${App().prefs.hostname}/api/steps/medias/${s.id.toString()}${s.filesQuery}'));
         if (headResp.statusCode == 200) {
           setState(() {
             _hasMedia = true;
@@ -109,25 +109,29 @@ class _MyHomePageState extends State<MyHomePage> {
 
   Future<void> openSettings(Duration _) async {
     final formKey = GlobalKey<FormState>();
+    // A player joining from their QR code already exists
+    final joining = App().hasUser;
     await showDialog<String>(
       context: context,
       builder: (BuildContext context) => AlertDialog(
         title: Text(tr(context, "settings")),
         content: SizedBox(
           height: 250,
-          child: SettingsField(onboarding: true, formKey: formKey),
+          child: SettingsField(onboarding: !joining, formKey: formKey),
         ),
         actions: <Widget>[
           TextButton(
             onPressed: () async {
               if (formKey.currentState!.validate()) {
-                // Create an user with the given name and password
-                var user = await widget.userCrud.create(User(
-                    name: App().prefs.userName,
-                    password: App().prefs.userPassword,
-                    currentStep: 1,
-                    id: 0));
-                App().prefs.userId = user.id;
+                if (!joining) {
+                  // Create an user with the given name and password
+                  var user = await widget.userCrud.create(User(
+                      name: App().prefs.userName,
+                      password: App().prefs.userPassword,
+                      currentStep: 1,
+                      id: 0));
+                  App().prefs.userId = user.id;
+                }
                 if (!context.mounted) return;
                 Navigator.pop(context, 'OK');
               }
@@ -216,7 +220,7 @@ class _MyHomePageState extends State<MyHomePage> {
                                       minScale: 1,
                                       maxScale: 10,
                                       child: Image.network(
-                                        '${App().prefs.hostname}/api/steps/images/${snapshot.data!.id.toString()}',
+                                        '${App().prefs.hostname}/api/steps/images/${snapshot.data!.id.toString()}${snapshot.data!.filesQuery}',
                                         errorBuilder: (BuildContext context,
                                             Object exception,
                                             StackTrace? stackTrace) {
@@ -234,7 +238,7 @@ class _MyHomePageState extends State<MyHomePage> {
                                   child: MediaPlayer(
                                       key: UniqueKey(),
                                       uri:
-                                          '${App().prefs.hostname}/api/steps/medias/$_mediaFile',
+                                          '${App().prefs.hostname}/api/steps/medias/$_mediaFile${snapshot.data!.filesQuery}',
                                       autoplay: snapshot.data!.id > 1)),
                             ),
                           if (!snapshot.data!.isEnd) ...[
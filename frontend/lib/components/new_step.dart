@@ -348,6 +348,39 @@ class NewEditStepState extends State<NewEditStep>
                       widget.step.answer = value;
                     },
                   ),
+                  DropdownButtonFormField<String>(
+                    initialValue: widget.step.validationMode,
+                    decoration: InputDecoration(
+                      labelText: tr(context, "validation_mode"),
+                    ),
+                    items: validationModes
+                        .map(
+                          (mode) => DropdownMenuItem(
+                            value: mode,
+                            child: Text(tr(context, mode)),
+                          ),
+                        )
+                        .toList(),
+                    onChanged: (value) => setState(() {
+                      widget.step.validationMode = value!;
+                    }),
+                  ),
+                  if (widget.step.validationMode == 'SecretCode')
+                    TextFormField(
+                      initialValue: widget.step.secretCode ?? "",
+                      decoration: InputDecoration(
+                        labelText: tr(context, "secret_code"),
+                      ),
+                      validator: (value) {
+                        if (value == null || value.isEmpty) {
+                          return tr(context, "please_enter_some_text");
+                        }
+                        return null;
+                      },
+                      onChanged: (value) {
+                        widget.step.secretCode = value != "" ? value : null;
+                      },
+                    ),
                   TextFormField(
                     controller: _latitudeController,
                     keyboardType: TextInputType.number,
@@ -465,6 +498,17 @@ class NewEditStepState extends State<NewEditStep>
                       ),
                     ],
                   ),
+                  Row(
+                    children: [
+                      Text(tr(context, "show_bearing")),
+                      Checkbox(
+                        value: widget.step.showBearing,
+                        onChanged: (value) => setState(() {
+                          widget.step.showBearing = value!;
+                        }),
+                      ),
+                    ],
+                  ),
                   const Center(
                     child: Padding(
                       padding: EdgeInsets.all(8.0),
@@ -41,5 +41,14 @@ class App {
     if (kIsWeb || !Platform.environment.containsKey('FLUTTER_TEST')) {
       await prefs.read();
     }
+    // The QR code of a player opens the web app with ?user=<id>, the player is then only asked
+    // for their password
+    if (kIsWeb) {
+      var joined = int.tryParse(Uri.base.queryParameters['user'] ?? '');
+      if (joined != null && joined != prefs.userId) {
+        prefs.userId = joined;
+        prefs.userPassword = "";
+      }
+    }
   }
 }
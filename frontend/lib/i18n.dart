@@ -19,6 +19,7 @@ class MyLocalizations {
   static final Map<String, Map<String, String>> _localizedValues = {
     'en': {
       "answer": "Answer",
+      "AnswerOnly": "Answer only",
       "bad_response_code": "Bad server response code",
       "confirm_your_id": "Please confirm your identity...",
       "current_step": "Current step",
@@ -33,6 +34,8 @@ class MyLocalizations {
       "is_end": "Is the last step (success) ?",
       "latitude": "Latitude",
       "location_hint": "Location hint",
+      "LocationAndAnswer": "Location and answer",
+      "LocationOnly": "Location only",
       "longitude": "Longitude",
       "name": "Name",
       "new_step": "New step",
@@ -40,13 +43,17 @@ class MyLocalizations {
       "no_more_steps": "No more steps !",
       "no_users": "No users",
       "password": "Password",
+      "Photo": "Photo checked by an organizer",
       "playback_speed": "Playback speed",
       "please_enter_some_text": "Please enter some text.",
       "question": "Question",
       "rank": "Rank",
+      "secret_code": "Secret code",
+      "SecretCode": "Secret code",
       "settings": "Settings",
       "media": "Media",
       "shake_message": "Message displayed on shake",
+      "show_bearing": "Show the players the direction of the step?",
       "starting_game": "Starting game!",
       "step_created": "Step created",
       "step_deleted": "Step deleted",
@@ -59,11 +66,13 @@ class MyLocalizations {
       "username": "User name",
       "users_refreshed": "Users refreshed",
       "users": "Users",
+      "validation_mode": "Validation",
       "wrong_answer": "Wrong answer!",
       "wrong_password": "Wrong password!",
     },
     'fr': {
       "answer": "Réponse",
+      "AnswerOnly": "Réponse seule",
       "bad_response_code": "Mauvais code de réponse serveur",
       "confirm_your_id": "Veuillez confirmer votre identité...",
       "current_step": "Étape en cours",
@@ -78,6 +87,8 @@ class MyLocalizations {
       "is_end": "Est la dernière étape (succès) ?",
       "latitude": "Latitude",
       "location_hint": "Indice sur l'endroit",
+      "LocationAndAnswer": "Position et réponse",
+      "LocationOnly": "Position seule",
       "longitude": "Longitude",
       "name": "Nom",
       "new_step": "Nouvelle étape",
@@ -85,13 +96,17 @@ class MyLocalizations {
       "no_more_steps": "Pas d'étape suivante",
       "no_users": "Aucun utilisateur",
       "password": "Mot de passe",
+      "Photo": "Photo vérifiée par un organisateur",
       "playback_speed": "Vitesse de lecture",
       "please_enter_some_text": "Veuillez entrer un texte.",
       "question": "Question",
       "rank": "Rang de l'étape",
+      "secret_code": "Code secret",
+      "SecretCode": "Code secret",
       "settings": "Paramètres",
       "media": "Média",
       "shake_message": "Message affiché lors d'une secousse",
+      "show_bearing": "Montrer aux joueurs la direction de l'étape ?",
       "starting_game": "Démarrage du jeu !",
       "step_created": "Étape créée",
       "step_deleted": "Étape supprimée",
@@ -104,6 +119,7 @@ class MyLocalizations {
       "username": "Nom d'utilisateur",
       "users_refreshed": "Utilisateurs rafraîchis",
       "users": "Utilisateurs",
+      "validation_mode": "Validation",
       "wrong_answer": "Mauvaise réponse !",
       "wrong_password": "Mot de passe incorrect !",
     },
@@ -70,22 +70,24 @@ class AdvanceCrud {
           return AdvanceCrudResponse(
               step: Step.fromJson(json.decode(utf8.decode(response.bodyBytes))),
               outcome: tr(context, "going_next_step"));
-        } else if (response.statusCode == 403) {
-          return AdvanceCrudResponse(
-              step: null, outcome: tr(context, "wrong_password"));
-        } else if (response.statusCode == 406) {
-          Map<String, dynamic> r = jsonDecode(response.body);
-          String outcome = "";
-          if (r["type"] == "WrongPlace") {
-            outcome = MyLocalizations.of(context)!.wrongPlace(r["distance"]);
-          } else if (r["type"] == "WrongAnswer") {
+        }
+        // Errors tell their cause by a stable code, with what is needed in the details
+        Map<String, dynamic> r = jsonDecode(utf8.decode(response.bodyBytes));
+        String outcome;
+        switch (r["code"]) {
+          case "wrong_password":
+            outcome = tr(context, "wrong_password");
+          case "wrong_place":
+            outcome = MyLocalizations.of(context)!
+                .wrongPlace(r["details"]["distance"]);
+          case "wrong_answer":
             outcome = tr(context, "wrong_answer");
-          }
-          return AdvanceCrudResponse(step: null, outcome: outcome);
-        } else if (response.statusCode == 404) {
-          return AdvanceCrudResponse(
-              step: null, outcome: tr(context, "no_more_steps"));
+          case "no_more_steps":
+            outcome = tr(context, "no_more_steps");
+          default:
+            outcome = r["message"] ?? "bad_response_code";
         }
+        return AdvanceCrudResponse(step: null, outcome: outcome);
       }
       return AdvanceCrudResponse(step: null, outcome: "bad_response_code");
     } on GPSException {
@@ -1,5 +1,14 @@
 import 'crud.dart';
 
+// What a player must provide to validate a step, as named by the server
+const validationModes = [
+  'LocationAndAnswer',
+  'LocationOnly',
+  'AnswerOnly',
+  'SecretCode',
+  'Photo',
+];
+
 class Step extends Serialisable {
   int rank;
   double latitude;
@@ -9,6 +18,11 @@ class Step extends Serialisable {
   String? shakeMessage;
   String answer;
   bool isEnd;
+  bool showBearing;
+  String validationMode;
+  String? secretCode;
+  // The version a player's step belongs to, whose files are asked for with it
+  int? versionId;
 
   Step(
       {required super.id,
@@ -19,7 +33,11 @@ class Step extends Serialisable {
       required this.question,
       this.shakeMessage,
       required this.answer,
-      required this.isEnd});
+      required this.isEnd,
+      this.showBearing = false,
+      this.validationMode = 'LocationAndAnswer',
+      this.secretCode,
+      this.versionId});
 
   @override
   Map<String, dynamic> toJson() {
@@ -32,7 +50,10 @@ class Step extends Serialisable {
       'question': question,
       if (shakeMessage != null) 'shake_message': shakeMessage,
       'answer': answer,
-      'is_end': isEnd
+      'is_end': isEnd,
+      'show_bearing': showBearing,
+      'validation_mode': validationMode,
+      if (secretCode != null) 'secret_code': secretCode
     };
   }
 
@@ -45,10 +66,17 @@ class Step extends Serialisable {
         locationHint: json['location_hint'],
         question: json['question'],
         shakeMessage: json['shake_message'],
-        answer: json['answer'],
-        isEnd: json['is_end']);
+        answer: json['answer'] ?? '',
+        isEnd: json['is_end'],
+        showBearing: json['show_bearing'] ?? false,
+        validationMode: json['validation_mode'] ?? 'LocationAndAnswer',
+        secretCode: json['secret_code'],
+        versionId: json['version_id']);
   }
 
+  // The query asking for the files of the step as they are in its version
+  String get filesQuery => versionId != null ? '?version=$versionId' : '';
+
   @override
   bool operator ==(Object other) {
     if (identical(this, other)) return true;
@@ -64,6 +92,9 @@ class Step extends Serialisable {
         () => shakeMessage == other.shakeMessage,
         () => answer == other.answer,
         () => isEnd == other.isEnd,
+        () => showBearing == other.showBearing,
+        () => validationMode == other.validationMode,
+        () => secretCode == other.secretCode,
       ];
       // Compare each property in the subset
       return subset.every((comparison) => comparison());
@@ -75,6 +106,6 @@ class Step extends Serialisable {
   int get hashCode {
     // Use only the subset of properties for the hash code
     return Object.hash(id, rank, latitude, longitude, locationHint, question,
-        shakeMessage, answer, isEnd);
+        shakeMessage, answer, isEnd, showBearing, validationMode, secretCode);
   }
 }